@@ -0,0 +1,97 @@
+use std::f64::consts::PI;
+
+// Band-limited synthesis, along the lines of blip_buf
+// http://www.slack.net/~ant/bl-synth/
+//
+// The channels only ever change their output in steps, so instead of
+// sampling the output at the host rate (which aliases badly), every change
+// in amplitude is recorded as a delta at the emulated clock it happened on.
+// Each delta is spread over a few output samples using a windowed sinc
+// kernel, and the output is the running sum of all the deltas.
+
+const PHASE_BITS: u32 = 5;
+const PHASES: usize = 1 << PHASE_BITS;
+const KERNEL_WIDTH: usize = 16;
+
+// fraction of the nyquist frequency that is let through
+const CUTOFF: f64 = 0.9;
+
+const FRAC_BITS: u32 = 32;
+
+#[derive(Debug)]
+pub(super) struct BlipBuffer {
+    // output samples per clock, as a fixed point number
+    factor: u64,
+    // position of the start of the current frame in the output, as a fixed point number
+    offset: u64,
+    buffer: Vec<f32>,
+    integrator: f32,
+    kernel: Box<[[f32; KERNEL_WIDTH]; PHASES]>,
+}
+
+impl BlipBuffer {
+    // max_frame_clocks: the longest frame that will be passed to end_frame
+    pub(super) fn new(clock_rate: u64, sample_rate: u32, max_frame_clocks: u64) -> Self {
+        let factor = ((sample_rate as u64) << FRAC_BITS) / clock_rate;
+        let max_samples = ((max_frame_clocks * factor) >> FRAC_BITS) as usize + 1;
+        Self {
+            factor,
+            offset: 0,
+            buffer: vec![0.0; max_samples + KERNEL_WIDTH],
+            integrator: 0.0,
+            kernel: Box::new(Self::make_kernel()),
+        }
+    }
+
+    fn make_kernel() -> [[f32; KERNEL_WIDTH]; PHASES] {
+        let mut kernel = [[0.0; KERNEL_WIDTH]; PHASES];
+        let center = (KERNEL_WIDTH / 2) as f64 - 1.0;
+        for (phase, taps) in kernel.iter_mut().enumerate() {
+            let frac = phase as f64 / PHASES as f64;
+            let mut sum = 0.0;
+            let mut values = [0.0f64; KERNEL_WIDTH];
+            for (i, v) in values.iter_mut().enumerate() {
+                let x = i as f64 - center - frac;
+                let sinc = if x == 0.0 { 1.0 } else { (PI * CUTOFF * x).sin() / (PI * CUTOFF * x) };
+                // blackman window over the width of the kernel
+                let w = (x + center + 1.0) / KERNEL_WIDTH as f64;
+                let window = 0.42 - 0.5 * (2.0 * PI * w).cos() + 0.08 * (4.0 * PI * w).cos();
+                *v = sinc * window;
+                sum += *v;
+            }
+            // every phase has to add up to exactly the delta, or the output will drift
+            for (t, v) in taps.iter_mut().zip(values) {
+                *t = (v / sum) as f32;
+            }
+        }
+        kernel
+    }
+
+    // clock: time of the change, relative to the start of the current frame
+    pub(super) fn add_delta(&mut self, clock: u64, delta: f32) {
+        let time = self.offset + clock * self.factor;
+        let pos = (time >> FRAC_BITS) as usize;
+        let phase = ((time >> (FRAC_BITS - PHASE_BITS)) as usize) & (PHASES - 1);
+        let out = &mut self.buffer[pos..][..KERNEL_WIDTH];
+        for (o, k) in out.iter_mut().zip(self.kernel[phase].iter()) {
+            *o += delta * k;
+        }
+    }
+
+    pub(super) fn end_frame(&mut self, clocks: u64) {
+        self.offset += clocks * self.factor;
+    }
+
+    // moves all the samples that are complete to `out`
+    pub(super) fn read_samples(&mut self, out: &mut Vec<f32>) {
+        let available = (self.offset >> FRAC_BITS) as usize;
+        for s in &self.buffer[..available] {
+            self.integrator += s;
+            out.push(self.integrator);
+        }
+        self.buffer.copy_within(available.., 0);
+        let len = self.buffer.len();
+        self.buffer[len - available..].fill(0.0);
+        self.offset -= (available as u64) << FRAC_BITS;
+    }
+}
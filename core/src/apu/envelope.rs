@@ -0,0 +1,61 @@
+// NRx2
+// ============================================================================
+// Bit	Name	                        Usage notes
+// ============================================================================
+// 7-4	Initial volume	                0 = silent, 15 = loudest
+//  3	Envelope direction	            0=Decrease, 1=Increase
+// 2-0	Sweep pace	                    0 = envelope disabled
+
+#[derive(Debug, Clone, Copy, Default)]
+pub(super) struct Envelope {
+    initial_volume: u8,
+    increase: bool,
+    pace: u8,
+
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    pub(super) fn read(&self) -> u8 {
+        self.initial_volume << 4 | (self.increase as u8) << 3 | self.pace
+    }
+
+    pub(super) fn write(&mut self, value: u8) {
+        self.initial_volume = value >> 4;
+        self.increase = value & 0b0000_1000 != 0;
+        self.pace = value & 0b0000_0111;
+    }
+
+    // the DAC of a channel is powered as long as any of the upper 5 bits of NRx2 are set
+    pub(super) fn dac_enabled(&self) -> bool {
+        self.read() & 0xf8 != 0
+    }
+
+    pub(super) fn trigger(&mut self) {
+        self.volume = self.initial_volume;
+        self.timer = self.pace;
+    }
+
+    // clocked at 64 Hz by the frame sequencer
+    pub(super) fn clock(&mut self) {
+        if self.pace == 0 {
+            return;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer == 0 {
+            self.timer = self.pace;
+            if self.increase && self.volume < 15 {
+                self.volume += 1;
+            } else if !self.increase && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+
+    pub(super) fn volume(&self) -> u8 {
+        self.volume
+    }
+}
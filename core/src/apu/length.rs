@@ -0,0 +1,57 @@
+// https://gbdev.io/pandocs/Audio_details.html#length-timer
+
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Length {
+    max: u16,
+    counter: u16,
+    pub(super) enabled: bool,
+}
+
+impl Length {
+    pub(super) fn new(max: u16) -> Self {
+        Self {
+            max,
+            counter: 0,
+            enabled: false,
+        }
+    }
+
+    pub(super) fn load(&mut self, value: u8) {
+        self.counter = self.max - value as u16;
+    }
+
+    // clocked at 256 Hz by the frame sequencer
+    // returns true when the counter runs out and the channel has to be turned off
+    pub(super) fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter == 0;
+        }
+        false
+    }
+
+    // Writing to NRx4 has a few quirks, depending on whether the next step of
+    // the frame sequencer clocks the length counters or not.
+    // https://gbdev.gg8.se/wiki/articles/Gameboy_sound_hardware#Obscure_Behavior
+    // returns true if the channel has to be turned off
+    pub(super) fn write_control(&mut self, enable: bool, trigger: bool, next_step_clocks_length: bool) -> bool {
+        let was_enabled = self.enabled;
+        self.enabled = enable;
+
+        let mut expired = false;
+        if !next_step_clocks_length && !was_enabled && enable && self.counter > 0 {
+            self.counter -= 1;
+            expired = self.counter == 0;
+        }
+
+        if trigger && self.counter == 0 {
+            self.counter = self.max;
+            if enable && !next_step_clocks_length {
+                self.counter -= 1;
+            }
+            expired = false;
+        }
+
+        expired && !trigger
+    }
+}
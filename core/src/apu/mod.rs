@@ -0,0 +1,597 @@
+mod blip;
+mod envelope;
+mod length;
+mod noise;
+mod sink;
+mod square;
+mod wave;
+
+use crate::{mmu::busio::{BusIO, SResult}, util::{Addr, get_nth_bit}};
+use blip::BlipBuffer;
+use noise::Noise;
+pub use sink::{AudioSink, NullSink, RingBufferSink, WavSink};
+use square::Square;
+use wave::{Wave, WAVE_RAM_SIZE};
+
+// https://gbdev.io/pandocs/Audio.html
+// https://gbdev.io/pandocs/Audio_Registers.html
+// https://gbdev.gg8.se/wiki/articles/Gameboy_sound_hardware
+
+// Frame Sequencer (clocked at 512 Hz by DIV)
+// ==============================================
+// Step	    Length Ctr	    Vol Env	    Sweep
+// ==============================================
+//  0	    Clock	        -	        -
+//  1	    -	            -	        -
+//  2	    Clock	        -	        Clock
+//  3	    -	            -	        -
+//  4	    Clock	        -	        -
+//  5	    -	            -	        -
+//  6	    Clock	        -	        Clock
+//  7	    -	            Clock	    -
+
+pub(crate) const CPU_CLOCK_HZ: u64 = 4_194_304;
+pub const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+
+// the resampled output is handed to the sink once every video frame
+const FRAME_CLOCKS: u64 = 70224;
+// the last instruction of a frame can run past the end of it
+const MAX_FRAME_CLOCKS: u64 = FRAME_CLOCKS + 64;
+
+// bit 4 of DIV (bit 12 of the internal divider) drives the frame sequencer
+pub(crate) const FRAME_SEQUENCER_DIV_BIT: u16 = 12;
+
+// the output stage of the GB has a capacitor that removes the DC offset of the DACs
+const HIGH_PASS_CHARGE_FACTOR: f32 = 0.999958;
+
+pub(crate) const AUDIO_START: u16 = 0xff10;
+pub(crate) const AUDIO_END: u16 = 0xff3f;
+
+const REG_NR10: u16 = 0xff10;
+const REG_NR11: u16 = 0xff11;
+const REG_NR12: u16 = 0xff12;
+const REG_NR13: u16 = 0xff13;
+const REG_NR14: u16 = 0xff14;
+const REG_NR21: u16 = 0xff16;
+const REG_NR22: u16 = 0xff17;
+const REG_NR23: u16 = 0xff18;
+const REG_NR24: u16 = 0xff19;
+const REG_NR30: u16 = 0xff1a;
+const REG_NR31: u16 = 0xff1b;
+const REG_NR32: u16 = 0xff1c;
+const REG_NR33: u16 = 0xff1d;
+const REG_NR34: u16 = 0xff1e;
+const REG_NR41: u16 = 0xff20;
+const REG_NR42: u16 = 0xff21;
+const REG_NR43: u16 = 0xff22;
+const REG_NR44: u16 = 0xff23;
+const REG_NR50: u16 = 0xff24;
+const REG_NR51: u16 = 0xff25;
+const REG_NR52: u16 = 0xff26;
+const WAVE_RAM_START: u16 = 0xff30;
+const WAVE_RAM_END: u16 = WAVE_RAM_START + WAVE_RAM_SIZE as u16;
+
+pub type StereoSample = (f32, f32);
+
+pub struct APU {
+    enabled: bool,
+
+    ch1: Square,
+    ch2: Square,
+    ch3: Wave,
+    ch4: Noise,
+
+    // NR50
+    vin_left: bool,
+    volume_left: u8,
+    vin_right: bool,
+    volume_right: u8,
+
+    // NR51
+    panning: u8,
+
+    frame_sequencer_step: u8,
+    prev_div_bit: bool,
+
+    // outputs of the channels the last time the mix was computed
+    last_outputs: [u8; 4],
+    last_mix: StereoSample,
+    mix_dirty: bool,
+
+    frame_clock: u64,
+    blip_left: BlipBuffer,
+    blip_right: BlipBuffer,
+    capacitor_left: f32,
+    capacitor_right: f32,
+    sink: Box<dyn AudioSink>,
+}
+
+impl std::fmt::Debug for APU {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("APU")
+            .field("enabled", &self.enabled)
+            .field("ch1", &self.ch1)
+            .field("ch2", &self.ch2)
+            .field("ch3", &self.ch3)
+            .field("ch4", &self.ch4)
+            .field("panning", &self.panning)
+            .field("frame_sequencer_step", &self.frame_sequencer_step)
+            .field("sample_rate", &self.sink.sample_rate())
+            .finish_non_exhaustive()
+    }
+}
+
+impl APU {
+    pub(crate) fn new() -> Self {
+        Self {
+            enabled: false,
+
+            ch1: Square::new(true),
+            ch2: Square::new(false),
+            ch3: Wave::new(),
+            ch4: Noise::new(),
+
+            vin_left: false,
+            volume_left: 0,
+            vin_right: false,
+            volume_right: 0,
+
+            panning: 0,
+
+            frame_sequencer_step: 0,
+            prev_div_bit: false,
+
+            last_outputs: [0; 4],
+            last_mix: (0.0, 0.0),
+            mix_dirty: false,
+
+            frame_clock: 0,
+            blip_left: BlipBuffer::new(CPU_CLOCK_HZ, DEFAULT_SAMPLE_RATE, MAX_FRAME_CLOCKS),
+            blip_right: BlipBuffer::new(CPU_CLOCK_HZ, DEFAULT_SAMPLE_RATE, MAX_FRAME_CLOCKS),
+            capacitor_left: 0.0,
+            capacitor_right: 0.0,
+            sink: Box::new(NullSink),
+        }
+    }
+
+    // replaces the current sink, and returns the old one
+    pub fn set_sink(&mut self, sink: Box<dyn AudioSink>) -> Box<dyn AudioSink> {
+        let sample_rate = sink.sample_rate();
+        assert!(sample_rate > 0 && sample_rate as u64 <= CPU_CLOCK_HZ);
+
+        // whatever is left of the current frame belongs to the old sink
+        self.flush_frame();
+        self.blip_left = BlipBuffer::new(CPU_CLOCK_HZ, sample_rate, MAX_FRAME_CLOCKS);
+        self.blip_right = BlipBuffer::new(CPU_CLOCK_HZ, sample_rate, MAX_FRAME_CLOCKS);
+        // the new buffers start from silence
+        self.last_mix = (0.0, 0.0);
+        self.mix_dirty = true;
+        std::mem::replace(&mut self.sink, sink)
+    }
+
+    pub(crate) fn tick(&mut self, cpu_ticks: u64, div_bit: bool) {
+        // the frame sequencer is clocked on the falling edge of the DIV bit
+        if self.enabled && self.prev_div_bit && !div_bit {
+            self.step_frame_sequencer();
+        }
+        self.prev_div_bit = div_bit;
+
+        for _ in 0..cpu_ticks {
+            if self.enabled {
+                self.ch1.tick();
+                self.ch2.tick();
+                self.ch3.tick();
+                self.ch4.tick();
+            }
+
+            self.update_mix();
+            self.frame_clock += 1;
+        }
+
+        if self.frame_clock >= FRAME_CLOCKS {
+            self.flush_frame();
+        }
+    }
+
+    // records a delta in the band-limited buffers whenever the mixed output changes
+    fn update_mix(&mut self) {
+        let outputs = [self.ch1.output(), self.ch2.output(), self.ch3.output(), self.ch4.output()];
+        if outputs == self.last_outputs && !self.mix_dirty {
+            return;
+        }
+        self.last_outputs = outputs;
+        self.mix_dirty = false;
+
+        let (left, right) = if self.enabled { self.mix() } else { (0.0, 0.0) };
+        let (last_left, last_right) = self.last_mix;
+        if left != last_left {
+            self.blip_left.add_delta(self.frame_clock, left - last_left);
+        }
+        if right != last_right {
+            self.blip_right.add_delta(self.frame_clock, right - last_right);
+        }
+        self.last_mix = (left, right);
+    }
+
+    fn flush_frame(&mut self) {
+        self.blip_left.end_frame(self.frame_clock);
+        self.blip_right.end_frame(self.frame_clock);
+        self.frame_clock = 0;
+
+        let mut left = vec![];
+        let mut right = vec![];
+        self.blip_left.read_samples(&mut left);
+        self.blip_right.read_samples(&mut right);
+
+        let charge = HIGH_PASS_CHARGE_FACTOR.powf(CPU_CLOCK_HZ as f32 / self.sink.sample_rate() as f32);
+        let samples = left
+            .into_iter()
+            .zip(right)
+            .map(|(l, r)| {
+                let out_left = l - self.capacitor_left;
+                self.capacitor_left = l - out_left * charge;
+                let out_right = r - self.capacitor_right;
+                self.capacitor_right = r - out_right * charge;
+                (out_left, out_right)
+            })
+            .collect::<Vec<_>>();
+        if !samples.is_empty() {
+            self.sink.push_samples(&samples);
+        }
+    }
+
+    fn step_frame_sequencer(&mut self) {
+        let step = self.frame_sequencer_step;
+        if step % 2 == 0 {
+            self.ch1.clock_length();
+            self.ch2.clock_length();
+            self.ch3.clock_length();
+            self.ch4.clock_length();
+        }
+        if step == 2 || step == 6 {
+            self.ch1.clock_sweep();
+        }
+        if step == 7 {
+            self.ch1.clock_envelope();
+            self.ch2.clock_envelope();
+            self.ch4.clock_envelope();
+        }
+        self.frame_sequencer_step = (step + 1) % 8;
+    }
+
+    fn next_step_clocks_length(&self) -> bool {
+        self.frame_sequencer_step % 2 == 0
+    }
+
+    // converts the digital output of every channel to analog, and mixes them as per NR50/NR51
+    fn mix(&self) -> StereoSample {
+        let dac = |enabled: bool, digital: u8| if enabled { digital as f32 / 7.5 - 1.0 } else { 0.0 };
+        let channels = [
+            dac(self.ch1.dac_enabled(), self.ch1.output()),
+            dac(self.ch2.dac_enabled(), self.ch2.output()),
+            dac(self.ch3.dac_enabled(), self.ch3.output()),
+            dac(self.ch4.dac_enabled(), self.ch4.output()),
+        ];
+
+        let (mut left, mut right) = (0.0, 0.0);
+        for (i, c) in channels.into_iter().enumerate() {
+            if get_nth_bit(self.panning, i as u8 + 4) {
+                left += c;
+            }
+            if get_nth_bit(self.panning, i as u8) {
+                right += c;
+            }
+        }
+
+        // each side gets a volume between 1/8 and 8/8, and 4 channels are averaged
+        let left = left / 4.0 * (self.volume_left + 1) as f32 / 8.0;
+        let right = right / 4.0 * (self.volume_right + 1) as f32 / 8.0;
+        (left, right)
+    }
+
+    fn read_reg(&self, addr: u16) -> u8 {
+        match addr {
+            REG_NR10 => self.ch1.read_sweep(),
+            REG_NR11 => self.ch1.read_length_duty(),
+            REG_NR12 => self.ch1.read_envelope(),
+            REG_NR14 => self.ch1.read_control(),
+
+            REG_NR21 => self.ch2.read_length_duty(),
+            REG_NR22 => self.ch2.read_envelope(),
+            REG_NR24 => self.ch2.read_control(),
+
+            REG_NR30 => self.ch3.read_dac(),
+            REG_NR32 => self.ch3.read_output_level(),
+            REG_NR34 => self.ch3.read_control(),
+
+            REG_NR42 => self.ch4.read_envelope(),
+            REG_NR43 => self.ch4.read_polynomial(),
+            REG_NR44 => self.ch4.read_control(),
+
+            REG_NR50 => {
+                (self.vin_left as u8) << 7
+                | self.volume_left << 4
+                | (self.vin_right as u8) << 3
+                | self.volume_right
+            }
+            REG_NR51 => self.panning,
+            REG_NR52 => {
+                (self.enabled as u8) << 7
+                | 0b0111_0000
+                | (self.ch4.enabled as u8) << 3
+                | (self.ch3.enabled as u8) << 2
+                | (self.ch2.enabled as u8) << 1
+                | (self.ch1.enabled as u8)
+            }
+
+            WAVE_RAM_START..WAVE_RAM_END => self.ch3.read_ram((addr - WAVE_RAM_START) as usize),
+
+            // period registers, NR31, NR41 and the unused addresses are write only
+            _ => 0xff,
+        }
+    }
+
+    fn write_reg(&mut self, addr: u16, value: u8) {
+        // while powered off, only NR52 and the wave RAM can be written to
+        if !self.enabled && addr != REG_NR52 && !(WAVE_RAM_START..WAVE_RAM_END).contains(&addr) {
+            return;
+        }
+
+        // NR50, NR51 and the DACs affect the mix without changing the output of any channel
+        self.mix_dirty = true;
+
+        let next_step_clocks_length = self.next_step_clocks_length();
+        match addr {
+            REG_NR10 => self.ch1.write_sweep(value),
+            REG_NR11 => self.ch1.write_length_duty(value),
+            REG_NR12 => self.ch1.write_envelope(value),
+            REG_NR13 => self.ch1.write_period_low(value),
+            REG_NR14 => self.ch1.write_control(value, next_step_clocks_length),
+
+            REG_NR21 => self.ch2.write_length_duty(value),
+            REG_NR22 => self.ch2.write_envelope(value),
+            REG_NR23 => self.ch2.write_period_low(value),
+            REG_NR24 => self.ch2.write_control(value, next_step_clocks_length),
+
+            REG_NR30 => self.ch3.write_dac(value),
+            REG_NR31 => self.ch3.write_length(value),
+            REG_NR32 => self.ch3.write_output_level(value),
+            REG_NR33 => self.ch3.write_period_low(value),
+            REG_NR34 => self.ch3.write_control(value, next_step_clocks_length),
+
+            REG_NR41 => self.ch4.write_length(value),
+            REG_NR42 => self.ch4.write_envelope(value),
+            REG_NR43 => self.ch4.write_polynomial(value),
+            REG_NR44 => self.ch4.write_control(value, next_step_clocks_length),
+
+            REG_NR50 => {
+                self.vin_left = get_nth_bit(value, 7);
+                self.volume_left = (value >> 4) & 0b111;
+                self.vin_right = get_nth_bit(value, 3);
+                self.volume_right = value & 0b111;
+            }
+            REG_NR51 => self.panning = value,
+            REG_NR52 => self.write_power(get_nth_bit(value, 7)),
+
+            WAVE_RAM_START..WAVE_RAM_END => self.ch3.write_ram((addr - WAVE_RAM_START) as usize, value),
+
+            _ => {}
+        }
+    }
+
+    fn write_power(&mut self, enable: bool) {
+        if self.enabled && !enable {
+            self.ch1.reset();
+            self.ch2.reset();
+            self.ch3.reset();
+            self.ch4.reset();
+            self.vin_left = false;
+            self.volume_left = 0;
+            self.vin_right = false;
+            self.volume_right = 0;
+            self.panning = 0;
+        } else if !self.enabled && enable {
+            self.frame_sequencer_step = 0;
+        }
+        self.enabled = enable;
+    }
+}
+
+impl BusIO for APU {
+    fn readu8(&self, addr: Addr) -> SResult<u8> {
+        Ok(self.read_reg(addr.into()))
+    }
+
+    fn writeu8(&mut self, addr: Addr, value: u8) -> SResult<()> {
+        self.write_reg(addr.into(), value);
+        Ok(())
+    }
+
+    fn readu16(&self, addr: Addr) -> SResult<u16> {
+        Ok(u16::from_le_bytes([
+            self.readu8(addr)?,
+            self.readu8(addr + 1.into())?,
+        ]))
+    }
+
+    fn writeu16(&mut self, addr: Addr, value: u16) -> SResult<()> {
+        let value = value.to_le_bytes();
+        self.writeu8(addr, value[0])?;
+        self.writeu8(addr + 1.into(), value[1])?;
+        Ok(())
+    }
+
+    fn as_slice(&self, _addr: Addr, _len: usize) -> SResult<&[u8]> {
+        Err(Box::from("APU doesn't support as_slice"))
+    }
+
+    fn print_dbg(&self, _start: Addr, _len: u16) -> String {
+        unimplemented!()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apu() -> APU {
+        let mut apu = APU::new();
+        apu.write_reg(REG_NR52, 0x80);
+        apu
+    }
+
+    // one step of the frame sequencer, on the falling edge of the DIV bit
+    fn step(apu: &mut APU) {
+        apu.tick(0, true);
+        apu.tick(0, false);
+    }
+
+    // NR52's channel bits
+    fn channels(apu: &APU) -> u8 {
+        apu.read_reg(REG_NR52) & 0x0f
+    }
+
+    #[test]
+    fn read_back_masks() {
+        // the bits that always read 1, from NR10 to NR51
+        const MASKS: [u8; 0x16] = [
+            0x80, 0x3f, 0x00, 0xff, 0xbf, // NR10-NR14
+            0xff, 0x3f, 0x00, 0xff, 0xbf, // NR20-NR24
+            0x7f, 0xff, 0x9f, 0xff, 0xbf, // NR30-NR34
+            0xff, 0xff, 0x00, 0x00, 0xbf, // NR40-NR44
+            0x00, 0x00, // NR50, NR51
+        ];
+        let mut apu = apu();
+        for (addr, mask) in (REG_NR10..).zip(MASKS) {
+            apu.write_reg(addr, 0x00);
+            assert_eq!(apu.read_reg(addr), mask, "{:#06x}", addr);
+            apu.write_reg(addr, 0xff);
+            assert_eq!(apu.read_reg(addr), 0xff, "{:#06x}", addr);
+        }
+        // unused, between NR52 and the wave RAM
+        for addr in REG_NR52 + 1..WAVE_RAM_START {
+            assert_eq!(apu.read_reg(addr), 0xff, "{:#06x}", addr);
+        }
+
+        // power off clears them all, and only NR52 can be written until it's back on
+        apu.write_reg(REG_NR52, 0x00);
+        assert_eq!(apu.read_reg(REG_NR52), 0x70);
+        for (addr, mask) in (REG_NR10..).zip(MASKS) {
+            apu.write_reg(addr, 0xff);
+            assert_eq!(apu.read_reg(addr), mask, "{:#06x}", addr);
+        }
+    }
+
+    #[test]
+    fn length_counter() {
+        let mut apu = apu();
+        apu.write_reg(REG_NR12, 0xf0);
+        // 2 steps left, enabled on trigger
+        apu.write_reg(REG_NR11, 62);
+        apu.write_reg(REG_NR14, 0xc0);
+        assert_eq!(channels(&apu), 0b0001);
+
+        // clocked on every other step, as the DIV bit goes low
+        apu.tick(0, true);
+        assert_eq!(apu.frame_sequencer_step, 0);
+        apu.tick(0, false);
+        assert_eq!(apu.frame_sequencer_step, 1);
+        assert_eq!(channels(&apu), 0b0001);
+        step(&mut apu);
+        assert_eq!(channels(&apu), 0b0001);
+        step(&mut apu);
+        assert_eq!(channels(&apu), 0b0000);
+    }
+
+    #[test]
+    fn length_disabled() {
+        let mut apu = apu();
+        apu.write_reg(REG_NR22, 0xf0);
+        apu.write_reg(REG_NR21, 63);
+        apu.write_reg(REG_NR24, 0x80);
+        for _ in 0..16 {
+            step(&mut apu);
+        }
+        assert_eq!(channels(&apu), 0b0010);
+    }
+
+    #[test]
+    fn frame_sequencer_wraps() {
+        let mut apu = apu();
+        for i in 1..=16 {
+            step(&mut apu);
+            assert_eq!(apu.frame_sequencer_step, i % 8);
+        }
+        // and starts over at power on
+        apu.write_reg(REG_NR52, 0x00);
+        step(&mut apu);
+        assert_eq!(apu.frame_sequencer_step, 0);
+        apu.write_reg(REG_NR52, 0x80);
+        assert_eq!(apu.frame_sequencer_step, 0);
+    }
+
+    fn trigger_ch1(apu: &mut APU, sweep: u8, period: u16) {
+        apu.write_reg(REG_NR10, sweep);
+        apu.write_reg(REG_NR12, 0xf0);
+        apu.write_reg(REG_NR13, period as u8);
+        apu.write_reg(REG_NR14, 0x80 | (period >> 8) as u8);
+    }
+
+    #[test]
+    fn sweep_overflow_on_trigger() {
+        // 0x700 + 0x700 / 2 is past 0x7ff
+        let mut apu = apu();
+        trigger_ch1(&mut apu, 0x11, 0x700);
+        assert_eq!(channels(&apu), 0b0000);
+    }
+
+    #[test]
+    fn sweep_overflow() {
+        // 0x500 goes to 0x780 on the first sweep step, and the check that follows overflows
+        let mut apu = apu();
+        trigger_ch1(&mut apu, 0x11, 0x500);
+        assert_eq!(channels(&apu), 0b0001);
+        step(&mut apu);
+        step(&mut apu);
+        assert_eq!(channels(&apu), 0b0001);
+        step(&mut apu);
+        assert_eq!(channels(&apu), 0b0000);
+    }
+
+    #[test]
+    fn sweep_down() {
+        // subtracting never overflows, and turning negate off afterwards disables the channel
+        let mut apu = apu();
+        trigger_ch1(&mut apu, 0x19, 0x700);
+        for _ in 0..8 {
+            step(&mut apu);
+        }
+        assert_eq!(channels(&apu), 0b0001);
+        apu.write_reg(REG_NR10, 0x11);
+        assert_eq!(channels(&apu), 0b0000);
+    }
+
+    #[test]
+    fn dac_off() {
+        let mut apu = apu();
+        apu.write_reg(REG_NR22, 0x08);
+        apu.write_reg(REG_NR24, 0x80);
+        apu.write_reg(REG_NR30, 0x80);
+        apu.write_reg(REG_NR34, 0x80);
+        apu.write_reg(REG_NR42, 0xf0);
+        apu.write_reg(REG_NR44, 0x80);
+        assert_eq!(channels(&apu), 0b1110);
+
+        // volume 0 and decreasing is off
+        apu.write_reg(REG_NR22, 0x00);
+        apu.write_reg(REG_NR30, 0x00);
+        apu.write_reg(REG_NR42, 0x07);
+        assert_eq!(channels(&apu), 0b0000);
+
+        // and a trigger can't start the channel while it's off
+        apu.write_reg(REG_NR24, 0x80);
+        apu.write_reg(REG_NR34, 0x80);
+        apu.write_reg(REG_NR44, 0x80);
+        assert_eq!(channels(&apu), 0b0000);
+    }
+}
@@ -0,0 +1,137 @@
+use super::envelope::Envelope;
+use super::length::Length;
+
+// Channel 4
+// ============================================================================
+// Register	    Bits
+// ============================================================================
+// NR41	        --LL LLLL   Length load (64-L)
+// NR42	        VVVV APPP   Envelope
+// NR43	        SSSS WDDD   Clock shift, LFSR width (1 = 7 bits), Clock divider
+// NR44	        TL-- ----   Trigger, Length enable
+
+const MAX_LENGTH: u16 = 64;
+const DIVISORS: [u16; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Noise {
+    length: Length,
+    envelope: Envelope,
+
+    clock_shift: u8,
+    short_mode: bool,
+    divisor_code: u8,
+
+    timer: u32,
+    lfsr: u16,
+
+    pub(super) enabled: bool,
+}
+
+impl Noise {
+    pub(super) fn new() -> Self {
+        Self {
+            length: Length::new(MAX_LENGTH),
+            envelope: Default::default(),
+
+            clock_shift: 0,
+            short_mode: false,
+            divisor_code: 0,
+
+            timer: 0,
+            lfsr: 0x7fff,
+
+            enabled: false,
+        }
+    }
+
+    pub(super) fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    pub(super) fn write_length(&mut self, value: u8) {
+        self.length.load(value & 0b0011_1111);
+    }
+
+    pub(super) fn read_envelope(&self) -> u8 {
+        self.envelope.read()
+    }
+
+    pub(super) fn write_envelope(&mut self, value: u8) {
+        self.envelope.write(value);
+        if !self.envelope.dac_enabled() {
+            self.enabled = false;
+        }
+    }
+
+    pub(super) fn read_polynomial(&self) -> u8 {
+        self.clock_shift << 4 | (self.short_mode as u8) << 3 | self.divisor_code
+    }
+
+    pub(super) fn write_polynomial(&mut self, value: u8) {
+        self.clock_shift = value >> 4;
+        self.short_mode = value & 0b0000_1000 != 0;
+        self.divisor_code = value & 0b111;
+    }
+
+    pub(super) fn read_control(&self) -> u8 {
+        0b1011_1111 | (self.length.enabled as u8) << 6
+    }
+
+    pub(super) fn write_control(&mut self, value: u8, next_step_clocks_length: bool) {
+        let trigger = value & 0x80 != 0;
+        if self.length.write_control(value & 0x40 != 0, trigger, next_step_clocks_length) {
+            self.enabled = false;
+        }
+        if trigger {
+            self.enabled = self.envelope.dac_enabled();
+            self.timer = self.period();
+            self.lfsr = 0x7fff;
+            self.envelope.trigger();
+        }
+    }
+
+    fn period(&self) -> u32 {
+        (DIVISORS[self.divisor_code as usize] as u32) << self.clock_shift
+    }
+
+    pub(super) fn tick(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer == 0 {
+            self.timer = self.period();
+            // clock shifts of 14 and 15 stop the LFSR
+            if self.clock_shift >= 14 {
+                return;
+            }
+            let xor = (self.lfsr & 1) ^ ((self.lfsr >> 1) & 1);
+            self.lfsr = (self.lfsr >> 1) | (xor << 14);
+            if self.short_mode {
+                self.lfsr = (self.lfsr & !(1 << 6)) | (xor << 6);
+            }
+        }
+    }
+
+    pub(super) fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub(super) fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub(super) fn dac_enabled(&self) -> bool {
+        self.envelope.dac_enabled()
+    }
+
+    // digital output in the range 0..=15
+    pub(super) fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        (!self.lfsr & 1) as u8 * self.envelope.volume()
+    }
+}
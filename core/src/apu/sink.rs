@@ -0,0 +1,233 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use super::{StereoSample, DEFAULT_SAMPLE_RATE};
+
+// Receives the output of the APU, resampled to the rate the sink asks for.
+// Samples are in the range -1.0..=1.0, and arrive in batches of roughly one video frame.
+pub trait AudioSink {
+    fn sample_rate(&self) -> u32;
+    fn push_samples(&mut self, samples: &[StereoSample]);
+}
+
+// Throws away everything, for when there's no one listening.
+#[derive(Debug, Clone, Copy)]
+pub struct NullSink;
+
+impl AudioSink for NullSink {
+    fn sample_rate(&self) -> u32 {
+        DEFAULT_SAMPLE_RATE
+    }
+
+    fn push_samples(&mut self, _samples: &[StereoSample]) {}
+}
+
+// Keeps the most recent `capacity` samples in memory.
+// Clones share the same buffer, so one clone can be handed to the Machine
+// while the host (or an audio callback on another thread) reads from another.
+#[derive(Debug, Clone)]
+pub struct RingBufferSink {
+    sample_rate: u32,
+    capacity: usize,
+    buffer: Arc<Mutex<VecDeque<StereoSample>>>,
+}
+
+impl RingBufferSink {
+    pub fn new(sample_rate: u32, capacity: usize) -> Self {
+        Self {
+            sample_rate,
+            capacity,
+            buffer: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.buffer.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // removes up to `max` of the oldest samples
+    pub fn pop_samples(&self, max: usize) -> Vec<StereoSample> {
+        let mut buffer = self.buffer.lock().unwrap();
+        let n = max.min(buffer.len());
+        buffer.drain(..n).collect()
+    }
+
+    pub fn take_all(&self) -> Vec<StereoSample> {
+        self.buffer.lock().unwrap().drain(..).collect()
+    }
+}
+
+impl AudioSink for RingBufferSink {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn push_samples(&mut self, samples: &[StereoSample]) {
+        let mut buffer = self.buffer.lock().unwrap();
+        for &s in samples {
+            // overwrite the oldest samples when the host falls behind
+            if buffer.len() == self.capacity {
+                buffer.pop_front();
+            }
+            buffer.push_back(s);
+        }
+    }
+}
+
+// http://soundfile.sapp.org/doc/WaveFormat/
+const WAV_HEADER_SIZE: u32 = 44;
+const WAV_CHANNELS: u16 = 2;
+const WAV_BITS_PER_SAMPLE: u16 = 16;
+
+// Records the output to a 16 bit stereo PCM .wav file.
+// The sizes in the header are filled in by `finish`, or when the sink is dropped.
+#[derive(Debug)]
+pub struct WavSink {
+    sample_rate: u32,
+    writer: Option<BufWriter<File>>,
+    data_size: u32,
+    error: Option<io::Error>,
+}
+
+impl WavSink {
+    pub fn create(path: impl AsRef<Path>, sample_rate: u32) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        Self::write_header(&mut writer, sample_rate, 0)?;
+        Ok(Self {
+            sample_rate,
+            writer: Some(writer),
+            data_size: 0,
+            error: None,
+        })
+    }
+
+    fn write_header(w: &mut impl Write, sample_rate: u32, data_size: u32) -> io::Result<()> {
+        let block_align = WAV_CHANNELS * WAV_BITS_PER_SAMPLE / 8;
+        w.write_all(b"RIFF")?;
+        w.write_all(&(WAV_HEADER_SIZE - 8 + data_size).to_le_bytes())?;
+        w.write_all(b"WAVE")?;
+
+        w.write_all(b"fmt ")?;
+        w.write_all(&16u32.to_le_bytes())?;
+        w.write_all(&1u16.to_le_bytes())?; // PCM
+        w.write_all(&WAV_CHANNELS.to_le_bytes())?;
+        w.write_all(&sample_rate.to_le_bytes())?;
+        w.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+        w.write_all(&block_align.to_le_bytes())?;
+        w.write_all(&WAV_BITS_PER_SAMPLE.to_le_bytes())?;
+
+        w.write_all(b"data")?;
+        w.write_all(&data_size.to_le_bytes())?;
+        Ok(())
+    }
+
+    // writes the final header, and reports the first error that came up while recording
+    pub fn finish(mut self) -> io::Result<()> {
+        self.finalize()
+    }
+
+    fn finalize(&mut self) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        let Some(mut writer) = self.writer.take() else {
+            return Ok(());
+        };
+        writer.seek(SeekFrom::Start(0))?;
+        Self::write_header(&mut writer, self.sample_rate, self.data_size)?;
+        writer.flush()
+    }
+}
+
+impl AudioSink for WavSink {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn push_samples(&mut self, samples: &[StereoSample]) {
+        let Some(writer) = self.writer.as_mut() else {
+            return;
+        };
+        let to_pcm = |s: f32| ((s.clamp(-1.0, 1.0) * i16::MAX as f32) as i16).to_le_bytes();
+        for &(left, right) in samples {
+            let res = writer
+                .write_all(&to_pcm(left))
+                .and_then(|_| writer.write_all(&to_pcm(right)));
+            if let Err(e) = res {
+                self.error = Some(e);
+                self.writer = None;
+                return;
+            }
+            self.data_size += (WAV_CHANNELS * WAV_BITS_PER_SAMPLE / 8) as u32;
+        }
+    }
+}
+
+impl Drop for WavSink {
+    fn drop(&mut self) {
+        let _ = self.finalize();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::*;
+
+    fn read_wav(name: &str, write: impl FnOnce(WavSink)) -> Vec<u8> {
+        let path = env::temp_dir().join(format!("gb-wav-test-{}-{}.wav", std::process::id(), name));
+        write(WavSink::create(&path, 48000).unwrap());
+        let data = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        data
+    }
+
+    fn u16_at(data: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(data[offset..][..2].try_into().unwrap())
+    }
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..][..4].try_into().unwrap())
+    }
+
+    #[test]
+    fn header() {
+        let data = read_wav("header", |mut sink| {
+            sink.push_samples(&[(0.0, 0.0), (1.0, -1.0), (2.0, 0.5)]);
+            sink.finish().unwrap();
+        });
+        assert_eq!(data.len(), 44 + 3 * 4);
+
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(u32_at(&data, 4), 36 + 3 * 4);
+        assert_eq!(&data[8..16], b"WAVEfmt ");
+        assert_eq!(u32_at(&data, 16), 16);
+        assert_eq!(u16_at(&data, 20), 1, "PCM");
+        assert_eq!(u16_at(&data, 22), 2, "channels");
+        assert_eq!(u32_at(&data, 24), 48000, "sample rate");
+        assert_eq!(u32_at(&data, 28), 48000 * 4, "byte rate");
+        assert_eq!(u16_at(&data, 32), 4, "block align");
+        assert_eq!(u16_at(&data, 34), 16, "bits per sample");
+        assert_eq!(&data[36..40], b"data");
+        assert_eq!(u32_at(&data, 40), 3 * 4);
+
+        // left then right, clamped to -1.0..=1.0
+        let samples = data[44..].chunks(2).map(|s| i16::from_le_bytes([s[0], s[1]])).collect::<Vec<_>>();
+        assert_eq!(samples, [0, 0, i16::MAX, -i16::MAX, i16::MAX, i16::MAX / 2]);
+    }
+
+    #[test]
+    fn finished_on_drop() {
+        let data = read_wav("drop", |mut sink| sink.push_samples(&[(0.0, 0.0); 10]));
+        assert_eq!(u32_at(&data, 4), 36 + 10 * 4);
+        assert_eq!(u32_at(&data, 40), 10 * 4);
+    }
+}
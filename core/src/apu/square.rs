@@ -0,0 +1,234 @@
+use super::envelope::Envelope;
+use super::length::Length;
+
+// Channel 1 (with sweep) and Channel 2
+// ============================================================================
+// Register	    Bits
+// ============================================================================
+// NR10	        -PPP NSSS   Sweep pace, negate, shift (channel 1 only)
+// NRx1	        DDLL LLLL   Duty, Length load (64-L)
+// NRx2	        VVVV APPP   Envelope
+// NRx3	        FFFF FFFF   Period low
+// NRx4	        TL-- -FFF   Trigger, Length enable, Period high
+
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1], // 12.5%
+    [1, 0, 0, 0, 0, 0, 0, 1], // 25%
+    [1, 0, 0, 0, 0, 1, 1, 1], // 50%
+    [0, 1, 1, 1, 1, 1, 1, 0], // 75%
+];
+
+const MAX_LENGTH: u16 = 64;
+const MAX_PERIOD: u16 = 0x7ff;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Sweep {
+    pace: u8,
+    negate: bool,
+    shift: u8,
+
+    enabled: bool,
+    timer: u8,
+    shadow: u16,
+    // clearing the negate bit after a calculation was made in negate mode disables the channel
+    negate_used: bool,
+}
+
+impl Sweep {
+    fn read(&self) -> u8 {
+        0x80 | self.pace << 4 | (self.negate as u8) << 3 | self.shift
+    }
+
+    fn write(&mut self, value: u8) {
+        self.pace = (value >> 4) & 0b111;
+        self.negate = value & 0b0000_1000 != 0;
+        self.shift = value & 0b111;
+    }
+
+    fn reload_timer(&mut self) {
+        // a pace of 0 is treated as 8 by the timer
+        self.timer = if self.pace == 0 { 8 } else { self.pace };
+    }
+
+    // returns None on overflow
+    fn calculate(&mut self) -> Option<u16> {
+        let delta = self.shadow >> self.shift;
+        let period = if self.negate {
+            self.negate_used = true;
+            self.shadow - delta
+        } else {
+            self.shadow + delta
+        };
+        if period > MAX_PERIOD {
+            None
+        } else {
+            Some(period)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Square {
+    sweep: Option<Sweep>,
+    length: Length,
+    envelope: Envelope,
+
+    duty: u8,
+    period: u16,
+
+    timer: u16,
+    position: u8,
+
+    pub(super) enabled: bool,
+}
+
+impl Square {
+    pub(super) fn new(with_sweep: bool) -> Self {
+        Self {
+            sweep: with_sweep.then(Sweep::default),
+            length: Length::new(MAX_LENGTH),
+            envelope: Default::default(),
+
+            duty: 0,
+            period: 0,
+
+            timer: 0,
+            position: 0,
+
+            enabled: false,
+        }
+    }
+
+    pub(super) fn reset(&mut self) {
+        *self = Self::new(self.sweep.is_some());
+    }
+
+    pub(super) fn read_sweep(&self) -> u8 {
+        self.sweep.map(|s| s.read()).unwrap_or(0xff)
+    }
+
+    pub(super) fn write_sweep(&mut self, value: u8) {
+        if let Some(sweep) = self.sweep.as_mut() {
+            sweep.write(value);
+            if !sweep.negate && sweep.negate_used {
+                self.enabled = false;
+            }
+        }
+    }
+
+    pub(super) fn read_length_duty(&self) -> u8 {
+        self.duty << 6 | 0b0011_1111
+    }
+
+    pub(super) fn write_length_duty(&mut self, value: u8) {
+        self.duty = value >> 6;
+        self.length.load(value & 0b0011_1111);
+    }
+
+    pub(super) fn read_envelope(&self) -> u8 {
+        self.envelope.read()
+    }
+
+    pub(super) fn write_envelope(&mut self, value: u8) {
+        self.envelope.write(value);
+        if !self.envelope.dac_enabled() {
+            self.enabled = false;
+        }
+    }
+
+    pub(super) fn write_period_low(&mut self, value: u8) {
+        self.period = (self.period & 0x700) | value as u16;
+    }
+
+    pub(super) fn read_control(&self) -> u8 {
+        0b1011_1111 | (self.length.enabled as u8) << 6
+    }
+
+    pub(super) fn write_control(&mut self, value: u8, next_step_clocks_length: bool) {
+        self.period = (self.period & 0xff) | ((value as u16 & 0b111) << 8);
+
+        let trigger = value & 0x80 != 0;
+        if self.length.write_control(value & 0x40 != 0, trigger, next_step_clocks_length) {
+            self.enabled = false;
+        }
+        if trigger {
+            self.trigger();
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.timer = (2048 - self.period) * 4;
+        self.envelope.trigger();
+
+        if let Some(sweep) = self.sweep.as_mut() {
+            sweep.shadow = self.period;
+            sweep.negate_used = false;
+            sweep.reload_timer();
+            sweep.enabled = sweep.pace != 0 || sweep.shift != 0;
+            if sweep.shift != 0 && sweep.calculate().is_none() {
+                self.enabled = false;
+            }
+        }
+    }
+
+    pub(super) fn tick(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer == 0 {
+            self.timer = (2048 - self.period) * 4;
+            self.position = (self.position + 1) % 8;
+        }
+    }
+
+    pub(super) fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub(super) fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub(super) fn clock_sweep(&mut self) {
+        let Some(sweep) = self.sweep.as_mut() else {
+            return;
+        };
+        if sweep.timer > 0 {
+            sweep.timer -= 1;
+        }
+        if sweep.timer != 0 {
+            return;
+        }
+        sweep.reload_timer();
+        if !sweep.enabled || sweep.pace == 0 {
+            return;
+        }
+        match sweep.calculate() {
+            Some(period) if sweep.shift != 0 => {
+                sweep.shadow = period;
+                self.period = period;
+                // the new period is run through the overflow check once more
+                if sweep.calculate().is_none() {
+                    self.enabled = false;
+                }
+            }
+            Some(_) => {}
+            None => self.enabled = false,
+        }
+    }
+
+    pub(super) fn dac_enabled(&self) -> bool {
+        self.envelope.dac_enabled()
+    }
+
+    // digital output in the range 0..=15
+    pub(super) fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        DUTY_TABLE[self.duty as usize][self.position as usize] * self.envelope.volume()
+    }
+}
@@ -0,0 +1,157 @@
+use super::length::Length;
+
+// Channel 3
+// ============================================================================
+// Register	    Bits
+// ============================================================================
+// NR30	        E--- ----   DAC enable
+// NR31	        LLLL LLLL   Length load (256-L)
+// NR32	        -VV- ----   Output level (volume shift)
+// NR33	        FFFF FFFF   Period low
+// NR34	        TL-- -FFF   Trigger, Length enable, Period high
+// FF30-FF3F                Wave RAM, 32 4-bit samples (upper nibble first)
+
+pub(super) const WAVE_RAM_SIZE: usize = 16;
+const MAX_LENGTH: u16 = 256;
+
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Wave {
+    dac_enabled: bool,
+    length: Length,
+    output_level: u8,
+    period: u16,
+
+    timer: u16,
+    position: u8,
+    sample: u8,
+
+    ram: [u8; WAVE_RAM_SIZE],
+
+    pub(super) enabled: bool,
+}
+
+impl Wave {
+    pub(super) fn new() -> Self {
+        Self {
+            dac_enabled: false,
+            length: Length::new(MAX_LENGTH),
+            output_level: 0,
+            period: 0,
+
+            timer: 0,
+            position: 0,
+            sample: 0,
+
+            ram: [0; WAVE_RAM_SIZE],
+
+            enabled: false,
+        }
+    }
+
+    // powering the APU off clears every register, but leaves the wave RAM alone
+    pub(super) fn reset(&mut self) {
+        *self = Self {
+            ram: self.ram,
+            ..Self::new()
+        };
+    }
+
+    pub(super) fn read_dac(&self) -> u8 {
+        (self.dac_enabled as u8) << 7 | 0b0111_1111
+    }
+
+    pub(super) fn write_dac(&mut self, value: u8) {
+        self.dac_enabled = value & 0x80 != 0;
+        if !self.dac_enabled {
+            self.enabled = false;
+        }
+    }
+
+    pub(super) fn write_length(&mut self, value: u8) {
+        self.length.load(value);
+    }
+
+    pub(super) fn read_output_level(&self) -> u8 {
+        self.output_level << 5 | 0b1001_1111
+    }
+
+    pub(super) fn write_output_level(&mut self, value: u8) {
+        self.output_level = (value >> 5) & 0b11;
+    }
+
+    pub(super) fn write_period_low(&mut self, value: u8) {
+        self.period = (self.period & 0x700) | value as u16;
+    }
+
+    pub(super) fn read_control(&self) -> u8 {
+        0b1011_1111 | (self.length.enabled as u8) << 6
+    }
+
+    pub(super) fn write_control(&mut self, value: u8, next_step_clocks_length: bool) {
+        self.period = (self.period & 0xff) | ((value as u16 & 0b111) << 8);
+
+        let trigger = value & 0x80 != 0;
+        if self.length.write_control(value & 0x40 != 0, trigger, next_step_clocks_length) {
+            self.enabled = false;
+        }
+        if trigger {
+            self.enabled = self.dac_enabled;
+            self.timer = (2048 - self.period) * 2;
+            self.position = 0;
+        }
+    }
+
+    // while the channel is playing, the wave RAM can only be accessed
+    // at the byte that is currently being read by the channel
+    pub(super) fn read_ram(&self, index: usize) -> u8 {
+        if self.enabled {
+            self.ram[self.position as usize / 2]
+        } else {
+            self.ram[index]
+        }
+    }
+
+    pub(super) fn write_ram(&mut self, index: usize, value: u8) {
+        if self.enabled {
+            self.ram[self.position as usize / 2] = value;
+        } else {
+            self.ram[index] = value;
+        }
+    }
+
+    pub(super) fn tick(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer == 0 {
+            self.timer = (2048 - self.period) * 2;
+            self.position = (self.position + 1) % 32;
+            let byte = self.ram[self.position as usize / 2];
+            self.sample = if self.position % 2 == 0 { byte >> 4 } else { byte & 0x0f };
+        }
+    }
+
+    pub(super) fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub(super) fn dac_enabled(&self) -> bool {
+        self.dac_enabled
+    }
+
+    // digital output in the range 0..=15
+    pub(super) fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        match self.output_level {
+            0 => 0,
+            1 => self.sample,
+            2 => self.sample >> 1,
+            3 => self.sample >> 2,
+            _ => unreachable!(),
+        }
+    }
+}
@@ -0,0 +1,179 @@
+// ROMs kept compressed, as zip or gzip files. What a file is is decided by its magic bytes,
+// anything else is taken as a raw ROM.
+
+use std::error::Error;
+use std::fs;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+
+use flate2::read::GzDecoder;
+use zip::ZipArchive;
+
+const ZIP_MAGIC: [u8; 4] = [0x50, 0x4b, 0x03, 0x04];
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ROM_EXTENSIONS: [&str; 2] = ["gb", "gbc"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Raw,
+    Zip,
+    Gzip,
+}
+
+impl Format {
+    pub fn detect(data: &[u8]) -> Self {
+        if data.starts_with(&ZIP_MAGIC) {
+            Format::Zip
+        } else if data.starts_with(&GZIP_MAGIC) {
+            Format::Gzip
+        } else {
+            Format::Raw
+        }
+    }
+}
+
+fn is_rom_name(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map_or(false, |e| ROM_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+}
+
+// The ROM in `data`, decompressed. For a zip that's the entry called `entry` (its full path in the
+// archive, or just the file name), or the first .gb/.gbc file when no entry is given.
+pub fn extract(data: Vec<u8>, entry: Option<&str>) -> Result<Vec<u8>, Box<dyn Error>> {
+    match Format::detect(&data) {
+        Format::Raw => Ok(data),
+        Format::Gzip => {
+            let mut rom = vec![];
+            GzDecoder::new(&data[..]).read_to_end(&mut rom)?;
+            Ok(rom)
+        }
+        Format::Zip => {
+            let mut zip = ZipArchive::new(Cursor::new(data))?;
+            // in the order they're stored, which file_names doesn't keep
+            let mut names = vec![];
+            for i in 0..zip.len() {
+                let file = zip.by_index_raw(i)?;
+                if file.is_file() {
+                    names.push(file.name().to_string());
+                }
+            }
+
+            let name = match entry {
+                Some(entry) => names
+                    .into_iter()
+                    .find(|n| n.as_str() == entry || Path::new(n).file_name().map_or(false, |f| f == entry))
+                    .ok_or_else(|| format!("no entry {} in the zip", entry))?,
+                None => names
+                    .into_iter()
+                    .find(|n| is_rom_name(n))
+                    .ok_or("no .gb or .gbc file in the zip")?,
+            };
+            let mut rom = vec![];
+            zip.by_name(&name)?.read_to_end(&mut rom)?;
+            Ok(rom)
+        }
+    }
+}
+
+// reads the ROM at `path`, see `extract`
+pub fn load(path: impl AsRef<Path>, entry: Option<&str>) -> Result<Vec<u8>, Box<dyn Error>> {
+    extract(fs::read(path.as_ref())?, entry)
+}
+
+// The .sav next to the ROM, named after the archive: game.zip and game.gb.gz both save to game.sav.
+pub fn save_path(path: impl AsRef<Path>) -> PathBuf {
+    let path = path.as_ref();
+    let archive = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map_or(false, |e| matches!(e.to_ascii_lowercase().as_str(), "zip" | "gz"));
+    if !archive {
+        return path.with_extension("sav");
+    }
+    let base = path.with_extension("");
+    if base.to_str().map_or(false, is_rom_name) {
+        base.with_extension("sav")
+    } else {
+        // keep dots in the name, game.v1.zip is game.v1.sav
+        let mut base = base.into_os_string();
+        base.push(".sav");
+        base.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::GzEncoder, Compression};
+    use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+    use super::*;
+
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut zip = ZipWriter::new(Cursor::new(vec![]));
+        let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+        zip.add_directory("roms/", options).unwrap();
+        for (name, data) in files {
+            zip.start_file(*name, options).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut gz = GzEncoder::new(vec![], Compression::default());
+        gz.write_all(data).unwrap();
+        gz.finish().unwrap()
+    }
+
+    #[test]
+    fn detect() {
+        assert_eq!(Format::detect(&zip(&[])), Format::Zip);
+        assert_eq!(Format::detect(&gzip(b"rom")), Format::Gzip);
+        assert_eq!(Format::detect(&[0x00, 0xc3, 0x50, 0x01]), Format::Raw);
+        assert_eq!(Format::detect(&[0x1f]), Format::Raw);
+        assert_eq!(Format::detect(&[]), Format::Raw);
+    }
+
+    #[test]
+    fn raw_and_gzip() {
+        assert_eq!(extract(b"rom".to_vec(), None).unwrap(), b"rom");
+        assert_eq!(extract(gzip(b"rom"), None).unwrap(), b"rom");
+    }
+
+    #[test]
+    fn first_rom_in_zip() {
+        let data = zip(&[("readme.txt", b"text"), ("roms/b.GBC", b"b"), ("a.gb", b"a")]);
+        assert_eq!(extract(data, None).unwrap(), b"b");
+
+        let data = zip(&[("readme.txt", b"text")]);
+        assert!(extract(data, None).is_err());
+    }
+
+    #[test]
+    fn zip_entry_by_name() {
+        let files: [(&str, &[u8]); 3] = [("roms/a.gb", b"a"), ("roms/b.gb", b"b"), ("notes.txt", b"text")];
+        assert_eq!(extract(zip(&files), Some("roms/b.gb")).unwrap(), b"b");
+        // the file name on its own is enough, and it doesn't have to be a .gb
+        assert_eq!(extract(zip(&files), Some("b.gb")).unwrap(), b"b");
+        assert_eq!(extract(zip(&files), Some("notes.txt")).unwrap(), b"text");
+        assert!(extract(zip(&files), Some("c.gb")).is_err());
+    }
+
+    #[test]
+    fn save_paths() {
+        for (rom, save) in [
+            ("dir/game.gb", "dir/game.sav"),
+            ("dir/game.gb.gz", "dir/game.sav"),
+            ("dir/game.gbc.GZ", "dir/game.sav"),
+            ("dir/game.zip", "dir/game.sav"),
+            ("dir/game.v1.zip", "dir/game.v1.sav"),
+            ("dir/game.gz", "dir/game.sav"),
+        ] {
+            assert_eq!(save_path(rom), PathBuf::from(save), "{}", rom);
+        }
+    }
+}
@@ -0,0 +1,235 @@
+// Prints what a ROM needs before running it: its header, whether the checksums add up,
+// its banks, and whether the emulator supports its mapper.
+//
+// usage: gb-info [--json] [--disasm[=N]] <ROM or directory>...
+//
+//   --json         one JSON object per ROM and line, for scripts
+//   --disasm[=N]   disassembles N (16 by default) instructions from the entry point, following jumps
+//
+// Directories are searched (not recursively) for .gb and .gbc files, and for .zip and .gz archives,
+// which are read like the emulator reads them.
+
+use std::{error::Error, fs, path::{Path, PathBuf}, process};
+
+use machine::{
+    archive, disassemble,
+    header::{CartridgeHeader, CgbSupport, HeaderWarning},
+    Instruction,
+};
+use serde_json::json;
+
+const ENTRY_POINT: u16 = 0x100;
+const DEFAULT_DISASM_COUNT: usize = 16;
+const ROM_BANK_SIZE: usize = 0x4000;
+const RAM_BANK_SIZE: usize = 0x2000;
+const ROM_EXTENSIONS: [&str; 4] = ["gb", "gbc", "zip", "gz"];
+
+struct Options {
+    json: bool,
+    disasm: Option<usize>,
+    paths: Vec<PathBuf>,
+}
+
+fn usage() -> ! {
+    eprintln!("usage: gb-info [--json] [--disasm[=N]] <ROM or directory>...");
+    process::exit(2);
+}
+
+fn parse_args() -> Options {
+    let mut options = Options { json: false, disasm: None, paths: vec![] };
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--json"   => options.json = true,
+            "--disasm" => options.disasm = Some(DEFAULT_DISASM_COUNT),
+            "-h" | "--help" => usage(),
+            _ if arg.starts_with("--disasm=") => match arg["--disasm=".len()..].parse() {
+                Ok(n) => options.disasm = Some(n),
+                Err(_) => usage(),
+            },
+            _ if arg.starts_with("--") => usage(),
+            _ => options.paths.push(arg.into()),
+        }
+    }
+    if options.paths.is_empty() {
+        usage();
+    }
+    options
+}
+
+// the ROMs in the order given, with directories expanded (sorted)
+fn roms(paths: &[PathBuf]) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut roms = vec![];
+    for path in paths {
+        if path.is_dir() {
+            let mut entries: Vec<PathBuf> = fs::read_dir(path)?
+                .filter_map(|e| e.ok().map(|e| e.path()))
+                .filter(|p| {
+                    p.extension()
+                        .and_then(|e| e.to_str())
+                        .map_or(false, |e| ROM_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+                })
+                .collect();
+            entries.sort();
+            roms.extend(entries);
+        } else {
+            roms.push(path.clone());
+        }
+    }
+    Ok(roms)
+}
+
+// linear disassembly from the entry point, jumping along unconditional JP and JR
+fn entry_disassembly(rom: &[u8], count: usize) -> Vec<Instruction> {
+    let mut out = vec![];
+    let mut addr = ENTRY_POINT;
+    while out.len() < count {
+        let Some(instruction) = disassemble(rom, addr) else {
+            break;
+        };
+        let b = &instruction.bytes;
+        addr = match b[0] {
+            0xc3 => u16::from_le_bytes([b[1], b[2]]),
+            0x18 => instruction.addr.wrapping_add(2).wrapping_add(b[1] as i8 as u16),
+            _    => instruction.addr.wrapping_add(b.len() as u16),
+        };
+        out.push(instruction);
+        // only bank 0 is mapped without knowing the MBC state
+        if addr as usize >= ROM_BANK_SIZE {
+            break;
+        }
+    }
+    out
+}
+
+fn cgb_name(cgb: CgbSupport) -> &'static str {
+    match cgb {
+        CgbSupport::None       => "no",
+        CgbSupport::Compatible => "yes, also runs on DMG",
+        CgbSupport::Only       => "CGB only",
+    }
+}
+
+fn size(bytes: usize) -> String {
+    format!("{} KiB", bytes / 1024)
+}
+
+fn print_text(path: &Path, rom: &[u8], header: &CartridgeHeader, disasm: &[Instruction]) {
+    let warnings = header.validate();
+    let checksum_ok = |bad: fn(&HeaderWarning) -> bool| if warnings.iter().any(bad) { "bad" } else { "ok" };
+    let ty = header.cartridge_type;
+
+    println!("{}", path.display());
+    println!("  title:           {}", header.title);
+    if let Some(manufacturer) = header.manufacturer.as_ref() {
+        println!("  manufacturer:    {}", manufacturer);
+    }
+    println!("  licensee:        {}", header.licensee());
+    println!("  cartridge type:  {}", ty);
+    println!("  mapper:          {}", if ty.mbc.is_supported() { "supported" } else { "not supported" });
+    println!("  saves:           {}", if ty.battery { "yes" } else { "no" });
+    println!("  CGB:             {}", cgb_name(header.cgb));
+    println!("  SGB:             {}", if header.sgb { "yes" } else { "no" });
+    match header.rom_size() {
+        Some(rom_size) => println!("  ROM:             {}, {} banks", size(rom_size), rom_size / ROM_BANK_SIZE),
+        None           => println!("  ROM:             unknown size code {:#04x}", header.rom_size_code),
+    }
+    println!("  file:            {}, {} banks", size(rom.len()), (rom.len() + ROM_BANK_SIZE - 1) / ROM_BANK_SIZE);
+    if header.ram_size() > 0 {
+        println!("  RAM:             {}, {} banks", size(header.ram_size()), header.ram_size() / RAM_BANK_SIZE);
+    } else {
+        println!("  RAM:             none");
+    }
+    println!("  version:         {}", header.version);
+    println!("  destination:     {}", if header.japanese { "Japan" } else { "overseas" });
+    println!(
+        "  header checksum: {:#04x} {}",
+        header.header_checksum,
+        checksum_ok(|w| matches!(w, HeaderWarning::HeaderChecksum { .. }))
+    );
+    println!(
+        "  global checksum: {:#06x} {}",
+        header.global_checksum,
+        checksum_ok(|w| matches!(w, HeaderWarning::GlobalChecksum { .. }))
+    );
+    for warning in warnings.iter() {
+        println!("  warning:         {}", warning);
+    }
+    if !disasm.is_empty() {
+        println!("  entry point:");
+        for i in disasm {
+            let bytes: Vec<String> = i.bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            println!("    {:04x}  {:<9} {}", i.addr, bytes.join(" "), i.text);
+        }
+    }
+}
+
+fn print_json(path: &Path, rom: &[u8], header: &CartridgeHeader, disasm: &[Instruction]) {
+    let warnings = header.validate();
+    let ty = header.cartridge_type;
+    let value = json!({
+        "path": path.display().to_string(),
+        "title": header.title,
+        "manufacturer": header.manufacturer,
+        "licensee": header.licensee(),
+        "cartridge_type": ty.code,
+        "mbc": format!("{:?}", ty.mbc),
+        "supported": ty.mbc.is_supported(),
+        "ram": ty.ram,
+        "battery": ty.battery,
+        "timer": ty.timer,
+        "rumble": ty.rumble,
+        "sensor": ty.sensor,
+        "cgb": format!("{:?}", header.cgb),
+        "sgb": header.sgb,
+        "rom_size": header.rom_size(),
+        "rom_banks": header.rom_size().map(|s| s / ROM_BANK_SIZE),
+        "file_size": rom.len(),
+        "ram_size": header.ram_size(),
+        "ram_banks": header.ram_size() / RAM_BANK_SIZE,
+        "version": header.version,
+        "japanese": header.japanese,
+        "header_checksum": header.header_checksum,
+        "header_checksum_ok": !warnings.iter().any(|w| matches!(w, HeaderWarning::HeaderChecksum { .. })),
+        "global_checksum": header.global_checksum,
+        "global_checksum_ok": !warnings.iter().any(|w| matches!(w, HeaderWarning::GlobalChecksum { .. })),
+        "warnings": warnings.iter().map(|w| w.to_string()).collect::<Vec<_>>(),
+        "disassembly": disasm.iter().map(|i| json!({
+            "addr": i.addr,
+            "bytes": i.bytes,
+            "text": i.text,
+        })).collect::<Vec<_>>(),
+    });
+    println!("{}", value);
+}
+
+fn main() {
+    let options = parse_args();
+    let roms = roms(&options.paths).unwrap_or_else(|e| {
+        eprintln!("gb-info: {}", e);
+        process::exit(1);
+    });
+
+    let mut failed = false;
+    for path in roms {
+        let info = archive::load(&path, None)
+            .and_then(|rom| CartridgeHeader::from_rom(&rom).map(|header| (rom, header)));
+        let (rom, header) = match info {
+            Ok(info) => info,
+            Err(e) => {
+                eprintln!("gb-info: {}: {}", path.display(), e);
+                failed = true;
+                continue;
+            }
+        };
+
+        let disasm = options.disasm.map_or(vec![], |n| entry_disassembly(&rom, n));
+        if options.json {
+            print_json(&path, &rom, &header, &disasm);
+        } else {
+            print_text(&path, &rom, &header, &disasm);
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
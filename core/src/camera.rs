@@ -0,0 +1,101 @@
+use std::error::Error;
+use std::fs::File;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+// size of the image the Game Boy Camera captures
+pub const CAMERA_WIDTH: usize = 128;
+pub const CAMERA_HEIGHT: usize = 112;
+
+pub type CameraFrame = [u8; CAMERA_WIDTH * CAMERA_HEIGHT];
+
+// What the Game Boy Camera's sensor sees, a greyscale frame, row by row, 0 is black and 255 white.
+pub trait ImageSource {
+    // called once for every capture
+    fn capture(&mut self, frame: &mut CameraFrame);
+}
+
+// A frame the host updates whenever it has a new one, e.g. from a webcam.
+// Clones share the same frame, so one clone can be handed to the Machine and the other kept to update it.
+#[derive(Debug, Clone)]
+pub struct SharedFrame {
+    frame: Arc<Mutex<Box<CameraFrame>>>,
+}
+
+impl Default for SharedFrame {
+    fn default() -> Self {
+        Self {
+            frame: Arc::new(Mutex::new(Box::new([0; CAMERA_WIDTH * CAMERA_HEIGHT]))),
+        }
+    }
+}
+
+impl SharedFrame {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&self, frame: &CameraFrame) {
+        self.frame.lock().unwrap().copy_from_slice(frame);
+    }
+}
+
+impl ImageSource for SharedFrame {
+    fn capture(&mut self, frame: &mut CameraFrame) {
+        frame.copy_from_slice(&**self.frame.lock().unwrap());
+    }
+}
+
+// The same picture on every capture, so the results are reproducible.
+#[derive(Debug, Clone)]
+pub struct StillImage {
+    frame: Box<CameraFrame>,
+}
+
+impl StillImage {
+    pub fn new(frame: &CameraFrame) -> Self {
+        Self { frame: Box::new(*frame) }
+    }
+
+    // Loads a PNG, converted to greyscale and scaled to fill the sensor (ignoring the aspect ratio).
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let mut decoder = png::Decoder::new(File::open(path.as_ref())?);
+        // palettes are expanded, and 16 bit channels cut down to 8 bits
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder.read_info()?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf)?;
+
+        let channels = match info.color_type {
+            png::ColorType::Grayscale => 1,
+            png::ColorType::GrayscaleAlpha => 2,
+            png::ColorType::Rgb => 3,
+            png::ColorType::Rgba => 4,
+            png::ColorType::Indexed => return Err("indexed PNG wasn't expanded".into()),
+        };
+        let (width, height) = (info.width as usize, info.height as usize);
+        if width == 0 || height == 0 {
+            return Err("image is empty".into());
+        }
+
+        let mut frame = Box::new([0; CAMERA_WIDTH * CAMERA_HEIGHT]);
+        for y in 0..CAMERA_HEIGHT {
+            for x in 0..CAMERA_WIDTH {
+                // nearest neighbour
+                let (sx, sy) = (x * width / CAMERA_WIDTH, y * height / CAMERA_HEIGHT);
+                let p = &buf[(sy * width + sx) * channels..][..channels];
+                frame[y * CAMERA_WIDTH + x] = match channels {
+                    1 | 2 => p[0],
+                    _ => ((p[0] as u32 * 299 + p[1] as u32 * 587 + p[2] as u32 * 114) / 1000) as u8,
+                };
+            }
+        }
+        Ok(Self { frame })
+    }
+}
+
+impl ImageSource for StillImage {
+    fn capture(&mut self, frame: &mut CameraFrame) {
+        frame.copy_from_slice(&*self.frame);
+    }
+}
@@ -0,0 +1,47 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Where cartridge real-time clocks get the current time from.
+// Only the difference between two calls matters, so the epoch can be anything,
+// as long as it doesn't go backwards.
+pub trait TimeSource {
+    fn now(&self) -> Duration;
+}
+
+// The host's wall clock, the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl TimeSource for SystemClock {
+    fn now(&self) -> Duration {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default()
+    }
+}
+
+// A clock that only moves when it's told to, so tests get the same result on every run.
+// Clones share the same time, so one clone can be handed to the Machine and the other kept to advance it.
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock {
+    millis: Arc<AtomicU64>,
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.millis.fetch_add(by.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    pub fn set(&self, now: Duration) {
+        self.millis.store(now.as_millis() as u64, Ordering::Relaxed);
+    }
+}
+
+impl TimeSource for ManualClock {
+    fn now(&self) -> Duration {
+        Duration::from_millis(self.millis.load(Ordering::Relaxed))
+    }
+}
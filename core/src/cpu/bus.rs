@@ -0,0 +1,69 @@
+use crate::mmu::MMU;
+use crate::util::Addr;
+
+// Everything the CPU sees of the rest of the machine.
+// The MMU is the real one, test harnesses can plug in a flat 64 KiB memory instead.
+pub(crate) trait Bus {
+    // the byte at addr, without it counting as a memory access
+    fn peeku8(&self, addr: Addr) -> u8;
+    fn pokeu8(&mut self, addr: Addr, value: u8);
+
+    // the rest of the machine runs for the machine cycle a memory access takes
+    fn cycle(&mut self) {}
+
+    // memory accesses by the CPU, each one takes a machine cycle
+    fn readu8(&mut self, addr: Addr) -> u8 {
+        self.cycle();
+        self.peeku8(addr)
+    }
+    fn writeu8(&mut self, addr: Addr, value: u8) {
+        self.cycle();
+        self.pokeu8(addr, value)
+    }
+
+    // little endian, the high byte wraps around to 0x0000 after 0xffff
+    fn readu16(&mut self, addr: Addr) -> u16 {
+        let lo = self.readu8(addr);
+        let hi = self.readu8(addr.0.wrapping_add(1).into());
+        u16::from_le_bytes([lo, hi])
+    }
+    fn writeu16(&mut self, addr: Addr, value: u16) {
+        let [lo, hi] = value.to_le_bytes();
+        self.writeu8(addr, lo);
+        self.writeu8(addr.0.wrapping_add(1).into(), hi);
+    }
+}
+
+impl Bus for MMU {
+    fn peeku8(&self, addr: Addr) -> u8 {
+        MMU::readu8(self, addr)
+    }
+    fn pokeu8(&mut self, addr: Addr, value: u8) {
+        MMU::writeu8(self, addr, value)
+    }
+    fn cycle(&mut self) {
+        self.cpu_cycle();
+    }
+}
+
+// No mapping and no IO registers, every address is plain RAM.
+pub(crate) struct FlatBus {
+    mem: Box<[u8; 0x10000]>,
+}
+
+impl FlatBus {
+    pub(crate) fn new() -> Self {
+        Self {
+            mem: Box::new([0; 0x10000]),
+        }
+    }
+}
+
+impl Bus for FlatBus {
+    fn peeku8(&self, addr: Addr) -> u8 {
+        self.mem[addr.0 as usize]
+    }
+    fn pokeu8(&mut self, addr: Addr, value: u8) {
+        self.mem[addr.0 as usize] = value;
+    }
+}
@@ -0,0 +1,107 @@
+// Disassembler, decoding the opcode fields the same way the tables do
+// https://gb-archive.github.io/salvage/decoding_gbz80_opcodes/Decoding%20Gamboy%20Z80%20Opcodes.html
+//
+// opcode bits: xx yyy zzz, and y split into pp q
+
+const R: [&str; 8] = ["B", "C", "D", "E", "H", "L", "(HL)", "A"];
+const RP: [&str; 4] = ["BC", "DE", "HL", "SP"];
+const RP2: [&str; 4] = ["BC", "DE", "HL", "AF"];
+const CC: [&str; 4] = ["NZ", "Z", "NC", "C"];
+const ALU: [&str; 8] = ["ADD A,", "ADC A,", "SUB ", "SBC A,", "AND ", "XOR ", "OR ", "CP "];
+const ROT: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SWAP", "SRL"];
+const ACC: [&str; 8] = ["RLCA", "RRCA", "RLA", "RRA", "DAA", "CPL", "SCF", "CCF"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    pub text: String,
+}
+
+// Decodes the instruction at `addr`, `mem` being what's mapped from 0x0000.
+// None if the instruction runs past the end of `mem`.
+pub fn disassemble(mem: &[u8], addr: u16) -> Option<Instruction> {
+    let at = addr as usize;
+    let op = *mem.get(at)?;
+    let d8 = || mem.get(at + 1).copied();
+    let d16 = || Some(u16::from_le_bytes([*mem.get(at + 1)?, *mem.get(at + 2)?]));
+    // relative jumps are shown with their target
+    let r8 = || d8().map(|v| addr.wrapping_add(2).wrapping_add(v as i8 as u16));
+
+    let (x, y, z) = (op >> 6, ((op >> 3) & 7) as usize, (op & 7) as usize);
+    let (p, q) = (y >> 1, y & 1);
+
+    let (len, text) = match (x, z) {
+        (0, 0) => match y {
+            0 => (1, "NOP".to_string()),
+            1 => (3, format!("LD (${:04x}),SP", d16()?)),
+            2 => (2, "STOP".to_string()),
+            3 => (2, format!("JR ${:04x}", r8()?)),
+            _ => (2, format!("JR {},${:04x}", CC[y - 4], r8()?)),
+        },
+        (0, 1) if q == 0 => (3, format!("LD {},${:04x}", RP[p], d16()?)),
+        (0, 1)           => (1, format!("ADD HL,{}", RP[p])),
+        (0, 2) => {
+            let mem = ["(BC)", "(DE)", "(HL+)", "(HL-)"][p];
+            if q == 0 { (1, format!("LD {},A", mem)) } else { (1, format!("LD A,{}", mem)) }
+        }
+        (0, 3) if q == 0 => (1, format!("INC {}", RP[p])),
+        (0, 3)           => (1, format!("DEC {}", RP[p])),
+        (0, 4) => (1, format!("INC {}", R[y])),
+        (0, 5) => (1, format!("DEC {}", R[y])),
+        (0, 6) => (2, format!("LD {},${:02x}", R[y], d8()?)),
+        (0, 7) => (1, ACC[y].to_string()),
+
+        (1, 6) if y == 6 => (1, "HALT".to_string()),
+        (1, _) => (1, format!("LD {},{}", R[y], R[z])),
+
+        (2, _) => (1, format!("{}{}", ALU[y], R[z])),
+
+        (3, 0) => match y {
+            0..=3 => (1, format!("RET {}", CC[y])),
+            4 => (2, format!("LDH ($ff{:02x}),A", d8()?)),
+            5 => (2, format!("ADD SP,{}", d8()? as i8)),
+            6 => (2, format!("LDH A,($ff{:02x})", d8()?)),
+            _ => (2, format!("LD HL,SP{:+}", d8()? as i8)),
+        },
+        (3, 1) if q == 0 => (1, format!("POP {}", RP2[p])),
+        (3, 1) => (1, ["RET", "RETI", "JP HL", "LD SP,HL"][p].to_string()),
+        (3, 2) => match y {
+            0..=3 => (3, format!("JP {},${:04x}", CC[y], d16()?)),
+            4 => (1, "LD ($ff00+C),A".to_string()),
+            5 => (3, format!("LD (${:04x}),A", d16()?)),
+            6 => (1, "LD A,($ff00+C)".to_string()),
+            _ => (3, format!("LD A,(${:04x})", d16()?)),
+        },
+        (3, 3) => match y {
+            0 => (3, format!("JP ${:04x}", d16()?)),
+            1 => {
+                let cb = d8()?;
+                let (x, y, z) = (cb >> 6, (cb >> 3) & 7, (cb & 7) as usize);
+                let text = match x {
+                    0 => format!("{} {}", ROT[y as usize], R[z]),
+                    1 => format!("BIT {},{}", y, R[z]),
+                    2 => format!("RES {},{}", y, R[z]),
+                    _ => format!("SET {},{}", y, R[z]),
+                };
+                (2, text)
+            }
+            6 => (1, "DI".to_string()),
+            7 => (1, "EI".to_string()),
+            _ => (1, format!("DB ${:02x}", op)),
+        },
+        (3, 4) if y < 4 => (3, format!("CALL {},${:04x}", CC[y], d16()?)),
+        (3, 5) if q == 0 => (1, format!("PUSH {}", RP2[p])),
+        (3, 5) if p == 0 => (3, format!("CALL ${:04x}", d16()?)),
+        (3, 6) => (2, format!("{}${:02x}", ALU[y], d8()?)),
+        (3, 7) => (1, format!("RST ${:02x}", y * 8)),
+        // the opcodes that don't exist on the SM83
+        _ => (1, format!("DB ${:02x}", op)),
+    };
+
+    Some(Instruction {
+        addr,
+        bytes: mem.get(at..at + len)?.to_vec(),
+        text,
+    })
+}
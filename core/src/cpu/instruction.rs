@@ -1,7 +1,6 @@
-use super::{Addr, IMEState, CPU};
-use crate::mmu::MMU;
+use super::{Addr, Bus, IMEState, CPU};
 
-pub(super) fn decode(opcode: u16, cpu: &mut CPU, mmu: &mut MMU) -> u64 {
+pub(super) fn decode(opcode: u16, cpu: &mut CPU, mmu: &mut impl Bus) -> u64 {
     // println!("{:#x}", opcode);
     match opcode {
         0x0000 => {
@@ -14,6 +13,7 @@ pub(super) fn decode(opcode: u16, cpu: &mut CPU, mmu: &mut MMU) -> u64 {
         }
         0x0076 => {
             /*HALT*/
+            cpu.halted = true;
             4
         }
         0x00f3 => {
@@ -180,7 +180,7 @@ pub(super) fn decode(opcode: u16, cpu: &mut CPU, mmu: &mut MMU) -> u64 {
             // Cycles: 4/12(hl)
             let (target, is_hl) = get_r8_reg(get_y(opcode as u8));
             let value = read_from_r8(cpu, mmu, target);
-            let new_value = value + 1;
+            let new_value = value.wrapping_add(1);
             write_to_r8(cpu, mmu, target, new_value);
             cpu.regs.f.zero = new_value == 0;
             cpu.regs.f.subtraction = false;
@@ -198,7 +198,7 @@ pub(super) fn decode(opcode: u16, cpu: &mut CPU, mmu: &mut MMU) -> u64 {
             // Cycles: 4/12(hl)
             let (target, is_hl) = get_r8_reg(get_y(opcode as u8));
             let value = read_from_r8(cpu, mmu, target);
-            let new_value = value - 1;
+            let new_value = value.wrapping_sub(1);
             write_to_r8(cpu, mmu, target, new_value);
             cpu.regs.f.zero = new_value == 0;
             cpu.regs.f.subtraction = true;
@@ -243,7 +243,7 @@ pub(super) fn decode(opcode: u16, cpu: &mut CPU, mmu: &mut MMU) -> u64 {
             // Cycles: 8
             let reg = R16G1::try_from(get_p(opcode as u8)).unwrap();
             let val = read_from_r16_group1(cpu, reg);
-            write_to_r16_group1(cpu, reg, val + 1);
+            write_to_r16_group1(cpu, reg, val.wrapping_add(1));
             8
         }
 
@@ -269,7 +269,7 @@ pub(super) fn decode(opcode: u16, cpu: &mut CPU, mmu: &mut MMU) -> u64 {
             // Cycles: 8
             let reg = R16G1::try_from(get_p(opcode as u8)).unwrap();
             let val = read_from_r16_group1(cpu, reg);
-            write_to_r16_group1(cpu, reg, val - 1);
+            write_to_r16_group1(cpu, reg, val.wrapping_sub(1));
             8
         }
 
@@ -568,7 +568,7 @@ fn get_r8_reg(oct: u8) -> (R8, bool) {
     (r8, is_hl)
 }
 
-fn read_from_r8(cpu: &mut CPU, mmu: &MMU, src: R8) -> u8 {
+fn read_from_r8(cpu: &mut CPU, mmu: &mut impl Bus, src: R8) -> u8 {
     match src {
         R8::B => cpu.regs.get_b(),
         R8::C => cpu.regs.get_c(),
@@ -581,7 +581,7 @@ fn read_from_r8(cpu: &mut CPU, mmu: &MMU, src: R8) -> u8 {
     }
 }
 
-fn write_to_r8(cpu: &mut CPU, mmu: &mut MMU, dst: R8, v: u8) {
+fn write_to_r8(cpu: &mut CPU, mmu: &mut impl Bus, dst: R8, v: u8) {
     match dst {
         R8::B => cpu.regs.set_b(v),
         R8::C => cpu.regs.set_c(v),
@@ -679,12 +679,12 @@ fn get_addr_from_r16_group2(cpu: &mut CPU, opcode: u8) -> Addr {
     addr.into()
 }
 
-fn write_to_r16_group2(cpu: &mut CPU, mmu: &mut MMU, opcode: u8, value: u8) {
+fn write_to_r16_group2(cpu: &mut CPU, mmu: &mut impl Bus, opcode: u8, value: u8) {
     let addr = get_addr_from_r16_group2(cpu, opcode);
     mmu.writeu8(addr, value);
 }
 
-fn read_from_r16_group2(cpu: &mut CPU, mmu: &MMU, opcode: u8) -> u8 {
+fn read_from_r16_group2(cpu: &mut CPU, mmu: &mut impl Bus, opcode: u8) -> u8 {
     let addr = get_addr_from_r16_group2(cpu, opcode);
     let val = mmu.readu8(addr);
     val
@@ -780,7 +780,7 @@ fn perform_alu8(cpu: &mut CPU, operation: AluOp, op2: u8) {
             cpu.regs.f.zero = res == 0;
             cpu.regs.f.subtraction = false;
             cpu.regs.f.half_carry =
-                bit_3_overflow(a, op2) || bit_3_overflow(a + op2, cpu.regs.f.carry as u8);
+                bit_3_overflow(a, op2) || bit_3_overflow(a.wrapping_add(op2), cpu.regs.f.carry as u8);
             cpu.regs.f.carry = of;
             cpu.regs.set_a(res);
         }
@@ -799,7 +799,7 @@ fn perform_alu8(cpu: &mut CPU, operation: AluOp, op2: u8) {
             cpu.regs.f.zero = res == 0;
             cpu.regs.f.subtraction = true;
             cpu.regs.f.half_carry =
-                bit_4_borrow(a, op2) || bit_4_borrow(a - op2, cpu.regs.f.carry as u8);
+                bit_4_borrow(a, op2) || bit_4_borrow(a.wrapping_sub(op2), cpu.regs.f.carry as u8);
             cpu.regs.f.carry = of;
             cpu.regs.set_a(res);
         }
@@ -1008,18 +1008,18 @@ fn perform_acc_flag(cpu: &mut CPU, operation: AccFlagOp) {
             let mut value = cpu.regs.get_a();
             if !cpu.regs.f.subtraction {
                 if cpu.regs.f.carry || value > 0x99 {
-                    value += 0x60;
+                    value = value.wrapping_add(0x60);
                     cpu.regs.f.carry = true;
                 }
                 if cpu.regs.f.half_carry || (value & 0x0f) > 0x09 {
-                    value += 0x06;
+                    value = value.wrapping_add(0x06);
                 }
             } else {
                 if cpu.regs.f.carry {
-                    value -= 0x60;
+                    value = value.wrapping_sub(0x60);
                 }
                 if cpu.regs.f.half_carry {
-                    value -= 0x06;
+                    value = value.wrapping_sub(0x06);
                 }
             }
             cpu.regs.set_a(value);
@@ -1,9 +1,12 @@
+pub(crate) mod bus;
+pub(crate) mod disasm;
 mod instruction;
 pub(crate) mod interrupts;
 mod registers;
 
 use super::mmu::MMU;
-use crate::util::Addr;
+use crate::{ppu::REG_LCDC, util::Addr};
+use bus::Bus;
 use instruction::decode;
 use interrupts::{Interrupt, Interrupts};
 use registers::Flags;
@@ -12,11 +15,32 @@ use registers::Registers;
 const INT_ENABLE_ADDR: Addr = Addr::from(0xffff);
 const INT_REQUEST_ADDR: Addr = Addr::from(0xff0f);
 
+// LD B,B doesn't do anything, so test ROMs (and debuggers) use it as a software breakpoint
+const LD_B_B: u16 = 0x40;
+
+// Snapshot of the CPU registers, for debuggers and test harnesses
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CpuState {
+    pub a: u8,
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+}
+
 pub(crate) struct CPU {
     regs: Registers,
     pc: Addr,
     sp: Addr,
     ime: IMEState,
+    // HALT stops the CPU until an enabled interrupt is requested, whether or not IME is set
+    halted: bool,
+    pub(crate) breakpoint: bool,
 }
 
 impl CPU {
@@ -26,6 +50,8 @@ impl CPU {
             pc: Addr::new(),
             sp: Addr::new(),
             ime: IMEState::Disabled,
+            halted: false,
+            breakpoint: false,
         }
     }
 
@@ -58,26 +84,81 @@ impl CPU {
         self.pc = 0x0101.into();
         self.sp = 0xfffe.into();
         // self.ime = IMEState::Enabled;
+        // the boot ROM leaves the LCD on
+        mmu.writeu8(REG_LCDC.into(), 0x91);
         mmu.boot_disabled = true;
     }
 
-    pub(crate) fn step(&mut self, mmu: &mut MMU) -> u64 {
+    pub(crate) fn step(&mut self, mmu: &mut impl Bus) -> u64 {
+        if self.halted {
+            let pending = self.get_interrupt_enable(mmu) & self.get_interrupt_request(mmu);
+            if pending.next_interrupt().is_none() {
+                return 4;
+            }
+            self.halted = false;
+        }
         if self.handle_ime(mmu) {
             return 20;
         }
+        self.execute(mmu)
+    }
+
+    // fetches and runs a single instruction, without checking for interrupts first
+    pub(crate) fn execute(&mut self, mmu: &mut impl Bus) -> u64 {
         // println!("A: {:0>2X} F: {:0>2X} B: {:0>2X} C: {:0>2X} D: {:0>2X} E: {:0>2X} H: {:0>2X} L: {:0>2X} SP: {:0>4X} PC: 00:{:0>4X} ({:0>2X} {:0>2X} {:0>2X} {:0>2X})", self.regs.a, <Flags as Into<u8>>::into(self.regs.f), self.regs.b, self.regs.c, self.regs.d, self.regs.e, self.regs.h, self.regs.l, self.sp.0, self.pc.0, mmu.readu8(self.pc),mmu.readu8(self.pc+1.into()),mmu.readu8(self.pc+2.into()),mmu.readu8(self.pc+3.into()));
         let mut opcode = self.readu8(mmu) as u16;
         if opcode == 0xcb {
             opcode = opcode << 8 | self.readu8(mmu) as u16;
         }
 
+        if opcode == LD_B_B {
+            self.breakpoint = true;
+        }
+
         // println!("{:#x?}\t", opcode);
         let ticks = decode(opcode, self, mmu);
         ticks
     }
 
+    pub(crate) fn state(&self) -> CpuState {
+        CpuState {
+            a: self.regs.get_a(),
+            f: self.regs.get_f(),
+            b: self.regs.get_b(),
+            c: self.regs.get_c(),
+            d: self.regs.get_d(),
+            e: self.regs.get_e(),
+            h: self.regs.get_h(),
+            l: self.regs.get_l(),
+            sp: self.sp.into(),
+            pc: self.pc.into(),
+        }
+    }
+
+    pub(crate) fn set_state(&mut self, state: CpuState) {
+        self.regs.set_a(state.a);
+        self.regs.set_f(state.f);
+        self.regs.set_b(state.b);
+        self.regs.set_c(state.c);
+        self.regs.set_d(state.d);
+        self.regs.set_e(state.e);
+        self.regs.set_h(state.h);
+        self.regs.set_l(state.l);
+        self.sp = state.sp.into();
+        self.pc = state.pc.into();
+    }
+
+    // true once interrupts are actually enabled, EI only takes effect after the next instruction
+    pub(crate) fn ime(&self) -> bool {
+        matches!(self.ime, IMEState::Enabled)
+    }
+
+    pub(crate) fn set_ime(&mut self, enabled: bool) {
+        self.ime = if enabled { IMEState::Enabled } else { IMEState::Disabled };
+    }
+
     // interrupts
-    fn handle_ime(&mut self, mmu: &mut MMU) -> bool {
+    fn handle_ime(&mut self, mmu: &mut impl Bus) -> bool {
         match self.ime {
             IMEState::Enabled => {
                 let mut request = self.get_interrupt_request(mmu);
@@ -121,28 +202,29 @@ impl CPU {
         false
     }
 
-    fn get_interrupt_enable(&self, mmu: &MMU) -> Interrupts {
-        mmu.readu8(INT_ENABLE_ADDR).into()
+    // the interrupt registers are checked between instructions, they aren't memory accesses
+    fn get_interrupt_enable(&self, mmu: &impl Bus) -> Interrupts {
+        mmu.peeku8(INT_ENABLE_ADDR).into()
     }
 
-    fn get_interrupt_request(&self, mmu: &MMU) -> Interrupts {
-        mmu.readu8(INT_REQUEST_ADDR).into()
+    fn get_interrupt_request(&self, mmu: &impl Bus) -> Interrupts {
+        mmu.peeku8(INT_REQUEST_ADDR).into()
     }
-    fn set_interrupt_request(&self, mmu: &mut MMU, request: Interrupts) {
-        mmu.writeu8(INT_REQUEST_ADDR, request.into());
+    fn set_interrupt_request(&self, mmu: &mut impl Bus, request: Interrupts) {
+        mmu.pokeu8(INT_REQUEST_ADDR, request.into());
     }
 
     // stack
-    pub fn push_stack(&mut self, mmu: &mut MMU, v: u16) {
+    pub fn push_stack(&mut self, mmu: &mut impl Bus, v: u16) {
         // TODO
         // invariance? sp pointing to the location where next piece of information
         // can be written?
-        self.sp -= 2.into();
+        self.sp = self.sp.0.wrapping_sub(2).into();
         mmu.writeu16(self.sp, v);
     }
-    pub fn pop_stack(&mut self, mmu: &MMU) -> u16 {
+    pub fn pop_stack(&mut self, mmu: &mut impl Bus) -> u16 {
         let v = mmu.readu16(self.sp);
-        self.sp += 2.into();
+        self.sp = self.sp.0.wrapping_add(2).into();
         v
     }
 
@@ -151,20 +233,20 @@ impl CPU {
     }
 
     // mmu
-    pub fn readu8(&mut self, mmu: &MMU) -> u8 {
+    pub fn readu8(&mut self, mmu: &mut impl Bus) -> u8 {
         let ret = mmu.readu8(self.pc);
-        self.pc += 1.into();
+        self.pc = self.pc.0.wrapping_add(1).into();
         ret
     }
-    pub fn readu16(&mut self, mmu: &MMU) -> u16 {
+    pub fn readu16(&mut self, mmu: &mut impl Bus) -> u16 {
         let ret = mmu.readu16(self.pc);
-        self.pc += 2.into();
+        self.pc = self.pc.0.wrapping_add(2).into();
         ret
     }
-    pub fn writeu8(&mut self, value: u8, mmu: &mut MMU) {
+    pub fn writeu8(&mut self, value: u8, mmu: &mut impl Bus) {
         mmu.writeu8(self.pc, value);
     }
-    pub fn writeu16(&mut self, value: u16, mmu: &mut MMU) {
+    pub fn writeu16(&mut self, value: u16, mmu: &mut impl Bus) {
         mmu.writeu16(self.pc, value);
     }
 }
@@ -135,11 +135,11 @@ impl Registers {
         self.h = msb(v)
     }
     pub fn incr_hl(&mut self) {
-        let v = self.get_hl() + 1;
+        let v = self.get_hl().wrapping_add(1);
         self.set_hl(v);
     }
     pub fn decr_hl(&mut self) {
-        let v = self.get_hl() - 1;
+        let v = self.get_hl().wrapping_sub(1);
         self.set_hl(v);
     }
     pub fn set_af(&mut self, v: u16) {
@@ -0,0 +1,114 @@
+use std::{cell::RefCell, error::Error, path::Path, rc::Rc};
+
+use crate::{serial::SerialDevice, Machine};
+
+// Blargg's test ROMs (cpu_instrs, instr_timing, mem_timing, ...) print their results
+// through the serial port: every character is written to SB, followed by 0x81 to SC.
+// https://github.com/retrio/gb-test-roms
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlarggStatus {
+    Passed,
+    Failed,
+    // ran out of cycles before the ROM reported anything
+    Timeout,
+}
+
+#[derive(Debug, Clone)]
+pub struct BlarggResult {
+    pub status: BlarggStatus,
+    // everything the ROM printed
+    pub output: String,
+    pub cycles: u64,
+}
+
+impl BlarggResult {
+    pub fn passed(&self) -> bool {
+        self.status == BlarggStatus::Passed
+    }
+}
+
+// Records every byte sent over the link cable, with nothing plugged in on the other end.
+#[derive(Debug, Clone, Default)]
+struct SerialRecorder(Rc<RefCell<Vec<u8>>>);
+
+impl SerialDevice for SerialRecorder {
+    fn transfer(&mut self, outgoing: u8) -> u8 {
+        self.0.borrow_mut().push(outgoing);
+        0xff
+    }
+}
+
+// Runs the ROM for at most `max_cycles` cpu ticks, stopping as soon as it reports a result.
+pub fn run(rom: impl AsRef<Path>, max_cycles: u64) -> Result<BlarggResult, Box<dyn Error>> {
+    let mut m = Machine::new(rom, None::<&Path>)?;
+    // test runs shouldn't leave .sav files next to the ROMs
+    m.set_save_path(None);
+    Ok(run_machine(&mut m, max_cycles))
+}
+
+pub fn run_machine(m: &mut Machine, max_cycles: u64) -> BlarggResult {
+    let recorder = SerialRecorder::default();
+    m.attach_serial(Box::new(recorder.clone()));
+
+    let mut cycles = 0;
+    let mut seen = 0;
+    let mut status = BlarggStatus::Timeout;
+    while cycles < max_cycles {
+        cycles += m.step();
+
+        // only look at the output when something new got printed
+        let len = recorder.0.borrow().len();
+        if len == seen {
+            continue;
+        }
+        seen = len;
+        if let Some(s) = check_output(&recorder.0.borrow()) {
+            status = s;
+            break;
+        }
+    }
+
+    m.detach_serial();
+    let output = String::from_utf8_lossy(&recorder.0.borrow()).into_owned();
+    BlarggResult { status, output, cycles }
+}
+
+fn check_output(output: &[u8]) -> Option<BlarggStatus> {
+    let output = String::from_utf8_lossy(output);
+    if output.contains("Passed") {
+        return Some(BlarggStatus::Passed);
+    }
+    // wait for the rest of the line, it says which test failed
+    let failed = output.find("Failed")?;
+    output[failed..].contains('\n').then_some(BlarggStatus::Failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passed() {
+        assert_eq!(check_output(b"cpu_instrs\n\n01:ok  02:ok\n\nPassed all tests\n"), Some(BlarggStatus::Passed));
+        // no need to wait for the end of the line
+        assert_eq!(check_output(b"instr_timing\n\n\nPassed"), Some(BlarggStatus::Passed));
+    }
+
+    #[test]
+    fn failed_waits_for_the_line() {
+        assert_eq!(check_output(b"mem_timing\n\n01:01\n\nFailed"), None);
+        assert_eq!(check_output(b"mem_timing\n\n01:01\n\nFailed 1 tests"), None);
+    }
+
+    #[test]
+    fn failed() {
+        assert_eq!(check_output(b"mem_timing\n\n01:01\n\nFailed 1 tests\n"), Some(BlarggStatus::Failed));
+    }
+
+    #[test]
+    fn nothing_yet() {
+        assert_eq!(check_output(b""), None);
+        assert_eq!(check_output(b"cpu_instrs\n\n01:ok  "), None);
+    }
+}
@@ -0,0 +1,166 @@
+use std::{error::Error, fmt::Write, fs::File, io::BufWriter, path::Path};
+
+use crate::ppu::{Colour, Screen, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+// Golden-image checks for the PPU: a captured frame is compared against a reference PNG
+// (dmg-acid2's reference-dmg.png, or frames saved from a known good build).
+// https://github.com/mattcurrie/dmg-acid2
+//
+// Reference images don't all use the same greys, so every reference pixel is
+// matched to the nearest of the 4 DMG shades by its brightness.
+
+// mismatches listed individually in the report
+const MAX_REPORTED_PIXELS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PixelMismatch {
+    pub x: usize,
+    pub y: usize,
+    pub expected: Colour,
+    pub actual: Colour,
+}
+
+#[derive(Debug, Clone)]
+pub struct ImageDiff {
+    pub mismatches: Vec<PixelMismatch>,
+}
+
+impl ImageDiff {
+    pub fn matches(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+
+    pub fn report(&self) -> String {
+        let mut s = String::new();
+        if self.matches() {
+            writeln!(s, "frame matches the reference").unwrap();
+            return s;
+        }
+
+        let total = SCREEN_WIDTH * SCREEN_HEIGHT;
+        writeln!(
+            s,
+            "{} of {} pixels differ ({:.2}%)",
+            self.mismatches.len(),
+            total,
+            self.mismatches.len() as f64 * 100.0 / total as f64
+        )
+        .unwrap();
+        for m in self.mismatches.iter().take(MAX_REPORTED_PIXELS) {
+            writeln!(s, "  ({:>3}, {:>3}): expected {:?}, got {:?}", m.x, m.y, m.expected, m.actual).unwrap();
+        }
+        if self.mismatches.len() > MAX_REPORTED_PIXELS {
+            writeln!(s, "  ... and {} more", self.mismatches.len() - MAX_REPORTED_PIXELS).unwrap();
+        }
+        s
+    }
+
+    // Writes the captured frame faded out, with every mismatched pixel in red.
+    pub fn write_diff_image(&self, screen: &Screen, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        let mut data: Vec<u8> = screen
+            .pixels()
+            .iter()
+            .flat_map(|&c| {
+                let grey = (shade(c) as u16 + 255 * 2) / 3;
+                [grey as u8; 3]
+            })
+            .collect();
+        for m in &self.mismatches {
+            let i = (m.y * SCREEN_WIDTH + m.x) * 3;
+            data[i..i + 3].copy_from_slice(&[0xff, 0x00, 0x00]);
+        }
+        write_png(path, png::ColorType::Rgb, &data)
+    }
+}
+
+pub fn compare(screen: &Screen, reference: impl AsRef<Path>) -> Result<ImageDiff, Box<dyn Error>> {
+    let expected = load_reference(reference)?;
+    let mismatches = expected
+        .into_iter()
+        .zip(screen.pixels().iter().copied())
+        .enumerate()
+        .filter(|(_, (expected, actual))| expected != actual)
+        .map(|(i, (expected, actual))| PixelMismatch {
+            x: i % SCREEN_WIDTH,
+            y: i / SCREEN_WIDTH,
+            expected,
+            actual,
+        })
+        .collect();
+    Ok(ImageDiff { mismatches })
+}
+
+// Compares against the reference, and writes the diff image to `diff_path` if they don't match.
+pub fn check(
+    screen: &Screen,
+    reference: impl AsRef<Path>,
+    diff_path: impl AsRef<Path>,
+) -> Result<ImageDiff, Box<dyn Error>> {
+    let diff = compare(screen, reference)?;
+    if !diff.matches() {
+        diff.write_diff_image(screen, diff_path)?;
+    }
+    Ok(diff)
+}
+
+// Saves the frame as an 8 bit greyscale PNG, e.g. to create a new reference image.
+pub fn save_png(screen: &Screen, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+    let data: Vec<u8> = screen.pixels().iter().map(|&c| shade(c)).collect();
+    write_png(path, png::ColorType::Grayscale, &data)
+}
+
+fn load_reference(path: impl AsRef<Path>) -> Result<Vec<Colour>, Box<dyn Error>> {
+    let mut decoder = png::Decoder::new(File::open(path.as_ref())?);
+    // palettes are expanded, and 16 bit channels cut down to 8 bits
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info()?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf)?;
+
+    if info.width as usize != SCREEN_WIDTH || info.height as usize != SCREEN_HEIGHT {
+        return Err(format!(
+            "reference image is {}x{}, expected {}x{}",
+            info.width, info.height, SCREEN_WIDTH, SCREEN_HEIGHT
+        )
+        .into());
+    }
+
+    let channels = match info.color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::Rgb => 3,
+        png::ColorType::Rgba => 4,
+        png::ColorType::Indexed => return Err("indexed PNG wasn't expanded".into()),
+    };
+    let colours = buf[..info.buffer_size()]
+        .chunks(channels)
+        .map(|p| match channels {
+            1 | 2 => p[0],
+            _ => ((p[0] as u32 * 299 + p[1] as u32 * 587 + p[2] as u32 * 114) / 1000) as u8,
+        })
+        .map(nearest_colour)
+        .collect();
+    Ok(colours)
+}
+
+fn write_png(path: impl AsRef<Path>, colour_type: png::ColorType, data: &[u8]) -> Result<(), Box<dyn Error>> {
+    let w = BufWriter::new(File::create(path.as_ref())?);
+    let mut encoder = png::Encoder::new(w, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32);
+    encoder.set_color(colour_type);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(data)?;
+    Ok(())
+}
+
+// brightness of a shade, the same greys as the window uses
+fn shade(c: Colour) -> u8 {
+    let rgb: u32 = c.into();
+    (rgb & 0xff) as u8
+}
+
+// nearest DMG shade to a brightness, works for both the 0/85/170/255 and the 0/96/192/255 greys
+fn nearest_colour(brightness: u8) -> Colour {
+    let level = (brightness as u16 + 42) / 85;
+    Colour::try_from(3 - level as u8).unwrap()
+}
@@ -0,0 +1,6 @@
+// Headless runners for the community test ROMs, so they can be driven from `cargo test`.
+
+pub mod blargg;
+pub mod golden;
+pub mod mooneye;
+pub mod sst;
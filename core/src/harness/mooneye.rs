@@ -0,0 +1,143 @@
+use std::{
+    error::Error,
+    fmt::Write,
+    fs,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+};
+
+use crate::{CpuState, Machine};
+
+// Mooneye test ROMs execute LD B,B once they are done, and report the result in the registers:
+// the Fibonacci numbers 3/5/8/13/21/34 in B/C/D/E/H/L for a pass, anything else is a failure
+// (the ROMs use 0x42 in all of them).
+// https://github.com/Gekkio/mooneye-test-suite
+
+const PASS_REGISTERS: [u8; 6] = [3, 5, 8, 13, 21, 34];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MooneyeStatus {
+    Passed,
+    Failed,
+    // ran out of cycles before hitting the breakpoint
+    Timeout,
+    // the ROM couldn't be loaded, or the emulator panicked while running it
+    Error(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct MooneyeResult {
+    pub rom: PathBuf,
+    pub status: MooneyeStatus,
+    pub cycles: u64,
+    // registers at the time of the breakpoint (or timeout)
+    pub registers: CpuState,
+}
+
+impl MooneyeResult {
+    pub fn passed(&self) -> bool {
+        self.status == MooneyeStatus::Passed
+    }
+}
+
+pub fn run(rom: impl AsRef<Path>, max_cycles: u64) -> MooneyeResult {
+    let rom = rom.as_ref();
+    let res = panic::catch_unwind(AssertUnwindSafe(|| -> Result<_, Box<dyn Error>> {
+        let mut m = Machine::new(rom, None::<&Path>)?;
+        // test runs shouldn't leave .sav files next to the ROMs
+        m.set_save_path(None);
+        Ok(run_machine(&mut m, max_cycles))
+    }));
+
+    let (status, cycles, registers) = match res {
+        Ok(Ok(res)) => res,
+        Ok(Err(e)) => (MooneyeStatus::Error(e.to_string()), 0, Default::default()),
+        Err(e) => {
+            let msg = e
+                .downcast_ref::<String>()
+                .cloned()
+                .or_else(|| e.downcast_ref::<&str>().map(|s| s.to_string()))
+                .unwrap_or_else(|| "panicked".to_string());
+            (MooneyeStatus::Error(msg), 0, Default::default())
+        }
+    };
+
+    MooneyeResult {
+        rom: rom.to_owned(),
+        status,
+        cycles,
+        registers,
+    }
+}
+
+pub fn run_machine(m: &mut Machine, max_cycles: u64) -> (MooneyeStatus, u64, CpuState) {
+    let mut cycles = 0;
+    while cycles < max_cycles {
+        cycles += m.step();
+        if m.take_breakpoint() {
+            let regs = m.cpu_state();
+            let status = if [regs.b, regs.c, regs.d, regs.e, regs.h, regs.l] == PASS_REGISTERS {
+                MooneyeStatus::Passed
+            } else {
+                MooneyeStatus::Failed
+            };
+            return (status, cycles, regs);
+        }
+    }
+    (MooneyeStatus::Timeout, cycles, m.cpu_state())
+}
+
+// Runs every .gb file in `dir` (and its subdirectories), in alphabetical order.
+pub fn run_dir(dir: impl AsRef<Path>, max_cycles: u64) -> Result<Vec<MooneyeResult>, Box<dyn Error>> {
+    let mut roms = vec![];
+    collect_roms(dir.as_ref(), &mut roms)?;
+    roms.sort();
+    Ok(roms.iter().map(|rom| run(rom, max_cycles)).collect())
+}
+
+fn collect_roms(dir: &Path, roms: &mut Vec<PathBuf>) -> Result<(), Box<dyn Error>> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_roms(&path, roms)?;
+        } else if path.extension().map_or(false, |e| e == "gb") {
+            roms.push(path);
+        }
+    }
+    Ok(())
+}
+
+pub fn summary(results: &[MooneyeResult]) -> String {
+    let width = results
+        .iter()
+        .map(|r| r.rom.display().to_string().len())
+        .max()
+        .unwrap_or(0)
+        .max("ROM".len());
+
+    let mut s = String::new();
+    writeln!(s, "{:<width$}  {:<8}  {:>12}", "ROM", "RESULT", "CYCLES").unwrap();
+    writeln!(s, "{}", "=".repeat(width + 24)).unwrap();
+    for r in results {
+        let status = match &r.status {
+            MooneyeStatus::Passed => "pass",
+            MooneyeStatus::Failed => "FAIL",
+            MooneyeStatus::Timeout => "TIMEOUT",
+            MooneyeStatus::Error(_) => "ERROR",
+        };
+        write!(s, "{:<width$}  {:<8}  {:>12}", r.rom.display(), status, r.cycles).unwrap();
+        if let MooneyeStatus::Error(e) = &r.status {
+            write!(s, "  {}", e).unwrap();
+        }
+        writeln!(s).unwrap();
+    }
+
+    let passed = results.iter().filter(|r| r.passed()).count();
+    writeln!(s, "{}", "=".repeat(width + 24)).unwrap();
+    writeln!(s, "{}/{} passed", passed, results.len()).unwrap();
+    s
+}
+
+pub fn print_summary(results: &[MooneyeResult]) {
+    print!("{}", summary(results));
+}
@@ -0,0 +1,295 @@
+use std::{
+    error::Error,
+    fmt::Write,
+    fs,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::cpu::{
+    bus::{Bus, FlatBus},
+    CPU,
+};
+use crate::CpuState;
+
+// Per-instruction CPU tests in the SingleStepTests JSON format.
+// Every file holds the test cases for one opcode (00.json .. ff.json, cb 00.json .. cb ff.json),
+// and every case gives the state before and after running a single instruction,
+// plus one entry per M-cycle the instruction takes.
+// https://github.com/SingleStepTests/sm83
+//
+// The CPU runs on a flat 64 KiB bus, so there's no mapping or IO registers getting in the way.
+// Only the number of cycles is checked, not the bus activity during each of them.
+
+// failures listed individually per file in the summary
+const MAX_REPORTED_FAILURES: usize = 3;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TestCase {
+    pub name: String,
+    pub initial: TestState,
+    #[serde(rename = "final")]
+    pub expected: TestState,
+    // one entry per M-cycle, [addr, value, activity], entries of internal cycles can be null
+    pub cycles: Vec<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TestState {
+    pub pc: u16,
+    pub sp: u16,
+    pub a: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub f: u8,
+    pub h: u8,
+    pub l: u8,
+    #[serde(default)]
+    pub ime: Option<u8>,
+    #[serde(default)]
+    pub ie: Option<u8>,
+    // (address, value) pairs, everything else is 0
+    pub ram: Vec<(u16, u8)>,
+}
+
+impl TestState {
+    fn cpu_state(&self) -> CpuState {
+        CpuState {
+            a: self.a,
+            f: self.f,
+            b: self.b,
+            c: self.c,
+            d: self.d,
+            e: self.e,
+            h: self.h,
+            l: self.l,
+            sp: self.sp,
+            pc: self.pc,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CaseResult {
+    pub name: String,
+    // one line per difference from the expected state, empty if the case passed
+    pub errors: Vec<String>,
+}
+
+impl CaseResult {
+    pub fn passed(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FileResult {
+    pub file: PathBuf,
+    pub cases: Vec<CaseResult>,
+}
+
+impl FileResult {
+    pub fn passed(&self) -> usize {
+        self.cases.iter().filter(|c| c.passed()).count()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.cases.len() - self.passed()
+    }
+}
+
+pub fn load(path: impl AsRef<Path>) -> Result<Vec<TestCase>, Box<dyn Error>> {
+    let json = fs::read_to_string(path.as_ref())?;
+    Ok(serde_json::from_str(&json)?)
+}
+
+pub fn run_case(case: &TestCase) -> CaseResult {
+    let res = panic::catch_unwind(AssertUnwindSafe(|| execute(case)));
+    let errors = match res {
+        Ok(errors) => errors,
+        Err(e) => {
+            let msg = e
+                .downcast_ref::<String>()
+                .cloned()
+                .or_else(|| e.downcast_ref::<&str>().map(|s| s.to_string()))
+                .unwrap_or_else(|| "panicked".to_string());
+            vec![format!("panicked: {}", msg)]
+        }
+    };
+    CaseResult {
+        name: case.name.clone(),
+        errors,
+    }
+}
+
+fn execute(case: &TestCase) -> Vec<String> {
+    let mut bus = FlatBus::new();
+    let mut cpu = CPU::new();
+
+    let initial = &case.initial;
+    cpu.set_state(initial.cpu_state());
+    cpu.set_ime(initial.ime.map_or(false, |ime| ime != 0));
+    if let Some(ie) = initial.ie {
+        bus.writeu8(0xffff.into(), ie);
+    }
+    for &(addr, value) in &initial.ram {
+        bus.writeu8(addr.into(), value);
+    }
+
+    let ticks = cpu.execute(&mut bus);
+
+    let expected = &case.expected;
+    let mut errors = vec![];
+    let actual = cpu.state();
+    let registers: [(&str, u16, u16); 10] = [
+        ("a", expected.a as u16, actual.a as u16),
+        ("f", expected.f as u16, actual.f as u16),
+        ("b", expected.b as u16, actual.b as u16),
+        ("c", expected.c as u16, actual.c as u16),
+        ("d", expected.d as u16, actual.d as u16),
+        ("e", expected.e as u16, actual.e as u16),
+        ("h", expected.h as u16, actual.h as u16),
+        ("l", expected.l as u16, actual.l as u16),
+        ("sp", expected.sp, actual.sp),
+        ("pc", expected.pc, actual.pc),
+    ];
+    for (name, expected, actual) in registers {
+        if expected != actual {
+            errors.push(format!("{}: expected {:#06x}, got {:#06x}", name, expected, actual));
+        }
+    }
+
+    if let Some(ime) = expected.ime {
+        if (ime != 0) != cpu.ime() {
+            errors.push(format!("ime: expected {}, got {}", ime, cpu.ime() as u8));
+        }
+    }
+    if let Some(ie) = expected.ie {
+        let actual = bus.readu8(0xffff.into());
+        if ie != actual {
+            errors.push(format!("ie: expected {:#04x}, got {:#04x}", ie, actual));
+        }
+    }
+    for &(addr, value) in &expected.ram {
+        let actual = bus.readu8(addr.into());
+        if value != actual {
+            errors.push(format!("[{:#06x}]: expected {:#04x}, got {:#04x}", addr, value, actual));
+        }
+    }
+
+    let expected_ticks = case.cycles.len() as u64 * 4;
+    if ticks != expected_ticks {
+        errors.push(format!("cycles: expected {}, got {}", expected_ticks, ticks));
+    }
+    errors
+}
+
+pub fn run_file(path: impl AsRef<Path>) -> Result<FileResult, Box<dyn Error>> {
+    let path = path.as_ref();
+    let cases = load(path)?;
+    Ok(FileResult {
+        file: path.to_owned(),
+        cases: cases.iter().map(run_case).collect(),
+    })
+}
+
+// Runs every .json file in `dir`, in alphabetical order.
+pub fn run_dir(dir: impl AsRef<Path>) -> Result<Vec<FileResult>, Box<dyn Error>> {
+    let mut files = vec![];
+    for entry in fs::read_dir(dir.as_ref())? {
+        let path = entry?.path();
+        if path.extension().map_or(false, |e| e == "json") {
+            files.push(path);
+        }
+    }
+    files.sort();
+    files.iter().map(run_file).collect()
+}
+
+pub fn summary(results: &[FileResult]) -> String {
+    let width = results
+        .iter()
+        .map(|r| r.file.display().to_string().len())
+        .max()
+        .unwrap_or(0)
+        .max("FILE".len());
+
+    let mut s = String::new();
+    writeln!(s, "{:<width$}  {:>8}  {:>8}", "FILE", "PASSED", "FAILED").unwrap();
+    writeln!(s, "{}", "=".repeat(width + 20)).unwrap();
+    for r in results {
+        writeln!(s, "{:<width$}  {:>8}  {:>8}", r.file.display(), r.passed(), r.failed()).unwrap();
+        for case in r.cases.iter().filter(|c| !c.passed()).take(MAX_REPORTED_FAILURES) {
+            writeln!(s, "    {}: {}", case.name, case.errors.join(", ")).unwrap();
+        }
+    }
+
+    let total: usize = results.iter().map(|r| r.cases.len()).sum();
+    let passed: usize = results.iter().map(|r| r.passed()).sum();
+    let files = results.iter().filter(|r| r.failed() == 0).count();
+    writeln!(s, "{}", "=".repeat(width + 20)).unwrap();
+    writeln!(s, "{}/{} cases passed, {}/{} files clean", passed, total, files, results.len()).unwrap();
+    s
+}
+
+pub fn print_summary(results: &[FileResult]) {
+    print!("{}", summary(results));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ADD A,B carrying out of both nibbles, and RLC (HL), which takes 4 M-cycles
+    const CASES: &str = r#"[
+        {
+            "name": "80 0000",
+            "initial": {"pc": 49152, "sp": 65534, "a": 58, "b": 198, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0, "ime": 0, "ie": 0,
+                        "ram": [[49152, 128]]},
+            "final": {"pc": 49153, "sp": 65534, "a": 0, "b": 198, "c": 0, "d": 0, "e": 0, "f": 176, "h": 0, "l": 0, "ime": 0, "ie": 0,
+                      "ram": [[49152, 128]]},
+            "cycles": [[49152, 128, "r-m"]]
+        },
+        {
+            "name": "cb 06 0000",
+            "initial": {"pc": 256, "sp": 65534, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 192, "l": 0, "ime": 0, "ie": 0,
+                        "ram": [[256, 203], [257, 6], [49152, 133]]},
+            "final": {"pc": 258, "sp": 65534, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 16, "h": 192, "l": 0, "ime": 0, "ie": 0,
+                      "ram": [[256, 203], [257, 6], [49152, 11]]},
+            "cycles": [[256, 203, "r-m"], [257, 6, "r-m"], [49152, 133, "r-m"], [49152, 11, "-wm"]]
+        }
+    ]"#;
+
+    fn cases() -> Vec<TestCase> {
+        serde_json::from_str(CASES).unwrap()
+    }
+
+    #[test]
+    fn passes() {
+        for case in cases() {
+            let res = run_case(&case);
+            assert!(res.passed(), "{}: {:?}", res.name, res.errors);
+        }
+    }
+
+    #[test]
+    fn reports_differences() {
+        let mut case = cases().remove(1);
+        case.expected.f = 0;
+        case.expected.ram[2].1 = 0x85;
+        case.cycles.pop();
+        let res = run_case(&case);
+        assert_eq!(
+            res.errors,
+            [
+                "f: expected 0x0000, got 0x0010",
+                "[0xc000]: expected 0x85, got 0x0b",
+                "cycles: expected 12, got 16",
+            ]
+        );
+    }
+}
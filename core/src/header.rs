@@ -0,0 +1,456 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::mmu::cartridge;
+
+// https://gbdev.io/pandocs/The_Cartridge_Header.html
+//
+// Header, 0x0100-0x014f of the ROM
+// ============================================================================
+// Address          Name                Usage notes
+// ============================================================================
+// 0x0100-0x0103    entry point         usually nop, jp 0x0150
+// 0x0104-0x0133    logo                checked by the boot ROM
+// 0x0134-0x0143    title               upper case ASCII, padded with 0s, shorter on newer cartridges:
+// 0x013f-0x0142    manufacturer code   4 characters, only on some newer cartridges
+// 0x0143           CGB flag            0x80: works on DMG too, 0xc0: CGB only
+// 0x0144-0x0145    new licensee code   2 characters, only used if the old licensee code is 0x33
+// 0x0146           SGB flag            0x03: uses SGB functions
+// 0x0147           cartridge type      MBC and what else is on the cartridge
+// 0x0148           ROM size            32 KiB << n
+// 0x0149           RAM size
+// 0x014a           destination code    0x00: Japan, 0x01: overseas
+// 0x014b           old licensee code
+// 0x014c           version
+// 0x014d           header checksum     checked by the boot ROM
+// 0x014e-0x014f    global checksum     big endian, not checked by anything
+
+pub(crate) const LOGO_ADDR: usize = 0x104;
+const TITLE_ADDR: usize = 0x134;
+const MANUFACTURER_ADDR: usize = 0x13f;
+const CGB_FLAG_ADDR: usize = 0x143;
+const NEW_LICENSEE_ADDR: usize = 0x144;
+const SGB_FLAG_ADDR: usize = 0x146;
+pub(crate) const MBC_MODE_ADDR: usize = 0x147;
+pub(crate) const ROM_SIZE_ADDR: usize = 0x148;
+pub(crate) const RAM_SIZE_ADDR: usize = 0x149;
+const DESTINATION_ADDR: usize = 0x14a;
+const OLD_LICENSEE_ADDR: usize = 0x14b;
+const VERSION_ADDR: usize = 0x14c;
+const HEADER_CHECKSUM_ADDR: usize = 0x14d;
+const GLOBAL_CHECKSUM_ADDR: usize = 0x14e;
+pub const HEADER_END: usize = 0x150;
+
+// the old licensee code that says to look at the new one instead
+const USE_NEW_LICENSEE: u8 = 0x33;
+
+// https://gbdev.io/pandocs/The_Cartridge_Header.html#0104-0133--nintendo-logo
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xce, 0xed, 0x66, 0x66, 0xcc, 0x0d, 0x00, 0x0b, 0x03, 0x73, 0x00, 0x83,
+    0x00, 0x0c, 0x00, 0x0d, 0x00, 0x08, 0x11, 0x1f, 0x88, 0x89, 0x00, 0x0e,
+    0xdc, 0xcc, 0x6e, 0xe6, 0xdd, 0xdd, 0xd9, 0x99, 0xbb, 0xbb, 0x67, 0x63,
+    0x6e, 0x0e, 0xec, 0xcc, 0xdd, 0xdc, 0x99, 0x9f, 0xbb, 0xb9, 0x33, 0x3e,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgbSupport {
+    None,
+    // enhanced for CGB, but still runs on DMG
+    Compatible,
+    Only,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mbc {
+    RomOnly,
+    MBC1,
+    MBC2,
+    MMM01,
+    MBC3,
+    MBC5,
+    MBC6,
+    MBC7,
+    PocketCamera,
+    TAMA5,
+    HuC3,
+    HuC1,
+    Unknown(u8),
+}
+
+impl Mbc {
+    // whether the emulator can run cartridges with it
+    pub fn is_supported(&self) -> bool {
+        cartridge::constructor(*self).is_some()
+    }
+}
+
+// https://gbdev.io/pandocs/The_Cartridge_Header.html#0147--cartridge-type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CartridgeType {
+    pub code: u8,
+    pub mbc: Mbc,
+    pub ram: bool,
+    pub battery: bool,
+    // real-time clock
+    pub timer: bool,
+    pub rumble: bool,
+    // MBC7's accelerometer
+    pub sensor: bool,
+}
+
+impl From<u8> for CartridgeType {
+    fn from(code: u8) -> Self {
+        let (mbc, ram, battery, timer, rumble, sensor) = match code {
+            0x00 => (Mbc::RomOnly,      false, false, false, false, false),
+            0x01 => (Mbc::MBC1,         false, false, false, false, false),
+            0x02 => (Mbc::MBC1,         true,  false, false, false, false),
+            0x03 => (Mbc::MBC1,         true,  true,  false, false, false),
+            0x05 => (Mbc::MBC2,         false, false, false, false, false),
+            0x06 => (Mbc::MBC2,         false, true,  false, false, false),
+            0x08 => (Mbc::RomOnly,      true,  false, false, false, false),
+            0x09 => (Mbc::RomOnly,      true,  true,  false, false, false),
+            0x0b => (Mbc::MMM01,        false, false, false, false, false),
+            0x0c => (Mbc::MMM01,        true,  false, false, false, false),
+            0x0d => (Mbc::MMM01,        true,  true,  false, false, false),
+            0x0f => (Mbc::MBC3,         false, true,  true,  false, false),
+            0x10 => (Mbc::MBC3,         true,  true,  true,  false, false),
+            0x11 => (Mbc::MBC3,         false, false, false, false, false),
+            0x12 => (Mbc::MBC3,         true,  false, false, false, false),
+            0x13 => (Mbc::MBC3,         true,  true,  false, false, false),
+            0x19 => (Mbc::MBC5,         false, false, false, false, false),
+            0x1a => (Mbc::MBC5,         true,  false, false, false, false),
+            0x1b => (Mbc::MBC5,         true,  true,  false, false, false),
+            0x1c => (Mbc::MBC5,         false, false, false, true,  false),
+            0x1d => (Mbc::MBC5,         true,  false, false, true,  false),
+            0x1e => (Mbc::MBC5,         true,  true,  false, true,  false),
+            0x20 => (Mbc::MBC6,         false, false, false, false, false),
+            0x22 => (Mbc::MBC7,         true,  true,  false, true,  true),
+            0xfc => (Mbc::PocketCamera, true,  true,  false, false, false),
+            0xfd => (Mbc::TAMA5,        false, false, false, false, false),
+            0xfe => (Mbc::HuC3,         true,  true,  true,  false, false),
+            0xff => (Mbc::HuC1,         true,  true,  false, false, false),
+            _    => (Mbc::Unknown(code), false, false, false, false, false),
+        };
+        Self { code, mbc, ram, battery, timer, rumble, sensor }
+    }
+}
+
+impl fmt::Display for CartridgeType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.mbc {
+            Mbc::RomOnly    => write!(f, "ROM")?,
+            Mbc::Unknown(_) => write!(f, "unknown")?,
+            mbc             => write!(f, "{:?}", mbc)?,
+        }
+        for (has, name) in [
+            (self.timer, "TIMER"),
+            (self.rumble, "RUMBLE"),
+            (self.sensor, "SENSOR"),
+            (self.ram, "RAM"),
+            (self.battery, "BATTERY"),
+        ] {
+            if has {
+                write!(f, "+{}", name)?;
+            }
+        }
+        write!(f, " ({:#04x})", self.code)
+    }
+}
+
+// ROM size in bytes from the code at 0x148, None for codes that aren't known
+pub(crate) fn rom_size(code: u8) -> Option<usize> {
+    // not then_some, the shift would overflow for the larger codes before they're checked
+    if code <= 8 { Some(0x8000 << code) } else { None }
+}
+
+// RAM size in bytes from the code at 0x149, 0x01 was never used by a licensed cartridge
+pub(crate) fn ram_size(code: u8) -> usize {
+    match code {
+        0x02 => 0x2000,
+        0x03 => 0x8000,
+        0x04 => 0x20000,
+        0x05 => 0x10000,
+        _    => 0,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeaderWarning {
+    // the boot ROM would lock up
+    LogoMismatch,
+    // the boot ROM would lock up
+    HeaderChecksum { expected: u8, actual: u8 },
+    GlobalChecksum { expected: u16, actual: u16 },
+    UnknownRomSize(u8),
+    RomSize { header: usize, file: usize },
+}
+
+impl fmt::Display for HeaderWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::LogoMismatch => write!(f, "the Nintendo logo doesn't match"),
+            Self::HeaderChecksum { expected, actual } => {
+                write!(f, "header checksum is {:#04x}, but the header adds up to {:#04x}", expected, actual)
+            }
+            Self::GlobalChecksum { expected, actual } => {
+                write!(f, "global checksum is {:#06x}, but the ROM adds up to {:#06x}", expected, actual)
+            }
+            Self::UnknownRomSize(code) => write!(f, "unknown ROM size code {:#04x}", code),
+            Self::RomSize { header, file } => {
+                write!(f, "the header says the ROM is {} bytes, but the file is {} bytes", header, file)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CartridgeHeader {
+    pub logo: [u8; 48],
+    pub title: String,
+    pub manufacturer: Option<String>,
+    pub cgb: CgbSupport,
+    pub new_licensee: Option<String>,
+    pub sgb: bool,
+    pub cartridge_type: CartridgeType,
+    pub rom_size_code: u8,
+    pub ram_size_code: u8,
+    pub japanese: bool,
+    pub old_licensee: u8,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+
+    // what the checksums should have been, and the size of the ROM they were computed from
+    computed_header_checksum: u8,
+    computed_global_checksum: u16,
+    file_size: usize,
+}
+
+impl CartridgeHeader {
+    // The header the emulator goes by, which on MMM01 multicarts is the menu's rather than the one
+    // at 0x100 (see `parse`).
+    pub fn from_rom(rom: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let mut header = Self::parse(&rom[cartridge::header_offset(rom)..])?;
+        // the size in the menu's header is the whole multicart's
+        header.file_size = rom.len();
+        Ok(header)
+    }
+
+    // the header at 0x100
+    pub fn parse(rom: &[u8]) -> Result<Self, Box<dyn Error>> {
+        if rom.len() < HEADER_END {
+            return Err(format!("ROM is {} bytes, too short for a header", rom.len()).into());
+        }
+
+        let cgb = match rom[CGB_FLAG_ADDR] {
+            0x80 => CgbSupport::Compatible,
+            0xc0 => CgbSupport::Only,
+            _    => CgbSupport::None,
+        };
+        // Newer cartridges cut the title short for the manufacturer code (and the CGB flag),
+        // there's no flag for it, but the code is always 4 upper case letters or digits.
+        let code = &rom[MANUFACTURER_ADDR..CGB_FLAG_ADDR];
+        let has_manufacturer = cgb != CgbSupport::None
+            && code.iter().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit());
+        let title_end = match (has_manufacturer, cgb) {
+            (true, _)                 => MANUFACTURER_ADDR,
+            (false, CgbSupport::None) => NEW_LICENSEE_ADDR,
+            (false, _)                => CGB_FLAG_ADDR,
+        };
+
+        let old_licensee = rom[OLD_LICENSEE_ADDR];
+        Ok(Self {
+            logo: rom[LOGO_ADDR..LOGO_ADDR + NINTENDO_LOGO.len()].try_into().unwrap(),
+            title: ascii(&rom[TITLE_ADDR..title_end]),
+            manufacturer: has_manufacturer.then(|| ascii(code)),
+            cgb,
+            new_licensee: (old_licensee == USE_NEW_LICENSEE).then(|| ascii(&rom[NEW_LICENSEE_ADDR..SGB_FLAG_ADDR])),
+            sgb: rom[SGB_FLAG_ADDR] == 0x03,
+            cartridge_type: rom[MBC_MODE_ADDR].into(),
+            rom_size_code: rom[ROM_SIZE_ADDR],
+            ram_size_code: rom[RAM_SIZE_ADDR],
+            japanese: rom[DESTINATION_ADDR] == 0x00,
+            old_licensee,
+            version: rom[VERSION_ADDR],
+            header_checksum: rom[HEADER_CHECKSUM_ADDR],
+            global_checksum: u16::from_be_bytes([rom[GLOBAL_CHECKSUM_ADDR], rom[GLOBAL_CHECKSUM_ADDR + 1]]),
+
+            computed_header_checksum: header_checksum(rom),
+            computed_global_checksum: global_checksum(rom),
+            file_size: rom.len(),
+        })
+    }
+
+    // in bytes, None if the size code isn't known
+    pub fn rom_size(&self) -> Option<usize> {
+        rom_size(self.rom_size_code)
+    }
+
+    // in bytes, MBC2 and MBC7 have RAM (or an EEPROM) built in, which isn't counted here
+    pub fn ram_size(&self) -> usize {
+        ram_size(self.ram_size_code)
+    }
+
+    // the licensee code as it's usually written, 2 hex digits or 2 characters
+    pub fn licensee(&self) -> String {
+        match self.new_licensee.as_ref() {
+            Some(code) => code.clone(),
+            None       => format!("{:02X}", self.old_licensee),
+        }
+    }
+
+    pub fn validate(&self) -> Vec<HeaderWarning> {
+        let mut warnings = vec![];
+        if self.logo != NINTENDO_LOGO {
+            warnings.push(HeaderWarning::LogoMismatch);
+        }
+        if self.header_checksum != self.computed_header_checksum {
+            warnings.push(HeaderWarning::HeaderChecksum {
+                expected: self.header_checksum,
+                actual: self.computed_header_checksum,
+            });
+        }
+        if self.global_checksum != self.computed_global_checksum {
+            warnings.push(HeaderWarning::GlobalChecksum {
+                expected: self.global_checksum,
+                actual: self.computed_global_checksum,
+            });
+        }
+        match self.rom_size() {
+            Some(size) if size != self.file_size => {
+                warnings.push(HeaderWarning::RomSize { header: size, file: self.file_size });
+            }
+            Some(_) => {}
+            None    => warnings.push(HeaderWarning::UnknownRomSize(self.rom_size_code)),
+        }
+        warnings
+    }
+}
+
+// header bytes up to the first 0, anything that isn't printable ASCII shows up as '?'
+fn ascii(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .take_while(|&&c| c != 0)
+        .map(|&c| if c.is_ascii_graphic() || c == b' ' { c as char } else { '?' })
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
+
+// https://gbdev.io/pandocs/The_Cartridge_Header.html#014d--header-checksum
+fn header_checksum(rom: &[u8]) -> u8 {
+    rom[TITLE_ADDR..HEADER_CHECKSUM_ADDR]
+        .iter()
+        .fold(0u8, |x, &v| x.wrapping_sub(v).wrapping_sub(1))
+}
+
+// https://gbdev.io/pandocs/The_Cartridge_Header.html#014e-014f--global-checksum
+fn global_checksum(rom: &[u8]) -> u16 {
+    rom.iter()
+        .enumerate()
+        .filter(|&(i, _)| i != GLOBAL_CHECKSUM_ADDR && i != GLOBAL_CHECKSUM_ADDR + 1)
+        .fold(0u16, |sum, (_, &v)| sum.wrapping_add(v as u16))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(rom: &mut [u8], offset: usize, cartridge_type: u8) {
+        rom[offset + LOGO_ADDR..][..NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+        rom[offset + MBC_MODE_ADDR] = cartridge_type;
+    }
+
+    #[test]
+    fn mmm01_menu_header() {
+        // a game with MBC1 at the start, and the menu (MMM01+RAM+BATTERY) in the last 32 KiB
+        let mut rom = vec![0; 0x80000];
+        header(&mut rom, 0, 0x01);
+        header(&mut rom, 0x40000, 0x01);
+        header(&mut rom, 0x78000, 0x0d);
+
+        assert_eq!(CartridgeHeader::parse(&rom).unwrap().cartridge_type.mbc, Mbc::MBC1);
+        let menu = CartridgeHeader::from_rom(&rom).unwrap();
+        assert_eq!(menu.cartridge_type.mbc, Mbc::MMM01);
+        assert!(menu.cartridge_type.battery);
+        assert_eq!(menu.file_size, rom.len());
+    }
+
+    // a 32 KiB ROM with a clean header
+    fn valid_rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        header(&mut rom, 0, 0x00);
+        rom[TITLE_ADDR..][..4].copy_from_slice(b"TEST");
+        rom[0x4000] = 0x42;
+        rom[HEADER_CHECKSUM_ADDR] = header_checksum(&rom);
+        global(&mut rom);
+        rom
+    }
+
+    fn global(rom: &mut [u8]) {
+        let sum = global_checksum(rom);
+        rom[GLOBAL_CHECKSUM_ADDR..][..2].copy_from_slice(&sum.to_be_bytes());
+    }
+
+    fn warnings(rom: &[u8]) -> Vec<HeaderWarning> {
+        CartridgeHeader::parse(rom).unwrap().validate()
+    }
+
+    #[test]
+    fn clean_header() {
+        assert_eq!(warnings(&valid_rom()), []);
+    }
+
+    #[test]
+    fn logo_mismatch() {
+        let mut rom = valid_rom();
+        // the logo isn't in the header checksum
+        rom[LOGO_ADDR] ^= 0xff;
+        global(&mut rom);
+        assert_eq!(warnings(&rom), [HeaderWarning::LogoMismatch]);
+    }
+
+    #[test]
+    fn header_checksum_mismatch() {
+        let mut rom = valid_rom();
+        let expected = rom[HEADER_CHECKSUM_ADDR];
+        rom[VERSION_ADDR] = 1;
+        global(&mut rom);
+        let actual = expected.wrapping_sub(1);
+        assert_eq!(warnings(&rom), [HeaderWarning::HeaderChecksum { expected, actual }]);
+    }
+
+    #[test]
+    fn global_checksum_mismatch() {
+        let mut rom = valid_rom();
+        let expected = u16::from_be_bytes([rom[GLOBAL_CHECKSUM_ADDR], rom[GLOBAL_CHECKSUM_ADDR + 1]]);
+        rom[0x4000] = 0x43;
+        let actual = expected + 1;
+        assert_eq!(warnings(&rom), [HeaderWarning::GlobalChecksum { expected, actual }]);
+    }
+
+    #[test]
+    fn rom_size_mismatch() {
+        // the header says 64 KiB
+        let mut rom = valid_rom();
+        rom[ROM_SIZE_ADDR] = 0x01;
+        rom[HEADER_CHECKSUM_ADDR] = header_checksum(&rom);
+        global(&mut rom);
+        assert_eq!(warnings(&rom), [HeaderWarning::RomSize { header: 0x10000, file: 0x8000 }]);
+
+        rom[ROM_SIZE_ADDR] = 0x42;
+        rom[HEADER_CHECKSUM_ADDR] = header_checksum(&rom);
+        global(&mut rom);
+        assert_eq!(warnings(&rom), [HeaderWarning::UnknownRomSize(0x42)]);
+    }
+
+    #[test]
+    fn supported() {
+        assert!(Mbc::MBC5.is_supported());
+        assert!(Mbc::MMM01.is_supported());
+        assert!(!Mbc::MBC6.is_supported());
+        assert!(!Mbc::TAMA5.is_supported());
+        assert!(!Mbc::Unknown(0x42).is_supported());
+    }
+}
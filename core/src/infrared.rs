@@ -0,0 +1,42 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// The infrared port of cartridges with an IR LED and sensor (HuC1, HuC3).
+pub trait InfraredDevice {
+    // the cartridge turned its LED on or off
+    fn set_led(&mut self, on: bool);
+
+    // whether the cartridge's sensor currently sees light
+    fn light(&self) -> bool;
+}
+
+// Two cartridges facing each other, each one sees the other's LED.
+// Both ends can be on different threads.
+#[derive(Debug, Clone)]
+pub struct InfraredLink {
+    leds: Arc<[AtomicBool; 2]>,
+    side: usize,
+}
+
+impl InfraredLink {
+    pub fn pair() -> (Self, Self) {
+        let leds = Arc::new([AtomicBool::new(false), AtomicBool::new(false)]);
+        (
+            Self {
+                leds: leds.clone(),
+                side: 0,
+            },
+            Self { leds, side: 1 },
+        )
+    }
+}
+
+impl InfraredDevice for InfraredLink {
+    fn set_led(&mut self, on: bool) {
+        self.leds[self.side].store(on, Ordering::Relaxed);
+    }
+
+    fn light(&self) -> bool {
+        self.leds[1 - self.side].load(Ordering::Relaxed)
+    }
+}
@@ -2,19 +2,49 @@
 #![feature(exclusive_range_pattern)]
 #![feature(let_chains)]
 
+pub mod apu;
+pub mod archive;
+pub mod camera;
+pub mod clock;
 mod cpu;
+pub mod harness;
+pub mod header;
+pub mod infrared;
 pub mod joypad;
+pub mod mapper;
 mod mmu;
 pub mod ppu;
+pub mod serial;
 mod timer;
 mod util;
 
-use crate::{cpu::CPU, mmu::MMU};
-use std::{fs, io::Read, path::Path};
+use crate::{
+    apu::AudioSink, camera::ImageSource, clock::TimeSource, cpu::CPU, header::{CartridgeHeader, HEADER_END}, infrared::InfraredDevice,
+    mapper::Mapper, mmu::MMU, ppu::{Renderer, Screen}, serial::SerialDevice,
+};
+use std::{
+    error::Error,
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+pub use cpu::disasm::{disassemble, Instruction};
+pub use cpu::CpuState;
+
+// battery backed RAM is written back this long (in cpu ticks) after the game changes it, one second
+const SAVE_DELAY_TICKS: u64 = 4 * 1024 * 1024;
 
 pub struct Machine {
     cpu: CPU,
     pub mmu: MMU,
+    header: CartridgeHeader,
+
+    // where the battery backed RAM is kept, a .sav next to the ROM by default
+    save_path: Option<PathBuf>,
+    save_ticks: u64,
+    // why the last automatic save failed, cleared when one succeeds
+    save_error: Option<io::Error>,
 }
 
 fn file_helper(file_path: impl AsRef<Path>) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
@@ -27,32 +57,68 @@ fn file_helper(file_path: impl AsRef<Path>) -> Result<Vec<u8>, Box<dyn std::erro
 }
 
 impl Machine {
+    // the ROM can be a raw file, gzipped, or the first .gb/.gbc file in a zip
     pub fn new(
         cartridge: impl AsRef<Path>,
         bootrom: Option<impl AsRef<Path>>,
     ) -> Result<Self, Box<dyn std::error::Error>> {
-        let buf = file_helper(cartridge)?;
+        Self::with_entry(cartridge, None, bootrom)
+    }
+
+    // like `new`, picking the file called `entry` out of a zip
+    pub fn with_entry(
+        cartridge: impl AsRef<Path>,
+        entry: Option<&str>,
+        bootrom: Option<impl AsRef<Path>>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let save_path = archive::save_path(&cartridge);
+        let buf = archive::load(cartridge, entry)?;
 
         let bootrom = bootrom.map(|path| file_helper(path)).transpose()?;
         let bp = bootrom.is_some();
-        /*
-        let logo: [u8; 48] =
-            [0xce,0xed,0x66,0x66,0xcc,0x0d,0x00,0x0b,0x03,0x73,0x00,0x83,
-             0x00,0x0c,0x00,0x0d,0x00,0x08,0x11,0x1f,0x88,0x89,0x00,0x0e,
-             0xdc,0xcc,0x6e,0xe6,0xdd,0xdd,0xd9,0x99, 0xbb,0xbb,0x67,0x63,
-             0x6e,0x0e,0xec,0xcc,0xdd,0xdc,0x99,0x9f,0xbb,0xb9,0x33,0x3e];
-        logo.into_iter().enumerate().for_each(|(i, v)| {
-            buf[0x104 + i] = v;
-        });
-        let checksum = [0x42,0x47,0x42,0x57,0x45,0x4C,0x43,0x4F,0x4D, 0x45,0x10,00,00,00,00,00,00,00,00,00,00,00,00,00,00];
-        checksum.into_iter().enumerate().for_each(|(i, v)| {
-            buf[0x134 + i] = v;
-        });
-        */
+        let header = CartridgeHeader::from_rom(&buf)?;
 
         let mut m = Self {
             cpu: CPU::new(),
             mmu: MMU::new(bootrom, buf),
+            header,
+            save_path: None,
+            save_ticks: 0,
+            save_error: None,
+        };
+
+        if !bp {
+            m.cpu.no_boot(&mut m.mmu);
+        }
+
+        if m.mmu.battery {
+            if save_path.exists() {
+                m.load_save_ram(&fs::read(&save_path)?)?;
+            }
+            m.save_path = Some(save_path);
+        }
+
+        Ok(m)
+    }
+
+    // Runs a cartridge through a mapper that isn't built in. The header is read through the mapper,
+    // and nothing is saved automatically, see `set_save_path`.
+    pub fn with_mapper(
+        mapper: Box<dyn Mapper>,
+        bootrom: Option<impl AsRef<Path>>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let bootrom = bootrom.map(|path| file_helper(path)).transpose()?;
+        let bp = bootrom.is_some();
+        let bank0: Vec<u8> = (0..HEADER_END as u16).map(|addr| mapper.read_rom(addr)).collect();
+        let header = CartridgeHeader::parse(&bank0)?;
+
+        let mut m = Self {
+            cpu: CPU::new(),
+            mmu: MMU::with_mapper(bootrom, mapper, header.cartridge_type.battery),
+            header,
+            save_path: None,
+            save_ticks: 0,
+            save_error: None,
         };
 
         if !bp {
@@ -62,9 +128,177 @@ impl Machine {
         Ok(m)
     }
 
-    pub fn step(&mut self) {
+    // the fast scanline renderer by default, it can be switched any time
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.mmu.ppu.renderer = renderer;
+    }
+
+    pub fn renderer(&self) -> Renderer {
+        self.mmu.ppu.renderer
+    }
+
+    // audio is resampled to the rate of the sink, and handed over once every frame
+    pub fn set_audio_sink(&mut self, sink: Box<dyn AudioSink>) -> Box<dyn AudioSink> {
+        self.mmu.apu.set_sink(sink)
+    }
+
+    // plugs a peer into the link port, and returns the one that was there before
+    pub fn attach_serial(&mut self, device: Box<dyn SerialDevice>) -> Option<Box<dyn SerialDevice>> {
+        self.mmu.serial.attach(device)
+    }
+
+    pub fn detach_serial(&mut self) -> Option<Box<dyn SerialDevice>> {
+        self.mmu.serial.detach()
+    }
+
+    // CGB mode, off by default. So far it only gives the serial port its fast clock (SC bit 1),
+    // the rest of the machine stays a DMG.
+    pub fn set_cgb_mode(&mut self, cgb: bool) {
+        self.mmu.serial.set_cgb(cgb);
+    }
+
+    // points the cartridge's IR port (HuC1, HuC3) at a device, and returns the one that was there before
+    pub fn attach_infrared(&mut self, device: Box<dyn InfraredDevice>) -> Option<Box<dyn InfraredDevice>> {
+        self.mmu.attach_infrared(device)
+    }
+
+    pub fn detach_infrared(&mut self) -> Option<Box<dyn InfraredDevice>> {
+        self.mmu.detach_infrared()
+    }
+
+    // where the cartridge's real-time clock (if it has one) reads the time from, the wall clock by default
+    pub fn set_time_source(&mut self, source: Box<dyn TimeSource>) {
+        self.mmu.set_time_source(source);
+    }
+
+    // true if the cartridge keeps its RAM when switched off
+    pub fn has_battery(&self) -> bool {
+        self.mmu.battery
+    }
+
+    // the battery backed RAM (and RTC) in the .sav layout, None if the cartridge has no battery
+    pub fn save_ram(&self) -> Option<Vec<u8>> {
+        self.mmu.battery.then(|| self.mmu.save_data())
+    }
+
+    pub fn load_save_ram(&mut self, data: &[u8]) -> Result<(), Box<dyn Error>> {
+        if !self.mmu.battery {
+            return Err("cartridge has no battery backed RAM".into());
+        }
+        self.mmu.load_save_data(data);
+        Ok(())
+    }
+
+    // The mapper's registers and RAM, None if it can't save its state (none of the built-in MBCs
+    // can yet). Only the cartridge, the rest of the machine isn't in it.
+    pub fn mapper_state(&self) -> Option<Vec<u8>> {
+        self.mmu.mapper_state()
+    }
+
+    pub fn load_mapper_state(&mut self, state: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.mmu.load_mapper_state(state)
+    }
+
+    // where the RAM is saved automatically, None for frontends that store saves themselves (through `save_ram`)
+    pub fn set_save_path(&mut self, path: Option<PathBuf>) {
+        self.save_path = path;
+    }
+
+    // Writes the RAM to the save path, if it changed since the last time. When that fails the RAM
+    // stays dirty, and the automatic save tries again a second later.
+    pub fn flush_save(&mut self) -> io::Result<()> {
+        if !self.mmu.ram_dirty {
+            return Ok(());
+        }
+        self.save_ticks = 0;
+        if let Some(path) = self.save_path.as_ref() {
+            fs::write(path, self.mmu.save_data())?;
+        }
+        self.mmu.ram_dirty = false;
+        Ok(())
+    }
+
+    // why the last automatic save (from `step`) failed, None once one succeeds
+    pub fn last_save_error(&self) -> Option<&io::Error> {
+        self.save_error.as_ref()
+    }
+
+    // Tilt of the cartridge for MBC7's accelerometer, in g (-1.0..=1.0 for 90 degrees).
+    // x is positive when tilted to the right, y when tilted towards the bottom of the screen.
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.mmu.set_tilt(x, y);
+    }
+
+    // called with true when a rumble cartridge starts its motor, and false when it stops it
+    pub fn set_rumble_callback(&mut self, callback: impl FnMut(bool) + 'static) {
+        self.mmu.set_rumble_callback(Box::new(callback));
+    }
+
+    // what the Pocket Camera sees, None covers the lens
+    pub fn set_image_source(&mut self, source: Option<Box<dyn ImageSource>>) {
+        self.mmu.set_image_source(source);
+    }
+
+    // called with the tone number whenever a HuC3 cartridge plays a sound through its speaker
+    pub fn set_tone_callback(&mut self, callback: impl FnMut(u8) + 'static) {
+        self.mmu.set_tone_callback(Box::new(callback));
+    }
+
+    pub fn header(&self) -> &CartridgeHeader {
+        &self.header
+    }
+
+    pub fn cpu_state(&self) -> CpuState {
+        self.cpu.state()
+    }
+
+    // true if an LD B,B was executed since the last call
+    pub fn take_breakpoint(&mut self) -> bool {
+        std::mem::take(&mut self.cpu.breakpoint)
+    }
+
+    pub fn screen(&self) -> &Screen {
+        &self.mmu.ppu.screen
+    }
+
+    // number of frames the PPU has completed so far
+    pub fn frames(&self) -> u64 {
+        self.mmu.ppu.frames
+    }
+
+    pub fn run_frames(&mut self, n: u64) -> &Screen {
+        let target = self.frames() + n;
+        while self.frames() < target {
+            self.step();
+        }
+        self.screen()
+    }
+
+    // runs until the first LD B,B, which is how most test ROMs say they're done drawing
+    pub fn run_until_breakpoint(&mut self, max_cycles: u64) -> Option<&Screen> {
+        self.take_breakpoint();
+        let mut cycles = 0;
+        while cycles < max_cycles {
+            cycles += self.step();
+            if self.take_breakpoint() {
+                return Some(self.screen());
+            }
+        }
+        None
+    }
+
+    // returns the number of cpu ticks the step took
+    pub fn step(&mut self) -> u64 {
         let cpu_ticks = self.cpu.step(&mut self.mmu);
-        self.mmu.tick(cpu_ticks);
+        self.mmu.finish_instruction(cpu_ticks);
+
+        if self.mmu.ram_dirty {
+            self.save_ticks += cpu_ticks;
+            if self.save_ticks >= SAVE_DELAY_TICKS {
+                self.save_error = self.flush_save().err();
+            }
+        }
+        cpu_ticks
     }
 
     pub fn run(&mut self) {
@@ -76,3 +310,10 @@ impl Machine {
         }
     }
 }
+
+// frontends that want to know whether the last save worked call `flush_save` themselves first
+impl Drop for Machine {
+    fn drop(&mut self) {
+        let _ = self.flush_save();
+    }
+}
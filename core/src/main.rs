@@ -12,6 +12,9 @@ fn main() {
     // let cartridge = "/Users/ggd/projects/gb/roms/dmg-acid2.gb";
     // let cartridge = "/Users/ggd/projects/gb/gb-test-roms/cpu_instrs/individual/02-interrupts.gb";
     let mut m = Machine::new(cartridge, bootrom).unwrap();
+    for warning in m.header().validate() {
+        eprintln!("warning: {}", warning);
+    }
 
     let mut window = Window::new(
         "Test - ESC to exit",
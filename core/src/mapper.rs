@@ -0,0 +1,46 @@
+// The cartridge side of the bus, for plugging in hardware the emulator doesn't know about
+// (flash carts, homebrew mappers, ...). The built-in MBCs implement it too.
+//
+// ROM accesses get 0x0000-0x7fff, where writes are the mapper's registers,
+// and RAM accesses get 0xa000-0xbfff. Addresses are passed as they are on the bus.
+
+use std::error::Error;
+
+pub trait Mapper {
+    fn read_rom(&self, addr: u16) -> u8;
+
+    // bank switching and the other registers
+    fn write_rom(&mut self, addr: u16, value: u8);
+
+    // open bus (0xff) when there's no RAM, or it's disabled
+    fn read_ram(&self, _addr: u16) -> u8 {
+        0xff
+    }
+
+    fn write_ram(&mut self, _addr: u16, _value: u8) {}
+
+    // what's kept in the .sav file, when the header says the cartridge has a battery
+    fn save_data(&self) -> Vec<u8> {
+        vec![]
+    }
+
+    fn load_save_data(&mut self, _data: &[u8]) {}
+
+    // called with the cpu ticks that passed, for mappers that keep time or run something in the background
+    fn tick(&mut self, _cpu_ticks: u64) {}
+
+    // the whole state of the mapper (registers and RAM), None if it can't be saved, see Machine::mapper_state
+    fn save_state(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn load_state(&mut self, _state: &[u8]) -> Result<(), Box<dyn Error>> {
+        Err("mapper doesn't support save states".into())
+    }
+}
+
+impl std::fmt::Debug for dyn Mapper {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Mapper")
+    }
+}
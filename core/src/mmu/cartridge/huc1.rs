@@ -0,0 +1,221 @@
+use std::fmt::Debug;
+
+use crate::infrared::InfraredDevice;
+use crate::header::RAM_SIZE_ADDR;
+use crate::mmu::busio::{BusIO, SResult};
+use crate::util::Addr;
+
+use super::rom_bank::{RomBank, ROM_BANK_SIZE};
+use super::ram_bank::RamBank;
+
+// https://gbdev.io/pandocs/HuC1.html
+//
+// Registers (write only)
+// ============================================================================
+// Address          Name            Usage notes
+// ============================================================================
+// 0x0000-0x1fff    RAM/IR select   0x0e maps the IR port to 0xa000-0xbfff, anything else the RAM
+// 0x2000-0x3fff    ROM bank        6 bits
+// 0x4000-0x5fff    RAM bank        2 bits
+//
+// IR port
+// ============================================================================
+// read     0xc0 | 1 if the sensor sees light
+// write    bit 0 turns the LED on
+
+const IR_MODE: u8 = 0x0e;
+
+// LED and sensor, shared with HuC3
+#[derive(Default)]
+pub(super) struct IrPort {
+    led: bool,
+    device: Option<Box<dyn InfraredDevice>>,
+}
+
+impl Debug for IrPort {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IrPort")
+            .field("led", &self.led)
+            .field("device", &self.device.is_some())
+            .finish()
+    }
+}
+
+impl IrPort {
+    pub(super) fn attach(&mut self, mut device: Box<dyn InfraredDevice>) -> Option<Box<dyn InfraredDevice>> {
+        device.set_led(self.led);
+        self.device.replace(device)
+    }
+
+    pub(super) fn detach(&mut self) -> Option<Box<dyn InfraredDevice>> {
+        self.device.take()
+    }
+
+    // with nothing attached, it's dark
+    pub(super) fn light(&self) -> bool {
+        self.device.as_ref().map_or(false, |d| d.light())
+    }
+
+    pub(super) fn set_led(&mut self, on: bool) {
+        if on != self.led {
+            self.led = on;
+            if let Some(device) = self.device.as_mut() {
+                device.set_led(on);
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct HuC1 {
+    roms: Vec<RomBank>,
+    rams: Vec<RamBank>,
+    pub(super) ir: IrPort,
+
+    cur_rom: u8,
+    cur_ram: u8,
+    ir_mode: bool,
+}
+
+impl HuC1 {
+    pub(crate) fn new(raw: Vec<u8>, _mbc_mode: u8) -> Self {
+        let num_rams = RamBank::count(raw[RAM_SIZE_ADDR]);
+
+        Self {
+            roms: RomBank::split(&raw),
+            rams: vec![RamBank::new(); num_rams],
+            ir: Default::default(),
+
+            cur_rom: 1,
+            cur_ram: 0,
+            ir_mode: false,
+        }
+    }
+
+    pub(crate) fn save_data(&self) -> Vec<u8> {
+        RamBank::dump(&self.rams)
+    }
+
+    pub(crate) fn load_save_data(&mut self, data: &[u8]) {
+        RamBank::restore(&mut self.rams, data);
+    }
+
+    fn rom_index(&self, addr: u16) -> usize {
+        let index = match addr {
+            0x0000..0x4000 => 0,
+            _              => self.cur_rom as usize,
+        };
+        index % self.roms.len()
+    }
+
+    fn ram_index(&self) -> Option<usize> {
+        if self.ir_mode || self.rams.is_empty() {
+            return None;
+        }
+        Some(self.cur_ram as usize % self.rams.len())
+    }
+}
+
+impl BusIO for HuC1 {
+    fn readu8(&self, addr: Addr) -> SResult<u8> {
+        match addr.into() {
+            a @ 0x0000..0x8000 => self.roms[self.rom_index(a)].readu8(addr),
+            0xa000..0xc000 if self.ir_mode => Ok(0xc0 | self.ir.light() as u8),
+            0xa000..0xc000 => match self.ram_index() {
+                Some(index) => self.rams[index].readu8(addr),
+                None        => Ok(0xff),
+            },
+            _              => Err(format!("HuC1 readu8 - invalid addr: {:x?}", addr).into())
+        }
+    }
+
+    fn writeu8(&mut self, addr: Addr, value: u8) -> SResult<()> {
+        match addr.into() {
+            0x0000..0x2000 => self.ir_mode = value & 0x0f == IR_MODE,
+            0x2000..0x4000 => self.cur_rom = value & 0b0011_1111,
+            0x4000..0x6000 => self.cur_ram = value & 0b11,
+            0x6000..0x8000 => {}
+            0xa000..0xc000 if self.ir_mode => self.ir.set_led(value & 1 == 1),
+            0xa000..0xc000 => {
+                if let Some(index) = self.ram_index() {
+                    self.rams[index].writeu8(addr, value)?;
+                }
+            }
+            _              => return Err(format!("HuC1 writeu8 - invalid addr: {:x?}", addr).into())
+        };
+        Ok(())
+    }
+
+    fn readu16(&self, addr: Addr) -> SResult<u16> {
+        Ok(u16::from_le_bytes([
+            self.readu8(addr)?,
+            self.readu8(addr + 1.into())?
+        ]))
+    }
+
+    fn writeu16(&mut self, addr: Addr, value: u16) -> SResult<()> {
+        if addr > 0x0000.into() && addr < 0x8000.into() {
+            panic!("HuC1 writeu16 @ {:x?}", addr);
+        }
+        let value = value.to_le_bytes();
+        self.writeu8(addr, value[0])?;
+        self.writeu8(addr + 1.into(), value[1])?;
+        Ok(())
+    }
+
+    // used by OAM DMA
+    fn as_slice(&self, addr: Addr, len: usize) -> SResult<&[u8]> {
+        match addr.into() {
+            a @ 0x0000..0x8000 => {
+                let offset = a as usize % ROM_BANK_SIZE;
+                Ok(&self.roms[self.rom_index(a)].0[offset..][..len])
+            }
+            0xa000..0xc000 => match self.ram_index() {
+                Some(index) => self.rams[index].as_slice(addr, len),
+                None        => Err(format!("HuC1 as_slice - RAM not mapped: {:x?}", addr).into()),
+            },
+            _              => Err(format!("HuC1 as_slice - invalid addr: {:x?}", addr).into())
+        }
+    }
+
+    fn print_dbg(&self, _start: Addr, _len: u16) -> String {
+        unimplemented!()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mmu::cartridge::test_rom;
+
+    const HUC1: u8 = 0xff;
+
+    #[test]
+    fn banks() {
+        let mut huc1 = HuC1::new(test_rom(128, HUC1, 0x03), HUC1);
+        // 6 bits of ROM bank
+        huc1.writeu8(0x2000.into(), 0x45).unwrap();
+        assert_eq!(huc1.readu8(0x4000.into()).unwrap(), 0x05);
+
+        huc1.writeu8(0x4000.into(), 2).unwrap();
+        huc1.writeu8(0xa000.into(), 0x42).unwrap();
+        huc1.writeu8(0x4000.into(), 1).unwrap();
+        assert_eq!(huc1.readu8(0xa000.into()).unwrap(), 0x00);
+        huc1.writeu8(0x4000.into(), 2).unwrap();
+        assert_eq!(huc1.readu8(0xa000.into()).unwrap(), 0x42);
+    }
+
+    #[test]
+    fn ir_mode_hides_the_ram() {
+        let mut huc1 = HuC1::new(test_rom(8, HUC1, 0x03), HUC1);
+        huc1.writeu8(0xa000.into(), 0x42).unwrap();
+
+        huc1.writeu8(0x0000.into(), IR_MODE).unwrap();
+        assert_eq!(huc1.readu8(0xa000.into()).unwrap(), 0xc0, "nothing attached, it's dark");
+        huc1.writeu8(0xa000.into(), 1).unwrap();
+        assert!(huc1.ir.led);
+
+        huc1.writeu8(0x0000.into(), 0x00).unwrap();
+        assert_eq!(huc1.readu8(0xa000.into()).unwrap(), 0x42);
+    }
+}
@@ -0,0 +1,486 @@
+use std::fmt::Debug;
+use std::time::Duration;
+
+use crate::clock::{SystemClock, TimeSource};
+use crate::header::RAM_SIZE_ADDR;
+use crate::mmu::busio::{BusIO, SResult};
+use crate::util::Addr;
+
+use super::huc1::IrPort;
+use super::rom_bank::{RomBank, ROM_BANK_SIZE};
+use super::ram_bank::{RamBank, RAM_BANK_SIZE};
+
+// https://gbdev.io/pandocs/HuC3.html
+//
+// Registers (write only)
+// ============================================================================
+// Address          Name            Usage notes
+// ============================================================================
+// 0x0000-0x1fff    mode            selects what 0xa000-0xbfff maps to
+// 0x2000-0x3fff    ROM bank        7 bits
+// 0x4000-0x5fff    RAM bank        2 bits
+//
+// Modes
+// ============================================================================
+//  0x0     RAM, read only
+//  0xa     RAM, read/write
+//  0xb     write a command for the RTC chip, bits 4-6: command, bits 0-3: argument
+//  0xc     read the result of the last command, bits 4-6: command, bits 0-3: result
+//  0xd     semaphore, reads 1 in bit 0 when the chip is ready, writing bit 0 clear runs the command
+//  0xe     IR port, like HuC1's
+//
+// The RTC chip has 256 nibbles of memory, accessed through the commands
+// ============================================================================
+//  0x1     read the nibble at the access address into the result, and increment the address
+//  0x3     write the argument to the access address, and increment the address
+//  0x4     set the lower nibble of the access address
+//  0x5     set the upper nibble of the access address
+//  0x6     extended command, the argument selects:
+//            0x0   copy the clock to memory 0x00-0x05 (minutes of the day, then days, 12 bits each, LSB first)
+//            0x1   copy memory 0x00-0x05 to the clock
+//            0x2   status, always reads 1
+//            0xe   play the tone selected at memory 0x26
+
+const MODE_RAM_READ: u8 = 0x0;
+const MODE_RAM: u8 = 0xa;
+const MODE_COMMAND: u8 = 0xb;
+const MODE_RESULT: u8 = 0xc;
+const MODE_SEMAPHORE: u8 = 0xd;
+const MODE_IR: u8 = 0xe;
+
+const CMD_READ: u8 = 0x1;
+const CMD_WRITE: u8 = 0x3;
+const CMD_ADDR_LOW: u8 = 0x4;
+const CMD_ADDR_HIGH: u8 = 0x5;
+const CMD_EXTENDED: u8 = 0x6;
+
+const EXT_CLOCK_TO_MEMORY: u8 = 0x0;
+const EXT_MEMORY_TO_CLOCK: u8 = 0x1;
+const EXT_STATUS: u8 = 0x2;
+const EXT_TONE: u8 = 0xe;
+
+const TONE_ADDR: usize = 0x26;
+
+const MINUTES_PER_DAY: u64 = 24 * 60;
+const DAYS: u64 = 1 << 12;
+
+// The clock is saved after the RAM as little endian minutes (u32), days (u32) and the unix timestamp (u64).
+const CLOCK_SAVE_SIZE: usize = 16;
+
+pub(crate) type ToneCallback = Box<dyn FnMut(u8)>;
+
+pub(crate) struct HuC3 {
+    roms: Vec<RomBank>,
+    rams: Vec<RamBank>,
+    pub(super) ir: IrPort,
+
+    cur_rom: u8,
+    cur_ram: u8,
+    mode: u8,
+
+    memory: [u8; 256], // nibbles
+    address: u8,
+    command: u8,
+    result: u8,
+
+    minutes: u16,
+    days: u16,
+    source: Box<dyn TimeSource>,
+    synced: Duration,
+
+    tone: Option<ToneCallback>,
+}
+
+impl Debug for HuC3 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HuC3")
+            .field("cur_rom", &self.cur_rom)
+            .field("cur_ram", &self.cur_ram)
+            .field("mode", &self.mode)
+            .field("address", &self.address)
+            .field("command", &self.command)
+            .field("result", &self.result)
+            .field("minutes", &self.minutes)
+            .field("days", &self.days)
+            .field("ir", &self.ir)
+            .finish()
+    }
+}
+
+impl HuC3 {
+    pub(crate) fn new(raw: Vec<u8>, _mbc_mode: u8) -> Self {
+        let num_rams = RamBank::count(raw[RAM_SIZE_ADDR]);
+        let source: Box<dyn TimeSource> = Box::new(SystemClock);
+
+        Self {
+            roms: RomBank::split(&raw),
+            rams: vec![RamBank::new(); num_rams],
+            ir: Default::default(),
+
+            cur_rom: 1,
+            cur_ram: 0,
+            mode: MODE_RAM_READ,
+
+            memory: [0; 256],
+            address: 0,
+            command: 0,
+            result: 0,
+
+            minutes: 0,
+            days: 0,
+            synced: source.now(),
+            source,
+
+            tone: None,
+        }
+    }
+
+    pub(crate) fn set_time_source(&mut self, source: Box<dyn TimeSource>) {
+        self.update_clock();
+        self.synced = source.now();
+        self.source = source;
+    }
+
+    pub(crate) fn set_tone_callback(&mut self, callback: ToneCallback) {
+        self.tone = Some(callback);
+    }
+
+    // the RAM, followed by the clock
+    pub(crate) fn save_data(&self) -> Vec<u8> {
+        let now = self.source.now();
+        let (minutes, days) = self.clock_at(now);
+        let mut data = RamBank::dump(&self.rams);
+        data.extend_from_slice(&(minutes as u32).to_le_bytes());
+        data.extend_from_slice(&(days as u32).to_le_bytes());
+        data.extend_from_slice(&now.as_secs().to_le_bytes());
+        data
+    }
+
+    pub(crate) fn load_save_data(&mut self, data: &[u8]) {
+        let ram_size = self.rams.len() * RAM_BANK_SIZE;
+        RamBank::restore(&mut self.rams, &data[..ram_size.min(data.len())]);
+
+        let Some(clock) = data.get(ram_size..ram_size + CLOCK_SAVE_SIZE) else {
+            return;
+        };
+        let word = |i: usize| u32::from_le_bytes(clock[i..i + 4].try_into().unwrap());
+        self.minutes = (word(0) as u64 % MINUTES_PER_DAY) as u16;
+        self.days = (word(4) as u64 % DAYS) as u16;
+        // Like the MBC3's RTC, the stamp can come from a time source with another epoch than the
+        // current one. The time that passed is caught up on the current source, and a stamp from its
+        // future counts as no time passed.
+        let saved_at = Duration::from_secs(u64::from_le_bytes(clock[8..16].try_into().unwrap()));
+        self.synced = saved_at.min(self.source.now());
+        self.update_clock();
+    }
+
+    fn rom_index(&self, addr: u16) -> usize {
+        let index = match addr {
+            0x0000..0x4000 => 0,
+            _              => self.cur_rom as usize,
+        };
+        index % self.roms.len()
+    }
+
+    fn ram_index(&self) -> Option<usize> {
+        if !matches!(self.mode, MODE_RAM_READ | MODE_RAM) || self.rams.is_empty() {
+            return None;
+        }
+        Some(self.cur_ram as usize % self.rams.len())
+    }
+
+    // the clock as it would be at `now`
+    fn clock_at(&self, now: Duration) -> (u16, u16) {
+        let elapsed = now.saturating_sub(self.synced).as_secs() / 60;
+        let minutes = self.minutes as u64 + elapsed;
+        let days = (self.days as u64 + minutes / MINUTES_PER_DAY) % DAYS;
+        ((minutes % MINUTES_PER_DAY) as u16, days as u16)
+    }
+
+    fn update_clock(&mut self) {
+        let now = self.source.now();
+        let elapsed_minutes = now.saturating_sub(self.synced).as_secs() / 60;
+        (self.minutes, self.days) = self.clock_at(now);
+        // keep the seconds that didn't make a whole minute yet
+        self.synced += Duration::from_secs(elapsed_minutes * 60);
+        if self.synced > now {
+            self.synced = now;
+        }
+    }
+
+    fn run_command(&mut self) {
+        let command = (self.command >> 4) & 0x7;
+        let arg = self.command & 0x0f;
+        match command {
+            CMD_READ => {
+                self.result = self.memory[self.address as usize];
+                self.address = self.address.wrapping_add(1);
+            }
+            CMD_WRITE => {
+                self.memory[self.address as usize] = arg;
+                self.address = self.address.wrapping_add(1);
+            }
+            CMD_ADDR_LOW  => self.address = self.address & 0xf0 | arg,
+            CMD_ADDR_HIGH => self.address = self.address & 0x0f | arg << 4,
+            CMD_EXTENDED  => match arg {
+                EXT_CLOCK_TO_MEMORY => {
+                    self.update_clock();
+                    for i in 0..3 {
+                        self.memory[i] = (self.minutes >> (i * 4)) as u8 & 0x0f;
+                        self.memory[i + 3] = (self.days >> (i * 4)) as u8 & 0x0f;
+                    }
+                }
+                EXT_MEMORY_TO_CLOCK => {
+                    let nibbles = |start: usize| {
+                        (0..3).fold(0u16, |v, i| v | (self.memory[start + i] as u16) << (i * 4))
+                    };
+                    self.minutes = (nibbles(0) as u64 % MINUTES_PER_DAY) as u16;
+                    self.days = nibbles(3);
+                    self.synced = self.source.now();
+                }
+                EXT_STATUS => self.result = 1,
+                EXT_TONE => {
+                    let tone = self.memory[TONE_ADDR];
+                    if let Some(callback) = self.tone.as_mut() {
+                        callback(tone);
+                    }
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+}
+
+impl BusIO for HuC3 {
+    fn readu8(&self, addr: Addr) -> SResult<u8> {
+        match addr.into() {
+            a @ 0x0000..0x8000 => self.roms[self.rom_index(a)].readu8(addr),
+            0xa000..0xc000 => {
+                let v = match self.mode {
+                    MODE_RAM_READ | MODE_RAM => match self.ram_index() {
+                        Some(index) => self.rams[index].readu8(addr)?,
+                        None        => 0xff,
+                    },
+                    MODE_RESULT    => 0x80 | (self.command & 0x70) | self.result,
+                    // commands complete immediately, so the chip is always ready
+                    MODE_SEMAPHORE => 0xff,
+                    MODE_IR        => 0xc0 | self.ir.light() as u8,
+                    _              => 0xff,
+                };
+                Ok(v)
+            }
+            _              => Err(format!("HuC3 readu8 - invalid addr: {:x?}", addr).into())
+        }
+    }
+
+    fn writeu8(&mut self, addr: Addr, value: u8) -> SResult<()> {
+        match addr.into() {
+            0x0000..0x2000 => self.mode = value & 0x0f,
+            0x2000..0x4000 => self.cur_rom = value & 0b0111_1111,
+            0x4000..0x6000 => self.cur_ram = value & 0b11,
+            0x6000..0x8000 => {}
+            0xa000..0xc000 => match self.mode {
+                MODE_RAM => {
+                    if let Some(index) = self.ram_index() {
+                        self.rams[index].writeu8(addr, value)?;
+                    }
+                }
+                MODE_COMMAND   => self.command = value & 0x7f,
+                MODE_SEMAPHORE => {
+                    if value & 1 == 0 {
+                        self.run_command();
+                    }
+                }
+                MODE_IR        => self.ir.set_led(value & 1 == 1),
+                _              => {}
+            },
+            _              => return Err(format!("HuC3 writeu8 - invalid addr: {:x?}", addr).into())
+        };
+        Ok(())
+    }
+
+    fn readu16(&self, addr: Addr) -> SResult<u16> {
+        Ok(u16::from_le_bytes([
+            self.readu8(addr)?,
+            self.readu8(addr + 1.into())?
+        ]))
+    }
+
+    fn writeu16(&mut self, addr: Addr, value: u16) -> SResult<()> {
+        if addr > 0x0000.into() && addr < 0x8000.into() {
+            panic!("HuC3 writeu16 @ {:x?}", addr);
+        }
+        let value = value.to_le_bytes();
+        self.writeu8(addr, value[0])?;
+        self.writeu8(addr + 1.into(), value[1])?;
+        Ok(())
+    }
+
+    // used by OAM DMA
+    fn as_slice(&self, addr: Addr, len: usize) -> SResult<&[u8]> {
+        match addr.into() {
+            a @ 0x0000..0x8000 => {
+                let offset = a as usize % ROM_BANK_SIZE;
+                Ok(&self.roms[self.rom_index(a)].0[offset..][..len])
+            }
+            0xa000..0xc000 => match self.ram_index() {
+                Some(index) => self.rams[index].as_slice(addr, len),
+                None        => Err(format!("HuC3 as_slice - RAM not mapped: {:x?}", addr).into()),
+            },
+            _              => Err(format!("HuC3 as_slice - invalid addr: {:x?}", addr).into())
+        }
+    }
+
+    fn print_dbg(&self, _start: Addr, _len: u16) -> String {
+        unimplemented!()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::infrared::InfraredLink;
+    use crate::mmu::cartridge::huc1::HuC1;
+    use crate::mmu::cartridge::test_rom;
+
+    const HUC3: u8 = 0xfe;
+    const HUC1: u8 = 0xff;
+
+    fn huc3(clock: &ManualClock) -> HuC3 {
+        let mut huc3 = HuC3::new(test_rom(8, HUC3, 0x03), HUC3);
+        huc3.set_time_source(Box::new(clock.clone()));
+        huc3
+    }
+
+    // writes the command, and runs it through the semaphore
+    fn command(huc3: &mut HuC3, command: u8) {
+        huc3.writeu8(0x0000.into(), MODE_COMMAND).unwrap();
+        huc3.writeu8(0xa000.into(), command).unwrap();
+        huc3.writeu8(0x0000.into(), MODE_SEMAPHORE).unwrap();
+        assert_eq!(huc3.readu8(0xa000.into()).unwrap() & 1, 1, "ready");
+        huc3.writeu8(0xa000.into(), 0).unwrap();
+    }
+
+    fn result(huc3: &mut HuC3) -> u8 {
+        huc3.writeu8(0x0000.into(), MODE_RESULT).unwrap();
+        huc3.readu8(0xa000.into()).unwrap()
+    }
+
+    fn set_address(huc3: &mut HuC3, address: u8) {
+        command(huc3, CMD_ADDR_LOW << 4 | address & 0x0f);
+        command(huc3, CMD_ADDR_HIGH << 4 | address >> 4);
+    }
+
+    fn read_nibbles<const N: usize>(huc3: &mut HuC3, address: u8) -> [u8; N] {
+        set_address(huc3, address);
+        [(); N].map(|_| {
+            command(huc3, CMD_READ << 4);
+            result(huc3) & 0x0f
+        })
+    }
+
+    fn write_nibbles(huc3: &mut HuC3, address: u8, nibbles: &[u8]) {
+        set_address(huc3, address);
+        for &n in nibbles {
+            command(huc3, CMD_WRITE << 4 | n);
+        }
+    }
+
+    #[test]
+    fn memory() {
+        let mut huc3 = huc3(&ManualClock::new());
+        write_nibbles(&mut huc3, 0x42, &[0x5, 0xa]);
+        assert_eq!(read_nibbles(&mut huc3, 0x42), [0x5, 0xa]);
+        // the result comes with the command that made it, and bit 7 set
+        assert_eq!(result(&mut huc3), 0x80 | CMD_READ << 4 | 0xa);
+    }
+
+    #[test]
+    fn clock_to_memory() {
+        let clock = ManualClock::new();
+        let mut huc3 = huc3(&clock);
+        // a day, 90 minutes and a bit
+        clock.advance(Duration::from_secs(24 * 60 * 60 + 90 * 60 + 30));
+        command(&mut huc3, CMD_EXTENDED << 4 | EXT_CLOCK_TO_MEMORY);
+        assert_eq!(read_nibbles(&mut huc3, 0x00), [0xa, 0x5, 0x0, 0x1, 0x0, 0x0]);
+
+        // the 30 seconds aren't lost
+        clock.advance(Duration::from_secs(30));
+        command(&mut huc3, CMD_EXTENDED << 4 | EXT_CLOCK_TO_MEMORY);
+        assert_eq!(read_nibbles(&mut huc3, 0x00), [0xb, 0x5, 0x0, 0x1, 0x0, 0x0]);
+    }
+
+    #[test]
+    fn memory_to_clock() {
+        let clock = ManualClock::new();
+        let mut huc3 = huc3(&clock);
+        // 23:59 on day 0xfff
+        write_nibbles(&mut huc3, 0x00, &[0xf, 0x9, 0x5, 0xf, 0xf, 0xf]);
+        command(&mut huc3, CMD_EXTENDED << 4 | EXT_MEMORY_TO_CLOCK);
+
+        // and a minute later the day counter wraps
+        clock.advance(Duration::from_secs(60));
+        command(&mut huc3, CMD_EXTENDED << 4 | EXT_CLOCK_TO_MEMORY);
+        assert_eq!(read_nibbles(&mut huc3, 0x00), [0; 6]);
+    }
+
+    #[test]
+    fn status() {
+        let mut huc3 = huc3(&ManualClock::new());
+        command(&mut huc3, CMD_EXTENDED << 4 | EXT_STATUS);
+        assert_eq!(result(&mut huc3) & 0x0f, 1);
+    }
+
+    #[test]
+    fn tone() {
+        let mut huc3 = huc3(&ManualClock::new());
+        let tones = Rc::new(RefCell::new(Vec::new()));
+        let played = tones.clone();
+        huc3.set_tone_callback(Box::new(move |tone| played.borrow_mut().push(tone)));
+
+        write_nibbles(&mut huc3, TONE_ADDR as u8, &[0x3]);
+        command(&mut huc3, CMD_EXTENDED << 4 | EXT_TONE);
+        assert_eq!(*tones.borrow(), [0x3]);
+    }
+
+    #[test]
+    fn save_from_another_clock() {
+        // a save stamped with the wall clock, loaded with a clock that starts at 0
+        let wall = ManualClock::new();
+        wall.set(Duration::from_secs(1_700_000_000));
+        let save = huc3(&wall).save_data();
+
+        let clock = ManualClock::new();
+        let mut huc3 = huc3(&clock);
+        huc3.load_save_data(&save);
+        clock.advance(Duration::from_secs(60));
+        command(&mut huc3, CMD_EXTENDED << 4 | EXT_CLOCK_TO_MEMORY);
+        assert_eq!(read_nibbles(&mut huc3, 0x00), [0x1, 0, 0, 0, 0, 0], "the clock keeps running");
+    }
+
+    #[test]
+    fn infrared() {
+        let (a, b) = InfraredLink::pair();
+        let mut huc3 = huc3(&ManualClock::new());
+        let mut huc1 = HuC1::new(test_rom(8, HUC1, 0x03), HUC1);
+        huc3.ir.attach(Box::new(a));
+        huc1.ir.attach(Box::new(b));
+        huc3.writeu8(0x0000.into(), MODE_IR).unwrap();
+        huc1.writeu8(0x0000.into(), 0x0e).unwrap();
+
+        assert_eq!(huc1.readu8(0xa000.into()).unwrap(), 0xc0, "dark");
+        huc3.writeu8(0xa000.into(), 1).unwrap();
+        assert_eq!(huc1.readu8(0xa000.into()).unwrap(), 0xc1);
+        huc3.writeu8(0xa000.into(), 0).unwrap();
+        assert_eq!(huc1.readu8(0xa000.into()).unwrap(), 0xc0);
+
+        huc1.writeu8(0xa000.into(), 1).unwrap();
+        assert_eq!(huc3.readu8(0xa000.into()).unwrap(), 0xc1);
+    }
+}
@@ -21,6 +21,15 @@ impl MBC0 {
             ram: [0; MBC0_RAM_SIZE as usize],
         }
     }
+
+    pub(crate) fn save_data(&self) -> Vec<u8> {
+        self.ram.to_vec()
+    }
+
+    pub(crate) fn load_save_data(&mut self, data: &[u8]) {
+        let len = data.len().min(self.ram.len());
+        self.ram[..len].copy_from_slice(&data[..len]);
+    }
 }
 
 impl BusIO for MBC0 {
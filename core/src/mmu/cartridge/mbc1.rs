@@ -1,98 +1,126 @@
+use crate::header::RAM_SIZE_ADDR;
 use crate::mmu::busio::{BusIO, SResult};
 use crate::util::Addr;
 
 use super::rom_bank::{RomBank, ROM_BANK_SIZE};
-// use super::ram_bank::{RamBank, RAM_BANK_SIZE};
+use super::ram_bank::RamBank;
+use super::game_offsets;
 
-const ROM_SIZE_ADDR: u16 = 0x148;
-const NUM_OF_RAMS_ADDR: u16 = 0x149;
+// https://gbdev.io/pandocs/MBC1.html
+//
+// Registers (write only)
+// ============================================================================
+// Address          Name            Usage notes
+// ============================================================================
+// 0x0000-0x1fff    RAM enable      0x0a in the lower 4 bits enables, anything else disables
+// 0x2000-0x3fff    ROM bank        lower 5 bits of the ROM bank, 0 is treated as 1
+// 0x4000-0x5fff    secondary       2 bits, upper bits of the ROM bank or the RAM bank
+// 0x6000-0x7fff    banking mode    0: secondary only applies to 0x4000-0x7fff
+//                                  1: also applies to 0x0000-0x3fff and the RAM
+
+// MBC1M multicarts wire the secondary register to ROM bank bits 4-5 instead of 5-6,
+// so every game gets 16 banks, and its header starts at 0x40000 * n
+const MULTICART_ROM_SIZE: usize = 0x100000;
+
+// the secondary register can only pick one of 4 RAM banks, any more in the header can't be reached
+const MAX_RAM_BANKS: usize = 4;
 
 #[derive(Debug)]
 pub(crate) struct MBC1{
     roms: Vec<RomBank>,
-    // rams: Vec<RamBank>,
+    rams: Vec<RamBank>,
 
-    cur_rom: u8,
-    // secondary: u8, // 2 bit
+    cur_rom: u8,   // 5 bit
+    secondary: u8, // 2 bit
 
     ram_enabled: bool,
     use_secondary: bool,
+    multicart: bool,
 }
 
 impl MBC1 {
     pub(crate) fn new(raw: Vec<u8>, _mbc_mode: u8) -> Self {
-        let num_rams = raw[NUM_OF_RAMS_ADDR as usize] as usize;
-        println!("num_rams: {:x}", num_rams);
-        assert!(num_rams <= 4);
-        
-        let rom_size =  32usize * (1 << raw[ROM_SIZE_ADDR as usize]);
-        println!("raw_rom_size: {}, rom_size: {}", raw[ROM_SIZE_ADDR as usize], rom_size);
+        let num_rams = RamBank::count(raw[RAM_SIZE_ADDR]).min(MAX_RAM_BANKS);
 
-        let s = Self {
-            roms: raw.chunks(ROM_BANK_SIZE as usize).map(|c| c.to_vec()).map(RomBank::new).collect(),
-            // rams: vec![RamBank::new(); num_rams],
+        Self {
+            roms: RomBank::split(&raw),
+            rams: vec![RamBank::new(); num_rams],
 
             cur_rom: 1,
-            // secondary: 0, 
+            secondary: 0,
 
             ram_enabled: false,
             use_secondary: false,
+            multicart: Self::is_multicart(&raw),
+        }
+    }
+
+    // There's nothing in the header that says MBC1M, but every known multicart is 1 MiB
+    // and has more than one game (and so more than one copy of the logo) in it.
+    fn is_multicart(raw: &[u8]) -> bool {
+        raw.len() == MULTICART_ROM_SIZE && game_offsets(raw).len() > 1
+    }
+
+    fn secondary_shift(&self) -> u8 {
+        if self.multicart { 4 } else { 5 }
+    }
+
+    fn rom_index(&self, addr: u16) -> usize {
+        let index = match addr {
+            0x0000..0x4000 if self.use_secondary => (self.secondary << self.secondary_shift()) as usize,
+            0x0000..0x4000                       => 0,
+            _ => {
+                // the zero check happens on all 5 bits, even though MBC1M only uses 4 of them
+                let cur_rom = if self.multicart { self.cur_rom & 0x0f } else { self.cur_rom };
+                ((self.secondary << self.secondary_shift()) | cur_rom) as usize
+            }
         };
+        // banks past the end of the ROM wrap around, as the unused bank lines aren't connected
+        index % self.roms.len()
+    }
 
-        println!("num of rom banks created: {}", s.roms.len());
-        for r in &s.roms {
-            println!("{:x?}", &r.0[..][..20]);
+    fn ram_index(&self) -> Option<usize> {
+        if !self.ram_enabled || self.rams.is_empty() {
+            return None;
         }
-        // panic!();
-        s
+        let index = if self.use_secondary { self.secondary } else { 0 } as usize;
+        Some(index % self.rams.len())
+    }
+
+    pub(crate) fn save_data(&self) -> Vec<u8> {
+        RamBank::dump(&self.rams)
+    }
+
+    pub(crate) fn load_save_data(&mut self, data: &[u8]) {
+        RamBank::restore(&mut self.rams, data);
     }
 }
 
 impl BusIO for MBC1 {
     fn readu8(&self, addr: Addr) -> SResult<u8> {
-        // if addr == 0x65fd.into() {
-        //     panic!("read from 0x65fd, cur_rom: {:?}, secondary:  {:?}, ram_enabled: {:?}, use_secondary: {:?}", self.cur_rom, self.secondary, self.ram_enabled, self.use_secondary);
-        // }
         match addr.into() {
-            0x0000..0x4000 => { 
-                // let index = if self.use_secondary { self.secondary << 5  } else { 0 } as usize;
-                let index = 0usize;
-                self.roms[index].readu8(addr)
-            }
-            0x4000..0x8000 => {
-                // let index = if self.use_secondary {self.secondary << 5 | self.cur_rom } else { self.cur_rom } as usize;
-                let index =  self.cur_rom as usize;
-                // let index = index % self.roms.len(); // don't know if it's a good idea to use mod here
-                self.roms[index].readu8(addr) 
-            }
-            0xa000..0xc000 => {
-                // if self.ram_enabled { 
-                //     let index = if self.use_secondary { self.secondary } else { 0 } as usize;
-                //     let index = index % self.rams.len(); // don't know if it's a good idea to use mod here
-                //     self.rams[index].readu8(addr) 
-                // } else {
-                    Ok(0xff)
-                // }
-            }
+            a @ 0x0000..0x8000 => self.roms[self.rom_index(a)].readu8(addr),
+            0xa000..0xc000 => match self.ram_index() {
+                Some(index) => self.rams[index].readu8(addr),
+                None        => Ok(0xff),
+            },
             _              => Err(format!("MBC1 readu8 - invalid addr: {:x?}", addr).into())
         }
     }
 
     fn writeu8(&mut self, addr: Addr, value: u8) -> SResult<()> {
         match addr.into() {
-            0x0000..0x2000 => { if value & 0x0f == 0xa { panic!("write {:x?} to ram_enabled @ {:x?}", value, addr)}; self.ram_enabled = value & 0x0f == 0xa},
+            0x0000..0x2000 => self.ram_enabled = value & 0x0f == 0xa,
             0x2000..0x4000 => {
                 let cur_rom = value & 0b0001_1111;
                 self.cur_rom = if cur_rom == 0 {1} else {cur_rom};
             }
-            // 0x4000..0x6000 => { panic!("write to secondary");self.secondary = value & 0b11},
-            // 0x6000..0x8000 => { panic!("write to use_secondary");self.use_secondary = value != 0},
+            0x4000..0x6000 => self.secondary = value & 0b11,
+            0x6000..0x8000 => self.use_secondary = value & 1 == 1,
             0xa000..0xc000 => {
-                // if self.ram_enabled { 
-                //     let index = if self.use_secondary { self.secondary } else { 0 } as usize;
-                //     let index = index % self.rams.len(); // don't know if it's a good idea to use mod here
-                //     self.rams[index].writeu8(addr, value)?; 
-                // } 
+                if let Some(index) = self.ram_index() {
+                    self.rams[index].writeu8(addr, value)?;
+                }
             }
             _              => return Err(format!("MBC1 writeu8 - invalid addr: {:x?}", addr).into())
         };
@@ -113,14 +141,160 @@ impl BusIO for MBC1 {
         let value = value.to_le_bytes();
         self.writeu8(addr, value[0])?;
         self.writeu8(addr + 1.into(), value[1])?;
-        Ok(()) 
+        Ok(())
     }
 
+    // used by OAM DMA
     fn as_slice(&self, addr: Addr, len: usize) -> SResult<&[u8]> {
-        unimplemented!() // expecting this to blow up 
+        match addr.into() {
+            a @ 0x0000..0x8000 => {
+                let offset = a as usize % ROM_BANK_SIZE;
+                Ok(&self.roms[self.rom_index(a)].0[offset..][..len])
+            }
+            0xa000..0xc000 => match self.ram_index() {
+                Some(index) => self.rams[index].as_slice(addr, len),
+                None        => Err(format!("MBC1 as_slice - RAM disabled: {:x?}", addr).into()),
+            },
+            _              => Err(format!("MBC1 as_slice - invalid addr: {:x?}", addr).into())
+        }
     }
 
-    fn print_dbg(&self, start: Addr, len: u16) -> String {
+    fn print_dbg(&self, _start: Addr, _len: u16) -> String {
         unimplemented!()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::{LOGO_ADDR, NINTENDO_LOGO};
+    use crate::mmu::cartridge::test_rom;
+    use crate::mmu::cartridge::ram_bank::RAM_BANK_SIZE;
+
+    const MBC1_RAM_BATTERY: u8 = 0x03;
+
+    fn mbc1(banks: usize, ram_size_code: u8) -> MBC1 {
+        let mut mbc = MBC1::new(test_rom(banks, MBC1_RAM_BATTERY, ram_size_code), MBC1_RAM_BATTERY);
+        mbc.writeu8(0x0000.into(), 0x0a).unwrap();
+        mbc
+    }
+
+    fn write(mbc: &mut MBC1, addr: u16, value: u8) {
+        mbc.writeu8(addr.into(), value).unwrap();
+    }
+
+    // the banks at 0x0000-0x3fff and 0x4000-0x7fff
+    fn banks(mbc: &MBC1) -> (u8, u8) {
+        (mbc.readu8(0x0000.into()).unwrap(), mbc.readu8(0x4000.into()).unwrap())
+    }
+
+    #[test]
+    fn banking_mode() {
+        let mut mbc = mbc1(128, 0x00);
+        write(&mut mbc, 0x2000, 0x05);
+        write(&mut mbc, 0x4000, 0x03);
+        assert_eq!(banks(&mbc), (0x00, 0x65));
+
+        // mode 1 also moves 0x0000-0x3fff, to the secondary register's bank
+        write(&mut mbc, 0x6000, 1);
+        assert_eq!(banks(&mbc), (0x60, 0x65));
+        write(&mut mbc, 0x6000, 0);
+        assert_eq!(banks(&mbc), (0x00, 0x65));
+    }
+
+    #[test]
+    fn bank_zero() {
+        let mut mbc = mbc1(128, 0x00);
+        // 0x20, 0x40 and 0x60 can't be mapped at 0x4000-0x7fff, it's the bank after them
+        for (secondary, bank) in [(0, 0x01), (1, 0x21), (2, 0x41), (3, 0x61)] {
+            write(&mut mbc, 0x4000, secondary);
+            write(&mut mbc, 0x2000, 0x00);
+            assert_eq!(banks(&mbc).1, bank);
+            // only the 5 bits that are there are checked
+            write(&mut mbc, 0x2000, 0x20);
+            assert_eq!(banks(&mbc).1, bank);
+        }
+
+        // but they can be at 0x0000-0x3fff in mode 1
+        write(&mut mbc, 0x6000, 1);
+        assert_eq!(banks(&mbc), (0x60, 0x61));
+    }
+
+    #[test]
+    fn rom_wraps_around() {
+        let mut mbc = mbc1(8, 0x00);
+        write(&mut mbc, 0x2000, 0x0b);
+        assert_eq!(banks(&mbc), (0, 3));
+        write(&mut mbc, 0x4000, 1);
+        write(&mut mbc, 0x6000, 1);
+        assert_eq!(banks(&mbc), (0, 3));
+    }
+
+    #[test]
+    fn ram_banks() {
+        let mut mbc = mbc1(4, 0x03);
+        write(&mut mbc, 0x6000, 1);
+        for bank in 0..4 {
+            write(&mut mbc, 0x4000, bank);
+            write(&mut mbc, 0xa000, 0x10 + bank);
+        }
+        let save = mbc.save_data();
+        assert_eq!(save.len(), 4 * RAM_BANK_SIZE);
+        for bank in 0..4 {
+            assert_eq!(save[bank * RAM_BANK_SIZE], 0x10 + bank as u8);
+        }
+
+        // bank 0 only in mode 0, whatever the secondary register says
+        write(&mut mbc, 0x6000, 0);
+        assert_eq!(mbc.readu8(0xa000.into()).unwrap(), 0x10);
+
+        // and nothing while it's disabled
+        write(&mut mbc, 0x0000, 0x00);
+        assert_eq!(mbc.readu8(0xa000.into()).unwrap(), 0xff);
+    }
+
+    #[test]
+    fn ram_wraps_around() {
+        // a single 8 KiB bank is there whatever the secondary register says
+        let mut mbc = mbc1(4, 0x02);
+        write(&mut mbc, 0xa000, 0x42);
+        write(&mut mbc, 0x6000, 1);
+        write(&mut mbc, 0x4000, 3);
+        assert_eq!(mbc.readu8(0xa000.into()).unwrap(), 0x42);
+    }
+
+    #[test]
+    fn too_much_ram() {
+        // 128 KiB in the header, but only 4 banks can be reached
+        let mbc = mbc1(4, 0x04);
+        assert_eq!(mbc.save_data().len(), 4 * RAM_BANK_SIZE);
+    }
+
+    #[test]
+    fn multicart() {
+        // 4 games of 16 banks
+        let mut raw = test_rom(64, MBC1_RAM_BATTERY, 0x00);
+        for game in 0..4 {
+            raw[game * 0x40000 + LOGO_ADDR..][..NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+        }
+        let mut mbc = MBC1::new(raw, MBC1_RAM_BATTERY);
+        assert!(mbc.multicart);
+
+        // the secondary register is bits 4-5 of the bank
+        write(&mut mbc, 0x4000, 2);
+        write(&mut mbc, 0x2000, 0x03);
+        assert_eq!(banks(&mbc), (0, 0x23));
+        write(&mut mbc, 0x6000, 1);
+        assert_eq!(banks(&mbc), (0x20, 0x23));
+
+        // bit 4 of the ROM bank register isn't connected, but still counts for the check for 0
+        write(&mut mbc, 0x2000, 0x10);
+        assert_eq!(banks(&mbc), (0x20, 0x20));
+        write(&mut mbc, 0x2000, 0x00);
+        assert_eq!(banks(&mbc), (0x20, 0x21));
+
+        // not without the other games
+        let mbc = MBC1::new(test_rom(64, MBC1_RAM_BATTERY, 0x00), MBC1_RAM_BATTERY);
+        assert!(!mbc.multicart);
+    }
+}
@@ -0,0 +1,128 @@
+use crate::mmu::busio::{BusIO, SResult};
+use crate::util::Addr;
+
+use super::rom_bank::{RomBank, ROM_BANK_SIZE};
+
+// https://gbdev.io/pandocs/MBC2.html
+//
+// Registers (write only)
+// ============================================================================
+// Address          Name            Usage notes
+// ============================================================================
+// 0x0000-0x3fff    RAM enable      bit 8 of the address clear: 0x0a in the lower 4 bits enables the RAM
+//                  ROM bank        bit 8 of the address set: 4 bit ROM bank, 0 is treated as 1
+//
+// The MBC has 512 x 4 bits of RAM built in, only the lower 9 bits of the address are used,
+// so it repeats across all of 0xa000-0xbfff. The upper 4 bits aren't connected and read as 1s.
+
+const RAM_SIZE: usize = 512;
+const RAM_ADDR_MASK: u16 = RAM_SIZE as u16 - 1;
+
+#[derive(Debug)]
+pub(crate) struct MBC2 {
+    roms: Vec<RomBank>,
+    ram: [u8; RAM_SIZE],
+
+    cur_rom: u8, // 4 bit
+    ram_enabled: bool,
+}
+
+impl MBC2 {
+    pub(crate) fn new(raw: Vec<u8>, _mbc_mode: u8) -> Self {
+        Self {
+            roms: RomBank::split(&raw),
+            ram: [0; RAM_SIZE],
+
+            cur_rom: 1,
+            ram_enabled: false,
+        }
+    }
+
+    // one nibble per byte, like other emulators do, with the upper nibble as it reads
+    pub(crate) fn save_data(&self) -> Vec<u8> {
+        self.ram.iter().map(|v| v | 0xf0).collect()
+    }
+
+    pub(crate) fn load_save_data(&mut self, data: &[u8]) {
+        for (ram, v) in self.ram.iter_mut().zip(data) {
+            *ram = v & 0x0f;
+        }
+    }
+
+    fn rom_index(&self, addr: u16) -> usize {
+        let index = match addr {
+            0x0000..0x4000 => 0,
+            _              => self.cur_rom as usize,
+        };
+        index % self.roms.len()
+    }
+}
+
+impl BusIO for MBC2 {
+    fn readu8(&self, addr: Addr) -> SResult<u8> {
+        match addr.into() {
+            a @ 0x0000..0x8000 => self.roms[self.rom_index(a)].readu8(addr),
+            a @ 0xa000..0xc000 => {
+                if self.ram_enabled {
+                    Ok(self.ram[(a & RAM_ADDR_MASK) as usize] | 0xf0)
+                } else {
+                    Ok(0xff)
+                }
+            }
+            _              => Err(format!("MBC2 readu8 - invalid addr: {:x?}", addr).into())
+        }
+    }
+
+    fn writeu8(&mut self, addr: Addr, value: u8) -> SResult<()> {
+        match addr.into() {
+            a @ 0x0000..0x4000 => {
+                if a & 0x0100 == 0 {
+                    self.ram_enabled = value & 0x0f == 0xa;
+                } else {
+                    let cur_rom = value & 0x0f;
+                    self.cur_rom = if cur_rom == 0 {1} else {cur_rom};
+                }
+            }
+            0x4000..0x8000 => {}
+            a @ 0xa000..0xc000 => {
+                if self.ram_enabled {
+                    self.ram[(a & RAM_ADDR_MASK) as usize] = value & 0x0f;
+                }
+            }
+            _              => return Err(format!("MBC2 writeu8 - invalid addr: {:x?}", addr).into())
+        };
+        Ok(())
+    }
+
+    fn readu16(&self, addr: Addr) -> SResult<u16> {
+        Ok(u16::from_le_bytes([
+            self.readu8(addr)?,
+            self.readu8(addr + 1.into())?
+        ]))
+    }
+
+    fn writeu16(&mut self, addr: Addr, value: u16) -> SResult<()> {
+        if addr > 0x0000.into() && addr < 0x8000.into() {
+            panic!("MBC2 writeu16 @ {:x?}", addr);
+        }
+        let value = value.to_le_bytes();
+        self.writeu8(addr, value[0])?;
+        self.writeu8(addr + 1.into(), value[1])?;
+        Ok(())
+    }
+
+    // used by OAM DMA, only from the ROM, as the RAM reads need the upper nibble filled in
+    fn as_slice(&self, addr: Addr, len: usize) -> SResult<&[u8]> {
+        match addr.into() {
+            a @ 0x0000..0x8000 => {
+                let offset = a as usize % ROM_BANK_SIZE;
+                Ok(&self.roms[self.rom_index(a)].0[offset..][..len])
+            }
+            _              => Err(format!("MBC2 as_slice - invalid addr: {:x?}", addr).into())
+        }
+    }
+
+    fn print_dbg(&self, _start: Addr, _len: u16) -> String {
+        unimplemented!()
+    }
+}
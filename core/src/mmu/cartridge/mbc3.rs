@@ -0,0 +1,320 @@
+use crate::clock::TimeSource;
+use crate::header::RAM_SIZE_ADDR;
+use crate::mmu::busio::{BusIO, SResult};
+use crate::util::Addr;
+
+use super::rom_bank::{RomBank, ROM_BANK_SIZE};
+use super::ram_bank::{RamBank, RAM_BANK_SIZE};
+use super::rtc::{Rtc, RTC_DH, RTC_S};
+
+// https://gbdev.io/pandocs/MBC3.html
+//
+// Registers (write only)
+// ============================================================================
+// Address          Name            Usage notes
+// ============================================================================
+// 0x0000-0x1fff    enable          0x0a in the lower 4 bits enables the RAM and the RTC registers
+// 0x2000-0x3fff    ROM bank        7 bits, 0 is treated as 1
+// 0x4000-0x5fff    RAM bank        0x00-0x03 map a RAM bank, 0x08-0x0c an RTC register to 0xa000-0xbfff
+// 0x6000-0x7fff    latch clock     writing 0 and then 1 copies the clock to the RTC registers
+
+// cartridge types with the RTC (MBC3+TIMER+BATTERY, MBC3+TIMER+RAM+BATTERY)
+const MBC3_TIMER_BATTERY: u8 = 0x0f;
+const MBC3_TIMER_RAM_BATTERY: u8 = 0x10;
+
+#[derive(Debug)]
+pub(crate) struct MBC3 {
+    roms: Vec<RomBank>,
+    rams: Vec<RamBank>,
+    rtc: Option<Rtc>,
+
+    cur_rom: u8,
+    // RAM bank, or RTC register
+    cur_ram: u8,
+    ram_enabled: bool,
+}
+
+impl MBC3 {
+    pub(crate) fn new(raw: Vec<u8>, mbc_mode: u8) -> Self {
+        let num_rams = RamBank::count(raw[RAM_SIZE_ADDR]);
+        let has_rtc = matches!(mbc_mode, MBC3_TIMER_BATTERY | MBC3_TIMER_RAM_BATTERY);
+
+        Self {
+            roms: RomBank::split(&raw),
+            rams: vec![RamBank::new(); num_rams],
+            rtc: has_rtc.then(Rtc::new),
+
+            cur_rom: 1,
+            cur_ram: 0,
+            ram_enabled: false,
+        }
+    }
+
+    pub(crate) fn set_time_source(&mut self, source: Box<dyn TimeSource>) {
+        if let Some(rtc) = self.rtc.as_mut() {
+            rtc.set_time_source(source);
+        }
+    }
+
+    // the RAM, followed by the RTC if there is one
+    pub(crate) fn save_data(&self) -> Vec<u8> {
+        let mut data = RamBank::dump(&self.rams);
+        if let Some(rtc) = self.rtc.as_ref() {
+            data.extend(rtc.save());
+        }
+        data
+    }
+
+    pub(crate) fn load_save_data(&mut self, data: &[u8]) {
+        let ram_size = self.rams.len() * RAM_BANK_SIZE;
+        RamBank::restore(&mut self.rams, &data[..ram_size.min(data.len())]);
+        if let Some(rtc) = self.rtc.as_mut() {
+            // saves without the RTC (or with an unknown format) leave the clock alone
+            if data.len() > ram_size {
+                rtc.load(&data[ram_size..]);
+            }
+        }
+    }
+
+    fn rom_index(&self, addr: u16) -> usize {
+        let index = match addr {
+            0x0000..0x4000 => 0,
+            _              => self.cur_rom as usize,
+        };
+        index % self.roms.len()
+    }
+
+    fn ram_index(&self) -> Option<usize> {
+        if !self.ram_enabled || self.rams.is_empty() || self.cur_ram > 0x03 {
+            return None;
+        }
+        Some(self.cur_ram as usize % self.rams.len())
+    }
+
+    fn rtc_register(&self) -> Option<u8> {
+        (self.ram_enabled && (RTC_S..=RTC_DH).contains(&self.cur_ram)).then_some(self.cur_ram)
+    }
+}
+
+impl BusIO for MBC3 {
+    fn readu8(&self, addr: Addr) -> SResult<u8> {
+        match addr.into() {
+            a @ 0x0000..0x8000 => self.roms[self.rom_index(a)].readu8(addr),
+            0xa000..0xc000 => {
+                if let Some(index) = self.ram_index() {
+                    return self.rams[index].readu8(addr);
+                }
+                match (self.rtc_register(), self.rtc.as_ref()) {
+                    (Some(reg), Some(rtc)) => Ok(rtc.read(reg)),
+                    _                      => Ok(0xff),
+                }
+            }
+            _              => Err(format!("MBC3 readu8 - invalid addr: {:x?}", addr).into())
+        }
+    }
+
+    fn writeu8(&mut self, addr: Addr, value: u8) -> SResult<()> {
+        match addr.into() {
+            0x0000..0x2000 => self.ram_enabled = value & 0x0f == 0xa,
+            0x2000..0x4000 => {
+                let cur_rom = value & 0b0111_1111;
+                self.cur_rom = if cur_rom == 0 {1} else {cur_rom};
+            }
+            0x4000..0x6000 => self.cur_ram = value,
+            0x6000..0x8000 => {
+                if let Some(rtc) = self.rtc.as_mut() {
+                    rtc.write_latch(value);
+                }
+            }
+            0xa000..0xc000 => {
+                if let Some(index) = self.ram_index() {
+                    self.rams[index].writeu8(addr, value)?;
+                } else if let (Some(reg), Some(rtc)) = (self.rtc_register(), self.rtc.as_mut()) {
+                    rtc.write(reg, value);
+                }
+            }
+            _              => return Err(format!("MBC3 writeu8 - invalid addr: {:x?}", addr).into())
+        };
+        Ok(())
+    }
+
+    fn readu16(&self, addr: Addr) -> SResult<u16> {
+        Ok(u16::from_le_bytes([
+            self.readu8(addr)?,
+            self.readu8(addr + 1.into())?
+        ]))
+    }
+
+    fn writeu16(&mut self, addr: Addr, value: u16) -> SResult<()> {
+        if addr > 0x0000.into() && addr < 0x8000.into() {
+            panic!("MBC3 writeu16 @ {:x?}", addr);
+        }
+        let value = value.to_le_bytes();
+        self.writeu8(addr, value[0])?;
+        self.writeu8(addr + 1.into(), value[1])?;
+        Ok(())
+    }
+
+    // used by OAM DMA
+    fn as_slice(&self, addr: Addr, len: usize) -> SResult<&[u8]> {
+        match addr.into() {
+            a @ 0x0000..0x8000 => {
+                let offset = a as usize % ROM_BANK_SIZE;
+                Ok(&self.roms[self.rom_index(a)].0[offset..][..len])
+            }
+            0xa000..0xc000 => match self.ram_index() {
+                Some(index) => self.rams[index].as_slice(addr, len),
+                None        => Err(format!("MBC3 as_slice - RAM not mapped: {:x?}", addr).into()),
+            },
+            _              => Err(format!("MBC3 as_slice - invalid addr: {:x?}", addr).into())
+        }
+    }
+
+    fn print_dbg(&self, _start: Addr, _len: u16) -> String {
+        unimplemented!()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::mmu::cartridge::test_rom;
+    use crate::mmu::cartridge::rtc::{RTC_DL, RTC_H, RTC_M};
+
+    const RAM_SIZE: usize = 4 * RAM_BANK_SIZE;
+
+    fn mbc3(clock: &ManualClock) -> MBC3 {
+        let mut mbc = MBC3::new(test_rom(8, MBC3_TIMER_RAM_BATTERY, 0x03), MBC3_TIMER_RAM_BATTERY);
+        mbc.set_time_source(Box::new(clock.clone()));
+        mbc.writeu8(0x0000.into(), 0x0a).unwrap();
+        mbc
+    }
+
+    fn latch(mbc: &mut MBC3) {
+        mbc.writeu8(0x6000.into(), 0).unwrap();
+        mbc.writeu8(0x6000.into(), 1).unwrap();
+    }
+
+    // the latched registers, S, M, H, DL and DH
+    fn read_rtc(mbc: &mut MBC3) -> [u8; 5] {
+        [RTC_S, RTC_M, RTC_H, RTC_DL, RTC_DH].map(|reg| {
+            mbc.writeu8(0x4000.into(), reg).unwrap();
+            mbc.readu8(0xa000.into()).unwrap()
+        })
+    }
+
+    fn write_rtc(mbc: &mut MBC3, regs: [u8; 5]) {
+        for (reg, value) in [RTC_S, RTC_M, RTC_H, RTC_DL, RTC_DH].into_iter().zip(regs) {
+            mbc.writeu8(0x4000.into(), reg).unwrap();
+            mbc.writeu8(0xa000.into(), value).unwrap();
+        }
+    }
+
+    #[test]
+    fn latching() {
+        let clock = ManualClock::new();
+        let mut mbc = mbc3(&clock);
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(read_rtc(&mut mbc), [0; 5], "nothing latched yet");
+
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc), [5, 0, 0, 0, 0]);
+
+        // the latched copy doesn't move, and only a 0 followed by a 1 latches again
+        clock.advance(Duration::from_secs(3));
+        mbc.writeu8(0x6000.into(), 1).unwrap();
+        assert_eq!(read_rtc(&mut mbc)[0], 5);
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc)[0], 8);
+    }
+
+    #[test]
+    fn halt() {
+        let clock = ManualClock::new();
+        let mut mbc = mbc3(&clock);
+        write_rtc(&mut mbc, [10, 0, 0, 0, 0x40]);
+        clock.advance(Duration::from_secs(100));
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc), [10, 0, 0, 0, 0x40]);
+
+        write_rtc(&mut mbc, [10, 0, 0, 0, 0]);
+        clock.advance(Duration::from_secs(1));
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc), [11, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn seconds_carry_into_days() {
+        let clock = ManualClock::new();
+        let mut mbc = mbc3(&clock);
+        // day 255, 23:59:59
+        write_rtc(&mut mbc, [59, 59, 23, 0xff, 0]);
+        clock.advance(Duration::from_secs(1));
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc), [0, 0, 0, 0, 1], "day 256");
+    }
+
+    #[test]
+    fn day_overflow() {
+        let clock = ManualClock::new();
+        let mut mbc = mbc3(&clock);
+        // day 511, 23:59:59
+        write_rtc(&mut mbc, [59, 59, 23, 0xff, 1]);
+        clock.advance(Duration::from_secs(1));
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc), [0, 0, 0, 0, 0x80], "day 0 with the carry set");
+
+        // the carry stays set until it's written
+        clock.advance(Duration::from_secs(24 * 60 * 60));
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc), [0, 0, 0, 1, 0x80]);
+        write_rtc(&mut mbc, [0, 0, 0, 1, 0]);
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc), [0, 0, 0, 1, 0]);
+    }
+
+    #[test]
+    fn save_round_trip() {
+        let clock = ManualClock::new();
+        clock.set(Duration::from_secs(1_000));
+        let mut mbc = mbc3(&clock);
+        write_rtc(&mut mbc, [30, 20, 10, 5, 0]);
+        latch(&mut mbc);
+        let save = mbc.save_data();
+        assert_eq!(save.len(), RAM_SIZE + 48);
+        assert_eq!(save[RAM_SIZE + 40..], 1_000u64.to_le_bytes());
+
+        // 90 seconds later, with the 64 bit and the older 32 bit timestamp
+        let stamp = u32::to_le_bytes(1_000);
+        let old_save = [&save[..RAM_SIZE + 40], &stamp[..]].concat();
+        for save in [save, old_save] {
+            let clock = ManualClock::new();
+            clock.set(Duration::from_secs(1_090));
+            let mut mbc = mbc3(&clock);
+            mbc.load_save_data(&save);
+            assert_eq!(read_rtc(&mut mbc), [30, 20, 10, 5, 0], "the latched registers are restored");
+            latch(&mut mbc);
+            assert_eq!(read_rtc(&mut mbc), [0, 22, 10, 5, 0]);
+        }
+    }
+
+    #[test]
+    fn save_from_another_clock() {
+        // a save stamped with the wall clock, loaded with a clock that starts at 0
+        let wall = ManualClock::new();
+        wall.set(Duration::from_secs(1_700_000_000));
+        let mut mbc = mbc3(&wall);
+        write_rtc(&mut mbc, [30, 0, 0, 0, 0]);
+        let save = mbc.save_data();
+
+        let clock = ManualClock::new();
+        let mut mbc = mbc3(&clock);
+        mbc.load_save_data(&save);
+        clock.advance(Duration::from_secs(10));
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc)[0], 40, "the clock keeps running");
+    }
+}
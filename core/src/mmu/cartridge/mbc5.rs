@@ -1,11 +1,59 @@
+use std::fmt::Debug;
+
+use crate::header::{RAM_SIZE_ADDR, ROM_SIZE_ADDR};
 use crate::mmu::busio::{BusIO, SResult};
 use crate::util::Addr;
 
 use super::rom_bank::{RomBank, ROM_BANK_SIZE};
-use super::ram_bank::{RamBank, RAM_BANK_SIZE};
+use super::ram_bank::RamBank;
+
+// https://gbdev.io/pandocs/MBC5.html
+//
+// Registers (write only)
+// ============================================================================
+// Address          Name            Usage notes
+// ============================================================================
+// 0x0000-0x1fff    RAM enable      0x0a in the lower 4 bits enables, anything else disables
+// 0x2000-0x2fff    ROM bank low    lower 8 bits of the ROM bank
+// 0x3000-0x3fff    ROM bank high   bit 8 of the ROM bank
+// 0x4000-0x5fff    RAM bank        4 bit RAM bank, on rumble cartridges bit 3 drives the motor instead
+//
+// Unlike the older MBCs, bank 0 can be mapped to 0x4000-0x7fff as well.
+
+// MBC5+RUMBLE, MBC5+RUMBLE+RAM, MBC5+RUMBLE+RAM+BATTERY
+const MBC5_RUMBLE: u8 = 0x1c;
+const MBC5_RUMBLE_RAM_BATTERY: u8 = 0x1e;
+
+const RUMBLE_BIT: u8 = 0b1000;
+
+pub(crate) type RumbleCallback = Box<dyn FnMut(bool)>;
+
+#[derive(Default)]
+struct Rumble {
+    on: bool,
+    callback: Option<RumbleCallback>,
+}
+
+impl Debug for Rumble {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Rumble")
+            .field("on", &self.on)
+            .field("callback", &self.callback.is_some())
+            .finish()
+    }
+}
 
-const ROM_SIZE_ADDR: u16 = 0x148;
-const NUM_OF_RAMS_ADDR: u16 = 0x149;
+impl Rumble {
+    fn set(&mut self, on: bool) {
+        if on == self.on {
+            return;
+        }
+        self.on = on;
+        if let Some(callback) = self.callback.as_mut() {
+            callback(on);
+        }
+    }
+}
 
 #[derive(Debug)]
 pub(crate) struct MBC5 {
@@ -13,72 +61,85 @@ pub(crate) struct MBC5 {
     rams: Vec<RamBank>,
 
     cur_rom_lower: u8,
-    cur_rom_higher: u8,
-    // secondary: u8, // 2 bit
+    cur_rom_higher: u8, // 1 bit
 
     cur_ram: u8,
     ram_enabled: bool,
-    // use_secondary: bool,
+
+    // only on rumble cartridges
+    rumble: Option<Rumble>,
 }
 
 impl MBC5 {
-    pub(crate) fn new(raw: Vec<u8>, _mbc_mode: u8) -> Self {
-        let num_rams = raw[NUM_OF_RAMS_ADDR as usize] as usize;
-        println!("num_rams: {:x}", num_rams);
-        
-        let rom_size =  32usize * (1 << raw[ROM_SIZE_ADDR as usize]);
-        println!("raw_rom_size: {}, rom_size: {}", raw[ROM_SIZE_ADDR as usize], rom_size);
-
-        let s = Self {
-            roms: raw.chunks(ROM_BANK_SIZE as usize).map(|c| c.to_vec()).map(RomBank::new).collect(),
+    pub(crate) fn new(raw: Vec<u8>, mbc_mode: u8) -> Self {
+        let num_rams = RamBank::count(raw[RAM_SIZE_ADDR]);
+
+        // a ROM dump that's smaller than the header says reads as open bus past its end
+        let mut roms = RomBank::split(&raw);
+        if let Some(num_roms) = RomBank::count(raw[ROM_SIZE_ADDR]) {
+            if num_roms > roms.len() {
+                roms.resize_with(num_roms, || RomBank::new(vec![0xff; ROM_BANK_SIZE]));
+            }
+        }
+
+        Self {
+            roms,
             rams: vec![RamBank::new(); num_rams],
 
-            cur_rom_lower: 0,
+            // bank 1 is mapped at power on
+            cur_rom_lower: 1,
             cur_rom_higher: 0,
-            // secondary: 0, 
 
             cur_ram: 0,
             ram_enabled: false,
-            // use_secondary: false,
-        };
 
-        println!("num of rom banks created: {}", s.roms.len());
-        for r in &s.roms {
-            println!("{:x?}", &r.0[..][..20]);
+            rumble: (MBC5_RUMBLE..=MBC5_RUMBLE_RAM_BATTERY).contains(&mbc_mode).then(Rumble::default),
+        }
+    }
+
+    pub(crate) fn set_rumble_callback(&mut self, callback: RumbleCallback) {
+        if let Some(rumble) = self.rumble.as_mut() {
+            rumble.callback = Some(callback);
         }
-        s
+    }
+
+    pub(crate) fn save_data(&self) -> Vec<u8> {
+        RamBank::dump(&self.rams)
+    }
+
+    pub(crate) fn load_save_data(&mut self, data: &[u8]) {
+        RamBank::restore(&mut self.rams, data);
     }
 
     fn get_rom_number(&self) -> usize {
         ((self.cur_rom_higher as usize & 1) << 8) | (self.cur_rom_lower as usize)
     }
+
+    fn rom_index(&self, addr: u16) -> usize {
+        let index = match addr {
+            0x0000..0x4000 => 0,
+            _              => self.get_rom_number(),
+        };
+        // the bank lines past the size of the ROM aren't connected
+        index % self.roms.len()
+    }
+
+    fn ram_index(&self) -> Option<usize> {
+        if !self.ram_enabled || self.rams.is_empty() {
+            return None;
+        }
+        Some(self.cur_ram as usize % self.rams.len())
+    }
 }
 
 impl BusIO for MBC5 {
     fn readu8(&self, addr: Addr) -> SResult<u8> {
-        // if addr == 0x65fd.into() {
-        //     panic!("read from 0x65fd, cur_rom: {:?}, secondary:  {:?}, ram_enabled: {:?}, use_secondary: {:?}", self.cur_rom, self.secondary, self.ram_enabled, self.use_secondary);
-        // }
         match addr.into() {
-            0x0000..0x4000 => { 
-                let index = 0;
-                self.roms[index].readu8(addr)
-            }
-            0x4000..0x8000 => {
-                let index = self.get_rom_number();
-                let index = index % self.roms.len(); // don't know if it's a good idea to use mod here
-                self.roms[index].readu8(addr) 
-            }
-            0xa000..0xc000 => {
-                if self.ram_enabled { 
-                    let index = self.cur_ram as usize;
-                    let index = index % self.rams.len(); // don't know if it's a good idea to use mod here
-                    println!("reading from ram: {}", index);
-                    self.rams[index].readu8(addr) 
-                } else {
-                    Ok(0xff)
-                }
-            }
+            a @ 0x0000..0x8000 => self.roms[self.rom_index(a)].readu8(addr),
+            0xa000..0xc000 => match self.ram_index() {
+                Some(index) => self.rams[index].readu8(addr),
+                None        => Ok(0xff),
+            },
             _              => Err(format!("MBC5 readu8 - invalid addr: {:x?}", addr).into())
         }
     }
@@ -88,15 +149,18 @@ impl BusIO for MBC5 {
             0x0000..0x2000 => self.ram_enabled = value & 0x0f == 0xa,
             0x2000..0x3000 => self.cur_rom_lower = value,
             0x3000..0x4000 => self.cur_rom_higher = value & 1,
-            0x4000..0x6000 => self.cur_ram = value & 0x0f,
+            0x4000..0x6000 => match self.rumble.as_mut() {
+                Some(rumble) => {
+                    rumble.set(value & RUMBLE_BIT != 0);
+                    self.cur_ram = value & 0x07;
+                }
+                None => self.cur_ram = value & 0x0f,
+            },
             0x6000..0x8000 => {}
             0xa000..0xc000 => {
-                if self.ram_enabled { 
-                    let index = self.cur_ram as usize;
-                    let index = index % self.rams.len(); // don't know if it's a good idea to use mod here
-                    println!("writing to ram: {}", index);
-                    self.rams[index].writeu8(addr, value)?; 
-                } 
+                if let Some(index) = self.ram_index() {
+                    self.rams[index].writeu8(addr, value)?;
+                }
             }
             _              => return Err(format!("MBC5 writeu8 - invalid addr: {:x?}", addr).into())
         };
@@ -117,14 +181,25 @@ impl BusIO for MBC5 {
         let value = value.to_le_bytes();
         self.writeu8(addr, value[0])?;
         self.writeu8(addr + 1.into(), value[1])?;
-        Ok(()) 
+        Ok(())
     }
 
-    fn as_slice(&self, _addr: Addr, _len: usize) -> SResult<&[u8]> {
-        unimplemented!() // expecting this to blow up 
+    // used by OAM DMA
+    fn as_slice(&self, addr: Addr, len: usize) -> SResult<&[u8]> {
+        match addr.into() {
+            a @ 0x0000..0x8000 => {
+                let offset = a as usize % ROM_BANK_SIZE;
+                Ok(&self.roms[self.rom_index(a)].0[offset..][..len])
+            }
+            0xa000..0xc000 => match self.ram_index() {
+                Some(index) => self.rams[index].as_slice(addr, len),
+                None        => Err(format!("MBC5 as_slice - RAM disabled: {:x?}", addr).into()),
+            },
+            _              => Err(format!("MBC5 as_slice - invalid addr: {:x?}", addr).into())
+        }
     }
 
     fn print_dbg(&self, _start: Addr, _len: u16) -> String {
         unimplemented!()
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,353 @@
+use crate::mmu::busio::{BusIO, SResult};
+use crate::util::{get_nth_bit, Addr};
+
+use super::rom_bank::{RomBank, ROM_BANK_SIZE};
+
+// https://gbdev.io/pandocs/MBC7.html
+//
+// Registers
+// ============================================================================
+// Address          Name            Usage notes
+// ============================================================================
+// 0x0000-0x1fff    RAM enable 1    0x0a enables
+// 0x2000-0x3fff    ROM bank        8 bits
+// 0x4000-0x5fff    RAM enable 2    0x40 enables, both have to be enabled to reach the registers below
+//
+// 0xa000-0xafff, mirrored every 0x100, the register is selected by bits 4-7 of the address
+// ============================================================================
+//  Ax0x    write 0x55 to erase the latched accelerometer values
+//  Ax1x    write 0xaa to latch the accelerometer, after erasing it
+//  Ax2x    X low
+//  Ax3x    X high
+//  Ax4x    Y low
+//  Ax5x    Y high
+//  Ax6x    reads 0x00
+//  Ax7x    reads 0xff
+//  Ax8x    EEPROM pins, bit 7: CS, bit 6: CLK, bit 1: DI, bit 0: DO (read only)
+
+const REG_ERASE: u16 = 0x0;
+const REG_LATCH: u16 = 0x1;
+const REG_X_LOW: u16 = 0x2;
+const REG_X_HIGH: u16 = 0x3;
+const REG_Y_LOW: u16 = 0x4;
+const REG_Y_HIGH: u16 = 0x5;
+const REG_ZERO: u16 = 0x6;
+const REG_EEPROM: u16 = 0x8;
+
+// latched value of a level cartridge, and how far it moves for 1g of tilt
+const ACCEL_CENTER: f32 = 0x81d0 as f32;
+const ACCEL_PER_G: f32 = 0x70 as f32;
+const ACCEL_ERASED: u16 = 0x8000;
+
+#[derive(Debug)]
+pub(crate) struct MBC7 {
+    roms: Vec<RomBank>,
+    eeprom: Eeprom,
+
+    cur_rom: u8,
+    ram_enabled_1: bool,
+    ram_enabled_2: bool,
+
+    // host tilt in g, x positive to the right, y positive towards the bottom of the screen
+    tilt: (f32, f32),
+    latched: (u16, u16),
+    erased: bool,
+}
+
+impl MBC7 {
+    pub(crate) fn new(raw: Vec<u8>, _mbc_mode: u8) -> Self {
+        Self {
+            roms: RomBank::split(&raw),
+            eeprom: Eeprom::new(),
+
+            cur_rom: 1,
+            ram_enabled_1: false,
+            ram_enabled_2: false,
+
+            tilt: (0.0, 0.0),
+            latched: (ACCEL_ERASED, ACCEL_ERASED),
+            erased: false,
+        }
+    }
+
+    pub(crate) fn set_tilt(&mut self, x: f32, y: f32) {
+        self.tilt = (x, y);
+    }
+
+    // the EEPROM, 128 16 bit words stored little endian
+    pub(crate) fn save_data(&self) -> Vec<u8> {
+        self.eeprom.words.iter().flat_map(|w| w.to_le_bytes()).collect()
+    }
+
+    pub(crate) fn load_save_data(&mut self, data: &[u8]) {
+        for (word, bytes) in self.eeprom.words.iter_mut().zip(data.chunks_exact(2)) {
+            *word = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+    }
+
+    fn rom_index(&self, addr: u16) -> usize {
+        let index = match addr {
+            0x0000..0x4000 => 0,
+            _              => self.cur_rom as usize,
+        };
+        index % self.roms.len()
+    }
+
+    fn registers_enabled(&self) -> bool {
+        self.ram_enabled_1 && self.ram_enabled_2
+    }
+
+    fn latch(&mut self) {
+        let (x, y) = self.tilt;
+        // the sensor is mounted so that tilting right lowers X
+        let to_reg = |v: f32| (ACCEL_CENTER + v * ACCEL_PER_G).clamp(0.0, u16::MAX as f32) as u16;
+        self.latched = (to_reg(-x), to_reg(y));
+    }
+}
+
+impl BusIO for MBC7 {
+    fn readu8(&self, addr: Addr) -> SResult<u8> {
+        match addr.into() {
+            a @ 0x0000..0x8000 => self.roms[self.rom_index(a)].readu8(addr),
+            a @ 0xa000..0xb000 if self.registers_enabled() => {
+                let (x, y) = self.latched;
+                let v = match (a >> 4) & 0xf {
+                    REG_X_LOW  => x as u8,
+                    REG_X_HIGH => (x >> 8) as u8,
+                    REG_Y_LOW  => y as u8,
+                    REG_Y_HIGH => (y >> 8) as u8,
+                    REG_ZERO   => 0x00,
+                    REG_EEPROM => self.eeprom.read(),
+                    _          => 0xff,
+                };
+                Ok(v)
+            }
+            0xa000..0xc000 => Ok(0xff),
+            _              => Err(format!("MBC7 readu8 - invalid addr: {:x?}", addr).into())
+        }
+    }
+
+    fn writeu8(&mut self, addr: Addr, value: u8) -> SResult<()> {
+        match addr.into() {
+            0x0000..0x2000 => self.ram_enabled_1 = value == 0x0a,
+            0x2000..0x4000 => self.cur_rom = value,
+            0x4000..0x6000 => self.ram_enabled_2 = value == 0x40,
+            0x6000..0x8000 => {}
+            a @ 0xa000..0xb000 if self.registers_enabled() => match (a >> 4) & 0xf {
+                REG_ERASE if value == 0x55 => {
+                    self.latched = (ACCEL_ERASED, ACCEL_ERASED);
+                    self.erased = true;
+                }
+                REG_LATCH if value == 0xaa && self.erased => {
+                    self.latch();
+                    self.erased = false;
+                }
+                REG_EEPROM => self.eeprom.write(value),
+                _          => {}
+            },
+            0xa000..0xc000 => {}
+            _              => return Err(format!("MBC7 writeu8 - invalid addr: {:x?}", addr).into())
+        };
+        Ok(())
+    }
+
+    fn readu16(&self, addr: Addr) -> SResult<u16> {
+        Ok(u16::from_le_bytes([
+            self.readu8(addr)?,
+            self.readu8(addr + 1.into())?
+        ]))
+    }
+
+    fn writeu16(&mut self, addr: Addr, value: u16) -> SResult<()> {
+        if addr > 0x0000.into() && addr < 0x8000.into() {
+            panic!("MBC7 writeu16 @ {:x?}", addr);
+        }
+        let value = value.to_le_bytes();
+        self.writeu8(addr, value[0])?;
+        self.writeu8(addr + 1.into(), value[1])?;
+        Ok(())
+    }
+
+    // used by OAM DMA
+    fn as_slice(&self, addr: Addr, len: usize) -> SResult<&[u8]> {
+        match addr.into() {
+            a @ 0x0000..0x8000 => {
+                let offset = a as usize % ROM_BANK_SIZE;
+                Ok(&self.roms[self.rom_index(a)].0[offset..][..len])
+            }
+            _              => Err(format!("MBC7 as_slice - invalid addr: {:x?}", addr).into())
+        }
+    }
+
+    fn print_dbg(&self, _start: Addr, _len: u16) -> String {
+        unimplemented!()
+    }
+}
+
+// 93LC56 serial EEPROM, 128 x 16 bits, bit banged through Ax8x
+// https://ww1.microchip.com/downloads/en/DeviceDoc/21794F.pdf
+//
+// Every command starts with a 1 bit, followed by a 2 bit opcode and 8 address bits (only 7 are used),
+// clocked in MSB first on the rising edge of CLK while CS is high.
+// ============================================================================
+// Command  Opcode  Address     Usage notes
+// ============================================================================
+// READ     10      A7-A0       shifts out a 0, then the 16 bit word
+// WRITE    01      A7-A0       followed by 16 data bits
+// ERASE    11      A7-A0       sets the word to 0xffff
+// EWEN     00      11xxxxxx    enables WRITE, ERASE, ERAL and WRAL
+// EWDS     00      00xxxxxx    disables them again
+// ERAL     00      10xxxxxx    sets every word to 0xffff
+// WRAL     00      01xxxxxx    followed by 16 data bits, written to every word
+//
+// Writes complete immediately, so DO always reports ready (1) when it isn't shifting out data.
+
+const EEPROM_WORDS: usize = 128;
+const EEPROM_CS: u8 = 7;
+const EEPROM_CLK: u8 = 6;
+const EEPROM_DI: u8 = 1;
+// start bit, opcode and address
+const COMMAND_BITS: u8 = 11;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EepromState {
+    // waiting for the start bit, and then the rest of the command
+    Command,
+    // shifting in the 16 data bits of a WRITE or WRAL
+    Data { command: u16 },
+    // shifting out a word, and ignoring DI until CS goes low
+    Read,
+}
+
+#[derive(Debug)]
+struct Eeprom {
+    words: [u16; EEPROM_WORDS],
+    write_enabled: bool,
+
+    cs: bool,
+    clk: bool,
+    // DO
+    out: bool,
+
+    state: EepromState,
+    shift_in: u16,
+    bits_in: u8,
+    shift_out: u16,
+}
+
+impl Eeprom {
+    fn new() -> Self {
+        Self {
+            words: [0xffff; EEPROM_WORDS],
+            write_enabled: false,
+            cs: false,
+            clk: false,
+            out: true,
+            state: EepromState::Command,
+            shift_in: 0,
+            bits_in: 0,
+            shift_out: 0,
+        }
+    }
+
+    fn read(&self) -> u8 {
+        (self.cs as u8) << EEPROM_CS | (self.clk as u8) << EEPROM_CLK | self.out as u8
+    }
+
+    fn write(&mut self, value: u8) {
+        let cs = get_nth_bit(value, EEPROM_CS);
+        let clk = get_nth_bit(value, EEPROM_CLK);
+        let di = get_nth_bit(value, EEPROM_DI);
+
+        if !cs {
+            // deselecting aborts whatever was going on
+            self.state = EepromState::Command;
+            self.shift_in = 0;
+            self.bits_in = 0;
+            self.out = true;
+        } else if clk && !self.clk {
+            self.rising_edge(di);
+        }
+        self.cs = cs;
+        self.clk = clk;
+    }
+
+    fn rising_edge(&mut self, di: bool) {
+        match self.state {
+            EepromState::Read => {
+                self.out = self.shift_out & 0x8000 != 0;
+                self.shift_out <<= 1;
+            }
+            EepromState::Command => {
+                // leading zeros before the start bit are ignored
+                if self.bits_in == 0 && !di {
+                    return;
+                }
+                self.shift_in = self.shift_in << 1 | di as u16;
+                self.bits_in += 1;
+                if self.bits_in == COMMAND_BITS {
+                    let command = self.shift_in;
+                    self.shift_in = 0;
+                    self.bits_in = 0;
+                    self.command(command);
+                }
+            }
+            EepromState::Data { command } => {
+                self.shift_in = self.shift_in << 1 | di as u16;
+                self.bits_in += 1;
+                if self.bits_in == 16 {
+                    let data = self.shift_in;
+                    self.shift_in = 0;
+                    self.bits_in = 0;
+                    self.state = EepromState::Command;
+                    self.write_data(command, data);
+                }
+            }
+        }
+    }
+
+    fn command(&mut self, command: u16) {
+        let opcode = (command >> 8) & 0b11;
+        let addr = command as u8;
+        let word = (addr & 0x7f) as usize;
+        match opcode {
+            0b10 => {
+                self.shift_out = self.words[word];
+                // dummy 0 bit before the data
+                self.out = false;
+                self.state = EepromState::Read;
+            }
+            0b01 => self.state = EepromState::Data { command },
+            0b11 => {
+                if self.write_enabled {
+                    self.words[word] = 0xffff;
+                }
+                self.out = true;
+            }
+            _ => match addr >> 6 {
+                0b11 => self.write_enabled = true,
+                0b00 => self.write_enabled = false,
+                0b10 => {
+                    if self.write_enabled {
+                        self.words = [0xffff; EEPROM_WORDS];
+                    }
+                    self.out = true;
+                }
+                _    => self.state = EepromState::Data { command },
+            },
+        }
+    }
+
+    fn write_data(&mut self, command: u16, data: u16) {
+        self.out = true;
+        if !self.write_enabled {
+            return;
+        }
+        let opcode = (command >> 8) & 0b11;
+        match opcode {
+            0b01 => self.words[(command & 0x7f) as usize] = data,
+            // WRAL
+            _    => self.words = [data; EEPROM_WORDS],
+        }
+    }
+}
@@ -0,0 +1,392 @@
+use crate::header::RAM_SIZE_ADDR;
+use crate::mmu::busio::{BusIO, SResult};
+use crate::util::Addr;
+
+use super::rom_bank::{RomBank, ROM_BANK_SIZE};
+use super::ram_bank::RamBank;
+
+// https://gbdev.io/pandocs/MMM01.html
+//
+// Multicart MBC, which starts out "unmapped", showing the menu in the last 32 KiB of the ROM.
+// The menu sets up the outer banks of the selected game, and then maps it, which locks them.
+// After that, the game sees an MBC1 restricted to its own banks.
+//
+// Registers (write only), the bits marked with * can only be written while unmapped
+// ============================================================================
+// Address          Name            Usage notes
+// ============================================================================
+// 0x0000-0x1fff    RAM enable      bits 0-3: 0x0a enables the RAM
+//                                  * bits 4-5: RAM bank mask, bit 6: map the game (and lock)
+// 0x2000-0x3fff    ROM bank        bits 0-4: ROM bank bits 0-4, 0 is treated as 1
+//                                  * bits 5-6: ROM bank bits 5-6
+// 0x4000-0x5fff    RAM bank        bits 0-1: RAM bank bits 0-1
+//                                  * bits 2-3: RAM bank bits 2-3, bits 4-5: ROM bank bits 7-8,
+//                                  * bit 6: lock the banking mode
+// 0x6000-0x7fff    banking mode    bit 0: MBC1 banking mode, unless locked
+//                                  * bits 2-5: ROM bank mask, for ROM bank bits 1-4
+//                                  * bit 6: multiplex, ROM bank bits 5-6 and RAM bank bits 0-1 swap places
+//
+// Bits set in the masks keep the value the menu wrote, so the game can't bank outside of its own space.
+
+// the menu's header is at the start of the last 32 KiB
+pub(super) const MENU_SIZE: usize = 2 * ROM_BANK_SIZE;
+
+// what the unmapped MMM01 shows at 0x0000-0x3fff and 0x4000-0x7fff, all bank lines pulled high
+const UNMAPPED_BANK_0: usize = 0x1fe;
+const UNMAPPED_BANK_1: usize = 0x1ff;
+
+#[derive(Debug)]
+pub(crate) struct MMM01 {
+    roms: Vec<RomBank>,
+    rams: Vec<RamBank>,
+
+    mapped: bool,
+    ram_enabled: bool,
+
+    rom_low: u8,  // 5 bit
+    rom_mid: u8,  // 2 bit
+    rom_high: u8, // 2 bit
+    ram_low: u8,  // 2 bit
+    ram_high: u8, // 2 bit
+
+    rom_mask: u8, // bits 1-4 of rom_low
+    ram_mask: u8, // bits 0-1 of ram_low
+
+    use_secondary: bool,
+    mode_locked: bool,
+    multiplex: bool,
+}
+
+impl MMM01 {
+    pub(crate) fn new(raw: Vec<u8>, _mbc_mode: u8) -> Self {
+        let menu = raw.len().saturating_sub(MENU_SIZE);
+        let num_rams = RamBank::count(raw[menu + RAM_SIZE_ADDR]);
+
+        Self {
+            roms: RomBank::split(&raw),
+            rams: vec![RamBank::new(); num_rams],
+
+            mapped: false,
+            ram_enabled: false,
+
+            rom_low: 0,
+            rom_mid: 0,
+            rom_high: 0,
+            ram_low: 0,
+            ram_high: 0,
+
+            rom_mask: 0,
+            ram_mask: 0,
+
+            use_secondary: false,
+            mode_locked: false,
+            multiplex: false,
+        }
+    }
+
+    pub(crate) fn save_data(&self) -> Vec<u8> {
+        RamBank::dump(&self.rams)
+    }
+
+    pub(crate) fn load_save_data(&mut self, data: &[u8]) {
+        RamBank::restore(&mut self.rams, data);
+    }
+
+    // keeps the bits of `old` that are set in `mask` once the game is mapped
+    fn masked(&self, old: u8, new: u8, mask: u8) -> u8 {
+        if self.mapped {
+            old & mask | new & !mask
+        } else {
+            new
+        }
+    }
+
+    // the bits that come from the MBC1 secondary register as the game sees it
+    fn secondary(&self) -> u8 {
+        if self.multiplex { self.ram_low } else { self.rom_mid }
+    }
+
+    fn rom_index(&self, addr: u16) -> usize {
+        let index = match addr {
+            // unmapped, with a ROM that isn't 8 MiB this wraps to the last two banks
+            0x0000..0x4000 if !self.mapped => UNMAPPED_BANK_0,
+            _ if !self.mapped              => UNMAPPED_BANK_1,
+            0x0000..0x4000 => {
+                let mid = if self.multiplex && !self.use_secondary { 0 } else { self.secondary() };
+                let low = self.rom_low & (self.rom_mask << 1);
+                (self.rom_high as usize) << 7 | (mid as usize) << 5 | low as usize
+            }
+            _ => {
+                // the zero check only looks at the bits the game can change
+                let mut low = self.rom_low;
+                if low & !(self.rom_mask << 1) & 0x1f == 0 {
+                    low |= 1;
+                }
+                (self.rom_high as usize) << 7 | (self.secondary() as usize) << 5 | low as usize
+            }
+        };
+        index % self.roms.len()
+    }
+
+    fn ram_index(&self) -> Option<usize> {
+        if !self.ram_enabled || self.rams.is_empty() {
+            return None;
+        }
+        let low = match (self.multiplex, self.use_secondary) {
+            (true, _)      => self.rom_mid,
+            (false, true)  => self.ram_low,
+            (false, false) => self.ram_low & self.ram_mask,
+        };
+        Some(((self.ram_high << 2 | low) as usize) % self.rams.len())
+    }
+}
+
+impl BusIO for MMM01 {
+    fn readu8(&self, addr: Addr) -> SResult<u8> {
+        match addr.into() {
+            a @ 0x0000..0x8000 => self.roms[self.rom_index(a)].readu8(addr),
+            0xa000..0xc000 => match self.ram_index() {
+                Some(index) => self.rams[index].readu8(addr),
+                None        => Ok(0xff),
+            },
+            _              => Err(format!("MMM01 readu8 - invalid addr: {:x?}", addr).into())
+        }
+    }
+
+    fn writeu8(&mut self, addr: Addr, value: u8) -> SResult<()> {
+        match addr.into() {
+            0x0000..0x2000 => {
+                self.ram_enabled = value & 0x0f == 0xa;
+                if !self.mapped {
+                    self.ram_mask = (value >> 4) & 0b11;
+                    self.mapped = value & 0x40 != 0;
+                }
+            }
+            0x2000..0x4000 => {
+                self.rom_low = self.masked(self.rom_low, value & 0b1_1111, self.rom_mask << 1);
+                if !self.mapped {
+                    self.rom_mid = (value >> 5) & 0b11;
+                }
+            }
+            0x4000..0x6000 => {
+                self.ram_low = self.masked(self.ram_low, value & 0b11, self.ram_mask);
+                if !self.mapped {
+                    self.ram_high = (value >> 2) & 0b11;
+                    self.rom_high = (value >> 4) & 0b11;
+                    self.mode_locked = value & 0x40 != 0;
+                }
+            }
+            0x6000..0x8000 => {
+                if !self.mode_locked {
+                    self.use_secondary = value & 1 == 1;
+                }
+                if !self.mapped {
+                    self.rom_mask = (value >> 2) & 0b1111;
+                    self.multiplex = value & 0x40 != 0;
+                }
+            }
+            0xa000..0xc000 => {
+                if let Some(index) = self.ram_index() {
+                    self.rams[index].writeu8(addr, value)?;
+                }
+            }
+            _              => return Err(format!("MMM01 writeu8 - invalid addr: {:x?}", addr).into())
+        };
+        Ok(())
+    }
+
+    fn readu16(&self, addr: Addr) -> SResult<u16> {
+        Ok(u16::from_le_bytes([
+            self.readu8(addr)?,
+            self.readu8(addr + 1.into())?
+        ]))
+    }
+
+    fn writeu16(&mut self, addr: Addr, value: u16) -> SResult<()> {
+        if addr > 0x0000.into() && addr < 0x8000.into() {
+            panic!("MMM01 writeu16 @ {:x?}", addr);
+        }
+        let value = value.to_le_bytes();
+        self.writeu8(addr, value[0])?;
+        self.writeu8(addr + 1.into(), value[1])?;
+        Ok(())
+    }
+
+    // used by OAM DMA
+    fn as_slice(&self, addr: Addr, len: usize) -> SResult<&[u8]> {
+        match addr.into() {
+            a @ 0x0000..0x8000 => {
+                let offset = a as usize % ROM_BANK_SIZE;
+                Ok(&self.roms[self.rom_index(a)].0[offset..][..len])
+            }
+            0xa000..0xc000 => match self.ram_index() {
+                Some(index) => self.rams[index].as_slice(addr, len),
+                None        => Err(format!("MMM01 as_slice - RAM not mapped: {:x?}", addr).into()),
+            },
+            _              => Err(format!("MMM01 as_slice - invalid addr: {:x?}", addr).into())
+        }
+    }
+
+    fn print_dbg(&self, _start: Addr, _len: u16) -> String {
+        unimplemented!()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::{LOGO_ADDR, MBC_MODE_ADDR, NINTENDO_LOGO};
+    use crate::mmu::cartridge::ram_bank::RAM_BANK_SIZE;
+    use crate::mmu::cartridge::test_rom;
+
+    const MMM01_RAM_BATTERY: u8 = 0x0d;
+    const BANKS: usize = 128;
+
+    // 2 MiB, with the menu's header in the last 32 KiB (banks 126 and 127), and 32 KiB of RAM
+    fn mmm01() -> MMM01 {
+        let mut raw = test_rom(BANKS, 0x01, 0x00);
+        let menu = raw.len() - MENU_SIZE;
+        raw[menu + MBC_MODE_ADDR] = MMM01_RAM_BATTERY;
+        raw[menu + RAM_SIZE_ADDR] = 0x03;
+        raw[menu + LOGO_ADDR..][..NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+        MMM01::new(raw, MMM01_RAM_BATTERY)
+    }
+
+    fn write(mbc: &mut MMM01, addr: u16, value: u8) {
+        mbc.writeu8(addr.into(), value).unwrap();
+    }
+
+    // the banks at 0x0000-0x3fff and 0x4000-0x7fff
+    fn banks(mbc: &MMM01) -> (u8, u8) {
+        (mbc.readu8(0x0000.into()).unwrap(), mbc.readu8(0x4000.into()).unwrap())
+    }
+
+    // every bank the game can map at 0x4000-0x7fff through the ROM bank register
+    fn reachable(mbc: &mut MMM01) -> Vec<u8> {
+        let mut banks: Vec<u8> = (0..=0xff)
+            .map(|value| {
+                write(mbc, 0x2000, value);
+                self::banks(mbc).1
+            })
+            .collect();
+        banks.sort();
+        banks.dedup();
+        banks
+    }
+
+    #[test]
+    fn menu_first() {
+        let mut mbc = mmm01();
+        assert_eq!(banks(&mbc), (126, 127));
+        // the bank registers can be set up, but nothing changes until the game is mapped
+        write(&mut mbc, 0x2000, 0x05);
+        write(&mut mbc, 0x4000, 0x10);
+        assert_eq!(banks(&mbc), (126, 127));
+    }
+
+    #[test]
+    fn rom_mask() {
+        // an 8 bank game at bank 16: bits 3-4 of the ROM bank are the menu's, bits 0-2 the game's
+        let mut mbc = mmm01();
+        write(&mut mbc, 0x2000, 0x10);
+        write(&mut mbc, 0x6000, 0b1100 << 2);
+        write(&mut mbc, 0x0000, 0x40);
+        assert_eq!(banks(&mbc), (16, 17));
+
+        // bank 0 can't be mapped at 0x4000-0x7fff, the game's bits are checked for 0
+        write(&mut mbc, 0x2000, 0x00);
+        assert_eq!(banks(&mbc), (16, 17));
+        write(&mut mbc, 0x2000, 0x05);
+        assert_eq!(banks(&mbc), (16, 21));
+        assert_eq!(reachable(&mut mbc), (17..=23).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn outer_banks() {
+        // a 32 bank game at bank 96: bits 5-6 of the ROM bank are set by the menu only
+        let mut mbc = mmm01();
+        write(&mut mbc, 0x2000, 0x60);
+        write(&mut mbc, 0x0000, 0x40);
+        assert_eq!(banks(&mbc), (96, 97));
+        assert_eq!(reachable(&mut mbc), (97..=127).collect::<Vec<_>>());
+
+        // and through the MBC1 secondary register it can't get out of them either
+        write(&mut mbc, 0x4000, 0x03);
+        write(&mut mbc, 0x6000, 1);
+        assert_eq!(banks(&mbc).0, 96);
+    }
+
+    #[test]
+    fn locked_once_mapped() {
+        let mut mbc = mmm01();
+        write(&mut mbc, 0x2000, 0x20);
+        write(&mut mbc, 0x6000, 0b1111 << 2);
+        write(&mut mbc, 0x0000, 0x40);
+        assert_eq!(banks(&mbc), (32, 33));
+
+        // the mask, the outer banks and the mapping can't be changed by the game
+        write(&mut mbc, 0x6000, 0x00);
+        write(&mut mbc, 0x4000, 0x30);
+        write(&mut mbc, 0x2000, 0x44);
+        write(&mut mbc, 0x0000, 0x00);
+        assert_eq!(banks(&mbc), (32, 33));
+        assert_eq!(reachable(&mut mbc), [33]);
+    }
+
+    // writes 0x42 to RAM bank `bank` as the game selects it, and returns the bank that got it
+    fn ram_write(mbc: &mut MMM01, bank: u8) -> usize {
+        write(mbc, 0x4000, bank);
+        write(mbc, 0xa000, 0x42);
+        let save = mbc.save_data();
+        let index = (0..4).find(|i| save[i * RAM_BANK_SIZE] == 0x42).unwrap();
+        mbc.load_save_data(&[0; 4 * RAM_BANK_SIZE]);
+        index
+    }
+
+    #[test]
+    fn mode_lock() {
+        // mode 1 gives the game the RAM banks, mode 0 only those in the mask
+        let mut mbc = mmm01();
+        write(&mut mbc, 0x0000, 0x4a);
+        write(&mut mbc, 0x6000, 1);
+        assert_eq!(ram_write(&mut mbc, 2), 2);
+        write(&mut mbc, 0x6000, 0);
+        assert_eq!(ram_write(&mut mbc, 2), 0);
+
+        // locked by the menu, the game stays in mode 0
+        let mut mbc = mmm01();
+        write(&mut mbc, 0x4000, 0x40);
+        write(&mut mbc, 0x0000, 0x4a);
+        write(&mut mbc, 0x6000, 1);
+        assert_eq!(ram_write(&mut mbc, 2), 0);
+    }
+
+    #[test]
+    fn ram_mask() {
+        // RAM bank 1 for the game, bit 0 is the menu's
+        let mut mbc = mmm01();
+        write(&mut mbc, 0x4000, 0x01);
+        write(&mut mbc, 0x6000, 1);
+        write(&mut mbc, 0x0000, 0x5a);
+        assert_eq!(ram_write(&mut mbc, 0), 1);
+        assert_eq!(ram_write(&mut mbc, 2), 3);
+    }
+
+    #[test]
+    fn multiplex() {
+        // ROM bank bits 5-6 come from the RAM bank register, and the RAM bank from the menu's bits
+        let mut mbc = mmm01();
+        write(&mut mbc, 0x2000, 0x20);
+        write(&mut mbc, 0x6000, 0x40);
+        write(&mut mbc, 0x0000, 0x4a);
+        write(&mut mbc, 0x4000, 2);
+        write(&mut mbc, 0x2000, 0x03);
+        assert_eq!(banks(&mbc), (0, 0x43));
+        assert_eq!(ram_write(&mut mbc, 3), 1);
+
+        // in mode 1 they move 0x0000-0x3fff too
+        write(&mut mbc, 0x4000, 2);
+        write(&mut mbc, 0x6000, 1);
+        assert_eq!(banks(&mbc), (0x40, 0x43));
+    }
+}
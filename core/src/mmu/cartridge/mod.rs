@@ -1,34 +1,296 @@
+mod huc1;
+mod huc3;
 mod mbc0;
 mod mbc1;
+mod mbc2;
+mod mbc3;
 mod mbc5;
+mod mbc7;
+mod mmm01;
+mod pocket_camera;
 mod ram_bank;
 mod rom_bank;
+mod rtc;
+
+use std::error::Error;
 
 use super::busio::{BusIO, SResult};
+use crate::camera::ImageSource;
+use crate::clock::TimeSource;
+use crate::header::{CartridgeType, Mbc, LOGO_ADDR, MBC_MODE_ADDR, NINTENDO_LOGO};
+use crate::infrared::InfraredDevice;
+use crate::mapper::Mapper;
 use crate::util::Addr;
+use huc1::HuC1;
+use huc3::HuC3;
 use mbc0::MBC0;
 use mbc1::MBC1;
+use mbc2::MBC2;
+use mbc3::MBC3;
 use mbc5::MBC5;
+use mbc7::MBC7;
+use mmm01::{MMM01, MENU_SIZE};
+use pocket_camera::PocketCamera;
+pub(crate) use huc3::ToneCallback;
+pub(crate) use mbc5::RumbleCallback;
+
+// The built-in MBCs as mappers, on top of their bus interface. Addresses come from the
+// cartridge's side of the bus, so the MBCs don't fail on them.
+macro_rules! builtin_mapper {
+    ($mbc:ident $(, $($extra:tt)*)?) => {
+        impl Mapper for $mbc {
+            fn read_rom(&self, addr: u16) -> u8 {
+                self.readu8(addr.into()).unwrap_or(0xff)
+            }
+
+            fn write_rom(&mut self, addr: u16, value: u8) {
+                let _ = self.writeu8(addr.into(), value);
+            }
+
+            fn read_ram(&self, addr: u16) -> u8 {
+                self.readu8(addr.into()).unwrap_or(0xff)
+            }
+
+            fn write_ram(&mut self, addr: u16, value: u8) {
+                let _ = self.writeu8(addr.into(), value);
+            }
+
+            fn save_data(&self) -> Vec<u8> {
+                $mbc::save_data(self)
+            }
+
+            fn load_save_data(&mut self, data: &[u8]) {
+                $mbc::load_save_data(self, data)
+            }
+
+            $($($extra)*)?
+        }
+    };
+}
+
+builtin_mapper!(MBC0);
+builtin_mapper!(MBC1);
+builtin_mapper!(MBC2);
+builtin_mapper!(MBC3);
+builtin_mapper!(MBC5);
+builtin_mapper!(MBC7);
+builtin_mapper!(MMM01);
+builtin_mapper!(HuC1);
+builtin_mapper!(HuC3);
+builtin_mapper!(PocketCamera,
+    fn tick(&mut self, cpu_ticks: u64) {
+        PocketCamera::tick(self, cpu_ticks)
+    }
+);
+
+// cartridge types with a battery, that keep their RAM (or RTC) when switched off
+pub(crate) fn has_battery(mbc_mode: u8) -> bool {
+    CartridgeType::from(mbc_mode).battery
+}
+
+// Multicarts put every game at a 256 KiB boundary, each with its own header,
+// so a copy of the logo at one of them is a game (or the menu).
+const MULTICART_GAME_SIZE: usize = 0x40000;
+
+fn has_logo(raw: &[u8], offset: usize) -> bool {
+    raw.get(offset + LOGO_ADDR..offset + LOGO_ADDR + NINTENDO_LOGO.len()) == Some(&NINTENDO_LOGO[..])
+}
+
+// offsets of the headers at 0x40000 intervals that have the logo
+pub(crate) fn game_offsets(raw: &[u8]) -> Vec<usize> {
+    (0..raw.len())
+        .step_by(MULTICART_GAME_SIZE)
+        .filter(|&offset| has_logo(raw, offset))
+        .collect()
+}
+
+// MMM01, MMM01+RAM, MMM01+RAM+BATTERY
+fn is_mmm01(raw: &[u8], offset: usize) -> bool {
+    matches!(raw.get(offset + MBC_MODE_ADDR), Some(0x0b..=0x0d)) && has_logo(raw, offset)
+}
+
+// where the MMM01 menu is: the last 32 KiB, as on the cartridge, or the start, as some dumps have it
+fn mmm01_menu(raw: &[u8]) -> Option<usize> {
+    let last = raw.len().checked_sub(MENU_SIZE)?;
+    if is_mmm01(raw, last) {
+        Some(last)
+    } else if is_mmm01(raw, 0) && raw.get(MENU_SIZE..).map_or(false, |games| !game_offsets(games).is_empty()) {
+        Some(0)
+    } else {
+        None
+    }
+}
+
+// where the header the cartridge goes by is, the menu's on MMM01 multicarts
+pub(crate) fn header_offset(raw: &[u8]) -> usize {
+    mmm01_menu(raw).unwrap_or(0)
+}
+
+// the cartridge type, from the menu's header on MMM01 multicarts
+pub(crate) fn mbc_mode(raw: &[u8]) -> u8 {
+    raw[header_offset(raw) + MBC_MODE_ADDR]
+}
+
+type Constructor = fn(Vec<u8>, u8) -> Cartridge;
 
-pub(crate) const MBC_MODE_ADDR: usize = 0x147;
+// How a cartridge with the MBC is built from its ROM and cartridge type, None if it isn't built in.
+// This is what decides which MBCs are supported.
+pub(crate) fn constructor(mbc: Mbc) -> Option<Constructor> {
+    let new: Constructor = match mbc {
+        Mbc::RomOnly      => |raw, _| Cartridge::MBC0(MBC0::new(raw)),
+        Mbc::MBC1         => |raw, mbc_mode| Cartridge::MBC1(MBC1::new(raw, mbc_mode)),
+        Mbc::MBC2         => |raw, mbc_mode| Cartridge::MBC2(MBC2::new(raw, mbc_mode)),
+        Mbc::MMM01        => |mut raw, mbc_mode| {
+            // the MMM01 boots from the end of the ROM, move a menu dumped first to where it's expected
+            if mmm01_menu(&raw) == Some(0) {
+                raw.rotate_left(MENU_SIZE);
+            }
+            Cartridge::MMM01(MMM01::new(raw, mbc_mode))
+        },
+        Mbc::MBC3         => |raw, mbc_mode| Cartridge::MBC3(MBC3::new(raw, mbc_mode)),
+        Mbc::MBC5         => |raw, mbc_mode| Cartridge::MBC5(MBC5::new(raw, mbc_mode)),
+        Mbc::MBC7         => |raw, mbc_mode| Cartridge::MBC7(MBC7::new(raw, mbc_mode)),
+        Mbc::PocketCamera => |raw, mbc_mode| Cartridge::PocketCamera(PocketCamera::new(raw, mbc_mode)),
+        Mbc::HuC3         => |raw, mbc_mode| Cartridge::HuC3(HuC3::new(raw, mbc_mode)),
+        Mbc::HuC1         => |raw, mbc_mode| Cartridge::HuC1(HuC1::new(raw, mbc_mode)),
+        Mbc::MBC6 | Mbc::TAMA5 | Mbc::Unknown(_) => return None,
+    };
+    Some(new)
+}
 
 #[derive(Debug)]
 #[non_exhaustive]
 pub(crate) enum Cartridge {
     MBC0(MBC0), // No MBC
     MBC1(MBC1),
+    MBC2(MBC2),
+    MBC3(MBC3),
     MBC5(MBC5),
+    MBC7(MBC7),
+    MMM01(MMM01),
+    PocketCamera(PocketCamera),
+    HuC1(HuC1),
+    HuC3(HuC3),
+    // one that isn't built in, see Machine::with_mapper
+    Custom(Box<dyn Mapper>),
 }
 
 impl Cartridge {
     pub(crate) fn new(raw: Vec<u8>) -> Self {
-        let mbc_mode = raw[MBC_MODE_ADDR];
+        let mbc_mode = mbc_mode(&raw);
         println!("mbc mode: {:x}", mbc_mode);
-        match mbc_mode {
-            0 => Cartridge::MBC0(MBC0::new(raw)),
-            1 | 2 | 3 => Cartridge::MBC1(MBC1::new(raw, mbc_mode)),
-            19 => Cartridge::MBC5(MBC5::new(raw, mbc_mode)),
-            _ => panic!("unsupported MBC type: {}", mbc_mode)
+        match constructor(CartridgeType::from(mbc_mode).mbc) {
+            Some(new) => new(raw, mbc_mode),
+            None      => panic!("unsupported MBC type: {}", mbc_mode),
+        }
+    }
+
+    fn mapper(&self) -> &dyn Mapper {
+        match self {
+            Self::MBC0(mbc0) => mbc0,
+            Self::MBC1(mbc1) => mbc1,
+            Self::MBC2(mbc2) => mbc2,
+            Self::MBC3(mbc3) => mbc3,
+            Self::MBC5(mbc5) => mbc5,
+            Self::MBC7(mbc7) => mbc7,
+            Self::MMM01(mmm01) => mmm01,
+            Self::HuC1(huc1) => huc1,
+            Self::PocketCamera(camera) => camera,
+            Self::HuC3(huc3) => huc3,
+            Self::Custom(mapper) => mapper.as_ref(),
+        }
+    }
+
+    fn mapper_mut(&mut self) -> &mut dyn Mapper {
+        match self {
+            Self::MBC0(mbc0) => mbc0,
+            Self::MBC1(mbc1) => mbc1,
+            Self::MBC2(mbc2) => mbc2,
+            Self::MBC3(mbc3) => mbc3,
+            Self::MBC5(mbc5) => mbc5,
+            Self::MBC7(mbc7) => mbc7,
+            Self::MMM01(mmm01) => mmm01,
+            Self::HuC1(huc1) => huc1,
+            Self::PocketCamera(camera) => camera,
+            Self::HuC3(huc3) => huc3,
+            Self::Custom(mapper) => mapper.as_mut(),
+        }
+    }
+
+    // external RAM (and the RTC) in the usual .sav layout
+    pub(crate) fn save_data(&self) -> Vec<u8> {
+        self.mapper().save_data()
+    }
+
+    pub(crate) fn load_save_data(&mut self, data: &[u8]) {
+        self.mapper_mut().load_save_data(data);
+    }
+
+    // registers and RAM, for the mappers that support save states
+    pub(crate) fn save_state(&self) -> Option<Vec<u8>> {
+        self.mapper().save_state()
+    }
+
+    pub(crate) fn load_state(&mut self, state: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.mapper_mut().load_state(state)
+    }
+
+    // only MBC7 has an accelerometer
+    pub(crate) fn set_tilt(&mut self, x: f32, y: f32) {
+        if let Self::MBC7(mbc7) = self {
+            mbc7.set_tilt(x, y);
+        }
+    }
+
+    // only rumble cartridges call it, with the new state of the motor whenever it changes
+    pub(crate) fn set_rumble_callback(&mut self, callback: RumbleCallback) {
+        if let Self::MBC5(mbc5) = self {
+            mbc5.set_rumble_callback(callback);
+        }
+    }
+
+    // only cartridges with a real-time clock use it
+    pub(crate) fn set_time_source(&mut self, source: Box<dyn TimeSource>) {
+        match self {
+            Self::MBC3(mbc3) => mbc3.set_time_source(source),
+            Self::HuC3(huc3) => huc3.set_time_source(source),
+            _ => {}
+        }
+    }
+
+    // only the Pocket Camera has an image sensor
+    pub(crate) fn set_image_source(&mut self, source: Option<Box<dyn ImageSource>>) {
+        if let Self::PocketCamera(camera) = self {
+            camera.set_image_source(source);
+        }
+    }
+
+    // for MBCs that keep time in cpu ticks
+    pub(crate) fn tick(&mut self, cpu_ticks: u64) {
+        self.mapper_mut().tick(cpu_ticks);
+    }
+
+    // only HuC3 has a speaker, called with the tone it was told to play
+    pub(crate) fn set_tone_callback(&mut self, callback: ToneCallback) {
+        if let Self::HuC3(huc3) = self {
+            huc3.set_tone_callback(callback);
+        }
+    }
+
+    // HuC1 and HuC3 have an IR port, returns the device that was attached before
+    pub(crate) fn attach_infrared(&mut self, device: Box<dyn InfraredDevice>) -> Option<Box<dyn InfraredDevice>> {
+        match self {
+            Self::HuC1(huc1) => huc1.ir.attach(device),
+            Self::HuC3(huc3) => huc3.ir.attach(device),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn detach_infrared(&mut self) -> Option<Box<dyn InfraredDevice>> {
+        match self {
+            Self::HuC1(huc1) => huc1.ir.detach(),
+            Self::HuC3(huc3) => huc3.ir.detach(),
+            _ => None,
         }
     }
 }
@@ -38,8 +300,19 @@ impl BusIO for Cartridge {
         match self {
             Self::MBC0(mbc0) => mbc0.readu8(addr),
             Self::MBC1(mbc1) => mbc1.readu8(addr),
+            Self::MBC2(mbc2) => mbc2.readu8(addr),
+            Self::MBC3(mbc3) => mbc3.readu8(addr),
             Self::MBC5(mbc5) => mbc5.readu8(addr),
-            _ => unimplemented!()
+            Self::MBC7(mbc7) => mbc7.readu8(addr),
+            Self::MMM01(mmm01) => mmm01.readu8(addr),
+            Self::HuC1(huc1) => huc1.readu8(addr),
+            Self::PocketCamera(camera) => camera.readu8(addr),
+            Self::HuC3(huc3) => huc3.readu8(addr),
+            Self::Custom(mapper) => match addr.into() {
+                a @ 0x0000..0x8000 => Ok(mapper.read_rom(a)),
+                a @ 0xa000..0xc000 => Ok(mapper.read_ram(a)),
+                _                  => Err(format!("Mapper readu8 - invalid addr: {:x?}", addr).into()),
+            },
         }
     }
 
@@ -47,8 +320,22 @@ impl BusIO for Cartridge {
         match self {
             Self::MBC0(mbc0) => mbc0.writeu8(addr, value),
             Self::MBC1(mbc1) => mbc1.writeu8(addr, value),
+            Self::MBC2(mbc2) => mbc2.writeu8(addr, value),
+            Self::MBC3(mbc3) => mbc3.writeu8(addr, value),
             Self::MBC5(mbc5) => mbc5.writeu8(addr, value),
-            _ => unimplemented!()
+            Self::MBC7(mbc7) => mbc7.writeu8(addr, value),
+            Self::MMM01(mmm01) => mmm01.writeu8(addr, value),
+            Self::HuC1(huc1) => huc1.writeu8(addr, value),
+            Self::PocketCamera(camera) => camera.writeu8(addr, value),
+            Self::HuC3(huc3) => huc3.writeu8(addr, value),
+            Self::Custom(mapper) => {
+                match addr.into() {
+                    a @ 0x0000..0x8000 => mapper.write_rom(a, value),
+                    a @ 0xa000..0xc000 => mapper.write_ram(a, value),
+                    _                  => return Err(format!("Mapper writeu8 - invalid addr: {:x?}", addr).into()),
+                };
+                Ok(())
+            }
         }
     }
 
@@ -56,8 +343,18 @@ impl BusIO for Cartridge {
         match self {
             Self::MBC0(mbc0) => mbc0.readu16(addr),
             Self::MBC1(mbc1) => mbc1.readu16(addr),
+            Self::MBC2(mbc2) => mbc2.readu16(addr),
+            Self::MBC3(mbc3) => mbc3.readu16(addr),
             Self::MBC5(mbc5) => mbc5.readu16(addr),
-            _ => unimplemented!()
+            Self::MBC7(mbc7) => mbc7.readu16(addr),
+            Self::MMM01(mmm01) => mmm01.readu16(addr),
+            Self::HuC1(huc1) => huc1.readu16(addr),
+            Self::PocketCamera(camera) => camera.readu16(addr),
+            Self::HuC3(huc3) => huc3.readu16(addr),
+            Self::Custom(_) => Ok(u16::from_le_bytes([
+                self.readu8(addr)?,
+                self.readu8(addr + 1.into())?
+            ])),
         }
     }
 
@@ -65,8 +362,19 @@ impl BusIO for Cartridge {
         match self {
             Self::MBC0(mbc0) => mbc0.writeu16(addr, value),
             Self::MBC1(mbc1) => mbc1.writeu16(addr, value),
+            Self::MBC2(mbc2) => mbc2.writeu16(addr, value),
+            Self::MBC3(mbc3) => mbc3.writeu16(addr, value),
             Self::MBC5(mbc5) => mbc5.writeu16(addr, value),
-            _ => unimplemented!()
+            Self::MBC7(mbc7) => mbc7.writeu16(addr, value),
+            Self::MMM01(mmm01) => mmm01.writeu16(addr, value),
+            Self::HuC1(huc1) => huc1.writeu16(addr, value),
+            Self::PocketCamera(camera) => camera.writeu16(addr, value),
+            Self::HuC3(huc3) => huc3.writeu16(addr, value),
+            Self::Custom(_) => {
+                let value = value.to_le_bytes();
+                self.writeu8(addr, value[0])?;
+                self.writeu8(addr + 1.into(), value[1])
+            }
         }
     }
 
@@ -74,8 +382,16 @@ impl BusIO for Cartridge {
         match self {
             Self::MBC0(mbc0) => mbc0.as_slice(addr, len),
             Self::MBC1(mbc1) => mbc1.as_slice(addr, len),
+            Self::MBC2(mbc2) => mbc2.as_slice(addr, len),
+            Self::MBC3(mbc3) => mbc3.as_slice(addr, len),
             Self::MBC5(mbc5) => mbc5.as_slice(addr, len),
-            _ => unimplemented!()
+            Self::MBC7(mbc7) => mbc7.as_slice(addr, len),
+            Self::MMM01(mmm01) => mmm01.as_slice(addr, len),
+            Self::HuC1(huc1) => huc1.as_slice(addr, len),
+            Self::PocketCamera(camera) => camera.as_slice(addr, len),
+            Self::HuC3(huc3) => huc3.as_slice(addr, len),
+            // nothing to borrow, OAM DMA reads it byte by byte
+            Self::Custom(_) => Err(format!("Mapper as_slice - not supported: {:x?}", addr).into()),
         }
     }
 
@@ -83,10 +399,92 @@ impl BusIO for Cartridge {
         match self {
             Self::MBC0(mbc0) => mbc0.print_dbg(start, len),
             Self::MBC1(mbc1) => mbc1.print_dbg(start, len),
+            Self::MBC2(mbc2) => mbc2.print_dbg(start, len),
+            Self::MBC3(mbc3) => mbc3.print_dbg(start, len),
             Self::MBC5(mbc5) => mbc5.print_dbg(start, len),
-            _ => unimplemented!()
+            Self::MBC7(mbc7) => mbc7.print_dbg(start, len),
+            Self::MMM01(mmm01) => mmm01.print_dbg(start, len),
+            Self::HuC1(huc1) => huc1.print_dbg(start, len),
+            Self::PocketCamera(camera) => camera.print_dbg(start, len),
+            Self::HuC3(huc3) => huc3.print_dbg(start, len),
+            Self::Custom(_) => unimplemented!(),
         }
     }
 }
 
 
+
+// A ROM for the MBCs' tests: `banks` 16 KiB banks that each start with their number, and a header
+// with the cartridge type and RAM size code.
+#[cfg(test)]
+pub(super) fn test_rom(banks: usize, mbc_mode: u8, ram_size_code: u8) -> Vec<u8> {
+    use crate::header::{RAM_SIZE_ADDR, ROM_SIZE_ADDR};
+
+    let mut raw = vec![0; banks * rom_bank::ROM_BANK_SIZE];
+    for (i, bank) in raw.chunks_mut(rom_bank::ROM_BANK_SIZE).enumerate() {
+        bank[0] = i as u8;
+    }
+    raw[MBC_MODE_ADDR] = mbc_mode;
+    raw[ROM_SIZE_ADDR] = (banks / 2).trailing_zeros() as u8;
+    raw[RAM_SIZE_ADDR] = ram_size_code;
+    raw
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MBC1: u8 = 0x01;
+    const MMM01_RAM_BATTERY: u8 = 0x0d;
+
+    fn logo(raw: &mut [u8], offset: usize) {
+        raw[offset + LOGO_ADDR..][..NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+    }
+
+    // 1 MiB with MBC1 games at every 256 KiB
+    fn multicart() -> Vec<u8> {
+        let mut raw = test_rom(64, MBC1, 0x00);
+        for game in 0..4 {
+            logo(&mut raw, game * MULTICART_GAME_SIZE);
+            raw[game * MULTICART_GAME_SIZE + MBC_MODE_ADDR] = MBC1;
+        }
+        raw
+    }
+
+    #[test]
+    fn mbc1m() {
+        // several games, but no MMM01 menu: it's an MBC1M
+        let raw = multicart();
+        assert_eq!(header_offset(&raw), 0);
+        assert!(matches!(Cartridge::new(raw), Cartridge::MBC1(_)));
+    }
+
+    #[test]
+    fn mmm01_menu_last() {
+        let mut raw = multicart();
+        let menu = raw.len() - MENU_SIZE;
+        logo(&mut raw, menu);
+        raw[menu + MBC_MODE_ADDR] = MMM01_RAM_BATTERY;
+        assert_eq!(header_offset(&raw), menu);
+
+        // unmapped, it shows the menu where it is
+        let cart = Cartridge::new(raw);
+        assert!(matches!(cart, Cartridge::MMM01(_)));
+        assert_eq!(cart.readu8(0x0000.into()).unwrap(), 62);
+    }
+
+    #[test]
+    fn mmm01_menu_first() {
+        // the menu's 32 KiB, then the games, 1 MiB in all
+        let mut raw = test_rom(2, MMM01_RAM_BATTERY, 0x00);
+        logo(&mut raw, 0);
+        raw.extend(&multicart()[..0x100000 - MENU_SIZE]);
+        assert_eq!(header_offset(&raw), 0);
+
+        // moved to the end, where the MMM01 looks for it
+        let cart = Cartridge::new(raw);
+        assert!(matches!(cart, Cartridge::MMM01(_)));
+        assert_eq!(cart.readu8(0x0000.into()).unwrap(), 0);
+        assert_eq!(cart.readu8(0x4000.into()).unwrap(), 1);
+    }
+}
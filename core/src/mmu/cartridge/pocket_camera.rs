@@ -0,0 +1,397 @@
+use std::fmt::Debug;
+
+use crate::camera::{CameraFrame, ImageSource, CAMERA_HEIGHT, CAMERA_WIDTH};
+use crate::mmu::busio::{BusIO, SResult};
+use crate::util::Addr;
+
+use super::rom_bank::{RomBank, ROM_BANK_SIZE};
+use super::ram_bank::RamBank;
+
+// https://gbdev.io/pandocs/Gameboy_Camera.html
+//
+// Registers (write only)
+// ============================================================================
+// Address          Name            Usage notes
+// ============================================================================
+// 0x0000-0x1fff    RAM enable      0x0a in the lower 4 bits enables writes, the RAM can always be read
+// 0x2000-0x3fff    ROM bank        6 bits
+// 0x4000-0x5fff    RAM bank        0x00-0x0f map a RAM bank, bit 4 set maps the camera registers instead
+//
+// Camera registers at 0xa000-0xa035, repeated every 0x80 bytes, only 0xa000 can be read (the rest reads 0)
+// ============================================================================
+//  0xa000      bit 0: write 1 to start a capture, reads 1 until it's done
+//  0xa001      bit 7: N, bits 5-6: VH edge mode (0 none, 1 horizontal, 2 vertical, 3 both), bits 0-4: gain
+//  0xa002-3    exposure time, big endian, in 16 cycle (M-cycle) steps
+//  0xa004      bits 5-7: edge enhancement ratio, bit 4: invert, bits 0-3: output voltage
+//  0xa005      bits 6-7: zero point calibration, bits 0-5: output reference voltage
+//  0xa006-35   4x4 dithering matrix, 3 thresholds (light to dark) for each position
+//
+// The sensor's analog side (the voltages, invert and calibration bits) isn't modelled,
+// the frame from the image source is scaled by the exposure and gain, edge enhanced,
+// and turned into 4 shades with the dithering matrix.
+//
+// The image ends up in RAM bank 0 at 0x0100, 16 x 14 tiles in the usual 2 bits per pixel tile format.
+
+const NUM_RAM_BANKS: usize = 16;
+const REGISTERS_SELECTED: u8 = 0x10;
+
+const REG_CAPTURE: usize = 0x00;
+const REG_EDGE_GAIN: usize = 0x01;
+const REG_EXPOSURE_HIGH: usize = 0x02;
+const REG_EXPOSURE_LOW: usize = 0x03;
+const REG_EDGE_RATIO: usize = 0x04;
+const REG_MATRIX: usize = 0x06;
+const NUM_REGISTERS: usize = REG_MATRIX + 4 * 4 * 3;
+const REGISTERS_MIRROR_MASK: u16 = 0x7f;
+
+const IMAGE_ADDR: usize = 0x0100;
+
+// duration of a capture in cpu ticks: (32446 + (N ? 0 : 512) + 16 * exposure) M-cycles
+const CAPTURE_BASE_TICKS: u64 = 32446 * 4;
+const CAPTURE_NO_N_TICKS: u64 = 512 * 4;
+const CAPTURE_EXPOSURE_TICKS: u64 = 16 * 4;
+
+// exposure at which a pixel comes out as bright as the source has it (with no gain)
+const EXPOSURE_NEUTRAL: f32 = 0x1000 as f32;
+// the gain is roughly linear in dB, 0 is no gain and every step adds about 1.4 dB
+const GAIN_DB_STEP: f32 = 1.4;
+const EDGE_RATIOS: [f32; 8] = [0.5, 0.75, 1.0, 1.25, 2.0, 3.0, 4.0, 5.0];
+
+pub(crate) struct PocketCamera {
+    roms: Vec<RomBank>,
+    rams: Vec<RamBank>,
+
+    cur_rom: u8,
+    cur_ram: u8,
+    ram_enabled: bool,
+
+    registers: [u8; NUM_REGISTERS],
+    // ticks left until the running capture is done, 0 when idle
+    capture_ticks: u64,
+    source: Option<Box<dyn ImageSource>>,
+}
+
+impl Debug for PocketCamera {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PocketCamera")
+            .field("cur_rom", &self.cur_rom)
+            .field("cur_ram", &self.cur_ram)
+            .field("ram_enabled", &self.ram_enabled)
+            .field("registers", &self.registers)
+            .field("capture_ticks", &self.capture_ticks)
+            .field("source", &self.source.is_some())
+            .finish()
+    }
+}
+
+impl PocketCamera {
+    pub(crate) fn new(raw: Vec<u8>, _mbc_mode: u8) -> Self {
+        Self {
+            roms: RomBank::split(&raw),
+            rams: vec![RamBank::new(); NUM_RAM_BANKS],
+
+            cur_rom: 1,
+            cur_ram: 0,
+            ram_enabled: false,
+
+            registers: [0; NUM_REGISTERS],
+            capture_ticks: 0,
+            source: None,
+        }
+    }
+
+    // with no source the lens is covered, and every capture is black
+    pub(crate) fn set_image_source(&mut self, source: Option<Box<dyn ImageSource>>) {
+        self.source = source;
+    }
+
+    pub(crate) fn save_data(&self) -> Vec<u8> {
+        RamBank::dump(&self.rams)
+    }
+
+    pub(crate) fn load_save_data(&mut self, data: &[u8]) {
+        RamBank::restore(&mut self.rams, data);
+    }
+
+    pub(crate) fn tick(&mut self, cpu_ticks: u64) {
+        if self.capture_ticks == 0 {
+            return;
+        }
+        self.capture_ticks = self.capture_ticks.saturating_sub(cpu_ticks);
+        if self.capture_ticks == 0 {
+            self.registers[REG_CAPTURE] &= !1;
+            self.capture();
+        }
+    }
+
+    fn rom_index(&self, addr: u16) -> usize {
+        let index = match addr {
+            0x0000..0x4000 => 0,
+            _              => self.cur_rom as usize,
+        };
+        index % self.roms.len()
+    }
+
+    fn registers_selected(&self) -> bool {
+        self.cur_ram & REGISTERS_SELECTED != 0
+    }
+
+    fn ram_index(&self) -> usize {
+        (self.cur_ram & 0x0f) as usize
+    }
+
+    fn exposure(&self) -> u16 {
+        u16::from_be_bytes([self.registers[REG_EXPOSURE_HIGH], self.registers[REG_EXPOSURE_LOW]])
+    }
+
+    fn start_capture(&mut self) {
+        let n = self.registers[REG_EDGE_GAIN] & 0x80 != 0;
+        self.capture_ticks = CAPTURE_BASE_TICKS
+            + if n { 0 } else { CAPTURE_NO_N_TICKS }
+            + self.exposure() as u64 * CAPTURE_EXPOSURE_TICKS;
+    }
+
+    fn capture(&mut self) {
+        let mut frame: CameraFrame = [0; CAMERA_WIDTH * CAMERA_HEIGHT];
+        if let Some(source) = self.source.as_mut() {
+            source.capture(&mut frame);
+        }
+
+        // exposure and gain
+        let gain = 10f32.powf((self.registers[REG_EDGE_GAIN] & 0x1f) as f32 * GAIN_DB_STEP / 20.0);
+        let scale = self.exposure() as f32 / EXPOSURE_NEUTRAL * gain;
+        let levels: Vec<f32> = frame.iter().map(|&p| p as f32 * scale).collect();
+        let level = |x: usize, y: usize| levels[y * CAMERA_WIDTH + x];
+
+        // edge enhancement, the neighbours are clamped at the borders
+        let mode = (self.registers[REG_EDGE_GAIN] >> 5) & 0b11;
+        let ratio = EDGE_RATIOS[(self.registers[REG_EDGE_RATIO] >> 5) as usize];
+
+        let image = &mut self.rams[0].as_mut_slice()[IMAGE_ADDR..];
+        for y in 0..CAMERA_HEIGHT {
+            for x in 0..CAMERA_WIDTH {
+                let v = level(x, y);
+                let (left, right) = (level(x.saturating_sub(1), y), level((x + 1).min(CAMERA_WIDTH - 1), y));
+                let (up, down) = (level(x, y.saturating_sub(1)), level(x, (y + 1).min(CAMERA_HEIGHT - 1)));
+                let edge = match mode {
+                    1 => 2.0 * v - left - right,
+                    2 => 2.0 * v - up - down,
+                    3 => 4.0 * v - left - right - up - down,
+                    _ => 0.0,
+                };
+                let v = (v + edge * ratio).clamp(0.0, 255.0) as u8;
+
+                // dithering, the darker the pixel, the more thresholds it's under
+                let matrix = REG_MATRIX + ((y & 3) * 4 + (x & 3)) * 3;
+                let colour = self.registers[matrix..matrix + 3].iter().filter(|&&t| v < t).count() as u8;
+
+                let tile = (y / 8) * (CAMERA_WIDTH / 8) + x / 8;
+                let row = tile * 16 + (y % 8) * 2;
+                let bit = 7 - (x % 8);
+                image[row] = image[row] & !(1 << bit) | (colour & 1) << bit;
+                image[row + 1] = image[row + 1] & !(1 << bit) | (colour >> 1) << bit;
+            }
+        }
+    }
+}
+
+impl BusIO for PocketCamera {
+    fn readu8(&self, addr: Addr) -> SResult<u8> {
+        match addr.into() {
+            a @ 0x0000..0x8000 => self.roms[self.rom_index(a)].readu8(addr),
+            a @ 0xa000..0xc000 if self.registers_selected() => {
+                match (a & REGISTERS_MIRROR_MASK) as usize {
+                    REG_CAPTURE => Ok(self.registers[REG_CAPTURE] & 0b111),
+                    _           => Ok(0x00),
+                }
+            }
+            0xa000..0xc000 => self.rams[self.ram_index()].readu8(addr),
+            _              => Err(format!("PocketCamera readu8 - invalid addr: {:x?}", addr).into())
+        }
+    }
+
+    fn writeu8(&mut self, addr: Addr, value: u8) -> SResult<()> {
+        match addr.into() {
+            0x0000..0x2000 => self.ram_enabled = value & 0x0f == 0xa,
+            0x2000..0x4000 => self.cur_rom = value & 0b0011_1111,
+            0x4000..0x6000 => self.cur_ram = value & 0b0001_1111,
+            0x6000..0x8000 => {}
+            a @ 0xa000..0xc000 if self.registers_selected() => {
+                let reg = (a & REGISTERS_MIRROR_MASK) as usize;
+                match reg {
+                    REG_CAPTURE => {
+                        let start = value & 1 == 1 && self.capture_ticks == 0;
+                        // the busy bit stays set while a capture is running
+                        self.registers[REG_CAPTURE] = value & 0b110 | (self.capture_ticks > 0 || start) as u8;
+                        if start {
+                            self.start_capture();
+                        }
+                    }
+                    1..NUM_REGISTERS => self.registers[reg] = value,
+                    _                => {}
+                }
+            }
+            0xa000..0xc000 => {
+                if self.ram_enabled {
+                    let index = self.ram_index();
+                    self.rams[index].writeu8(addr, value)?;
+                }
+            }
+            _              => return Err(format!("PocketCamera writeu8 - invalid addr: {:x?}", addr).into())
+        };
+        Ok(())
+    }
+
+    fn readu16(&self, addr: Addr) -> SResult<u16> {
+        Ok(u16::from_le_bytes([
+            self.readu8(addr)?,
+            self.readu8(addr + 1.into())?
+        ]))
+    }
+
+    fn writeu16(&mut self, addr: Addr, value: u16) -> SResult<()> {
+        if addr > 0x0000.into() && addr < 0x8000.into() {
+            panic!("PocketCamera writeu16 @ {:x?}", addr);
+        }
+        let value = value.to_le_bytes();
+        self.writeu8(addr, value[0])?;
+        self.writeu8(addr + 1.into(), value[1])?;
+        Ok(())
+    }
+
+    // used by OAM DMA
+    fn as_slice(&self, addr: Addr, len: usize) -> SResult<&[u8]> {
+        match addr.into() {
+            a @ 0x0000..0x8000 => {
+                let offset = a as usize % ROM_BANK_SIZE;
+                Ok(&self.roms[self.rom_index(a)].0[offset..][..len])
+            }
+            0xa000..0xc000 if !self.registers_selected() => self.rams[self.ram_index()].as_slice(addr, len),
+            _              => Err(format!("PocketCamera as_slice - invalid addr: {:x?}", addr).into())
+        }
+    }
+
+    fn print_dbg(&self, _start: Addr, _len: u16) -> String {
+        unimplemented!()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::StillImage;
+    use crate::mmu::cartridge::test_rom;
+
+    const POCKET_CAMERA: u8 = 0xfc;
+
+    // with `frame` in front of the lens, neutral exposure, no gain, and the registers mapped
+    fn camera(frame: impl Fn(usize, usize) -> u8) -> PocketCamera {
+        let mut still = [0; CAMERA_WIDTH * CAMERA_HEIGHT];
+        for y in 0..CAMERA_HEIGHT {
+            for x in 0..CAMERA_WIDTH {
+                still[y * CAMERA_WIDTH + x] = frame(x, y);
+            }
+        }
+        let mut camera = PocketCamera::new(test_rom(4, POCKET_CAMERA, 0x04), POCKET_CAMERA);
+        camera.set_image_source(Some(Box::new(StillImage::new(&still))));
+        write(&mut camera, 0x4000, REGISTERS_SELECTED);
+        write(&mut camera, 0xa000 + REG_EXPOSURE_HIGH as u16, (EXPOSURE_NEUTRAL as u16 >> 8) as u8);
+        camera
+    }
+
+    fn write(camera: &mut PocketCamera, addr: u16, value: u8) {
+        camera.writeu8(addr.into(), value).unwrap();
+    }
+
+    // the dithering matrix, the 3 thresholds at each of its positions
+    fn thresholds(camera: &mut PocketCamera, at: impl Fn(usize, usize) -> [u8; 3]) {
+        for y in 0..4 {
+            for x in 0..4 {
+                for (i, t) in at(x, y).into_iter().enumerate() {
+                    write(camera, 0xa000 + (REG_MATRIX + (y * 4 + x) * 3 + i) as u16, t);
+                }
+            }
+        }
+    }
+
+    // starts a capture and ticks until it's done
+    fn capture(camera: &mut PocketCamera) {
+        write(camera, 0xa000, 1);
+        let ticks = CAPTURE_BASE_TICKS + CAPTURE_NO_N_TICKS + EXPOSURE_NEUTRAL as u64 * CAPTURE_EXPOSURE_TICKS;
+        camera.tick(ticks - 4);
+        assert_eq!(camera.readu8(0xa000.into()).unwrap() & 1, 1, "busy until the end");
+        camera.tick(4);
+        assert_eq!(camera.readu8(0xa000.into()).unwrap() & 1, 0);
+    }
+
+    // the captured image's shade at (x, y), from the tiles in RAM bank 0
+    fn shade(camera: &mut PocketCamera, x: usize, y: usize) -> u8 {
+        write(camera, 0x4000, 0x00);
+        let tile = (y / 8) * (CAMERA_WIDTH / 8) + x / 8;
+        let addr = 0xa000 + (IMAGE_ADDR + tile * 16 + (y % 8) * 2) as u16;
+        let (low, high) = (camera.readu8(addr.into()).unwrap(), camera.readu8((addr + 1).into()).unwrap());
+        let bit = 7 - (x % 8);
+        write(camera, 0x4000, REGISTERS_SELECTED);
+        (high >> bit & 1) << 1 | low >> bit & 1
+    }
+
+    #[test]
+    fn dithering() {
+        // a flat grey, that the matrix makes shade (x + y) % 4: the thresholds over it are the dark ones
+        let mut camera = camera(|_, _| 0x80);
+        thresholds(&mut camera, |x, y| match (x + y) % 4 {
+            0 => [0x00, 0x00, 0x00],
+            1 => [0xff, 0x00, 0x00],
+            2 => [0xff, 0xff, 0x00],
+            _ => [0xff, 0xff, 0xff],
+        });
+        capture(&mut camera);
+
+        for y in 0..CAMERA_HEIGHT {
+            for x in 0..CAMERA_WIDTH {
+                assert_eq!(shade(&mut camera, x, y), ((x + y) % 4) as u8, "at ({}, {})", x, y);
+            }
+        }
+        // the first row of the first tile, shades 0 1 2 3 0 1 2 3
+        write(&mut camera, 0x4000, 0x00);
+        assert_eq!(camera.readu8(0xa100.into()).unwrap(), 0x55);
+        assert_eq!(camera.readu8(0xa101.into()).unwrap(), 0x33);
+    }
+
+    #[test]
+    fn edge_enhancement() {
+        // dark grey on the left half and light grey on the right, which are shades 2 and 0
+        let frame = |x: usize, _| if x < CAMERA_WIDTH / 2 { 100 } else { 200 };
+        let row = |camera: &mut PocketCamera| (60..68).map(|x| shade(camera, x, 50)).collect::<Vec<_>>();
+
+        // horizontal, at a ratio of 1, the pixels either side of the edge go to black and white
+        let mut camera = camera(frame);
+        thresholds(&mut camera, |_, _| [0x40, 0x80, 0xc0]);
+        write(&mut camera, 0xa001, 1 << 5);
+        write(&mut camera, 0xa004, 2 << 5);
+        capture(&mut camera);
+        assert_eq!(row(&mut camera), [2, 2, 2, 3, 0, 0, 0, 0]);
+
+        // vertical doesn't see it
+        write(&mut camera, 0xa001, 2 << 5);
+        capture(&mut camera);
+        assert_eq!(row(&mut camera), [2, 2, 2, 2, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn registers_hide_the_ram() {
+        let mut camera = camera(|_, _| 0);
+        // only the capture register can be read
+        write(&mut camera, 0xa000, 0x06);
+        assert_eq!(camera.readu8(0xa000.into()).unwrap(), 0x06);
+        assert_eq!(camera.readu8(0xa001.into()).unwrap(), 0x00);
+
+        // the RAM can be read while it's disabled, but not written
+        write(&mut camera, 0x4000, 0x00);
+        write(&mut camera, 0xa000, 0x42);
+        assert_eq!(camera.readu8(0xa000.into()).unwrap(), 0x00);
+        write(&mut camera, 0x0000, 0x0a);
+        write(&mut camera, 0xa000, 0x42);
+        assert_eq!(camera.readu8(0xa000.into()).unwrap(), 0x42);
+    }
+}
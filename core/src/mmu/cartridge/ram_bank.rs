@@ -1,3 +1,4 @@
+use crate::header;
 use crate::mmu::busio::{BusIO, SResult};
 use crate::util::Addr;
 
@@ -13,6 +14,28 @@ impl RamBank {
         Self([0; RAM_BANK_SIZE])
     }
 
+    // number of 8 KiB banks from the RAM size code at 0x149
+    pub(super) fn count(ram_size_code: u8) -> usize {
+        header::ram_size(ram_size_code) / RAM_BANK_SIZE
+    }
+
+    // all the banks back to back, the layout of a .sav file
+    pub(super) fn dump(rams: &[Self]) -> Vec<u8> {
+        rams.iter().flat_map(|r| r.0).collect()
+    }
+
+    // the inverse of `dump`, a shorter or longer save fills (or is cut to) what fits
+    pub(super) fn restore(rams: &mut [Self], data: &[u8]) {
+        for (ram, chunk) in rams.iter_mut().zip(data.chunks(RAM_BANK_SIZE)) {
+            ram.0[..chunk.len()].copy_from_slice(chunk);
+        }
+    }
+
+    // for MBCs that write to the RAM themselves
+    pub(super) fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+
     fn get_index(addr: Addr) -> usize {
         let index = <Addr as Into<u16>>::into(addr) & ((1 << RAM_BANK_SIZE_ORDER) - 1);
         index as usize
@@ -47,8 +70,8 @@ impl BusIO for RamBank {
     }
 
     fn as_slice(&self, addr: Addr, len: usize) -> SResult<&[u8]> {
-        let addr: u16 = addr.into();
-        Ok(&self.0[addr as usize..][..len])
+        let index = Self::get_index(addr);
+        Ok(&self.0[index..][..len])
     }
 
     fn print_dbg(&self, _start: Addr, _len: u16) -> String {
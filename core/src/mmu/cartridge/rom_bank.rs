@@ -1,3 +1,4 @@
+use crate::header;
 use crate::mmu::busio::{BusIO, SResult};
 use crate::util::Addr;
 
@@ -13,6 +14,22 @@ impl RomBank {
         Self(inner)
     }
 
+    // number of 16 KiB banks from the ROM size code at 0x148, None for codes that aren't known
+    pub(super) fn count(rom_size_code: u8) -> Option<usize> {
+        header::rom_size(rom_size_code).map(|size| size / ROM_BANK_SIZE)
+    }
+
+    // splits a ROM image into banks, padding the last one if the image is cut short
+    pub(super) fn split(raw: &[u8]) -> Vec<Self> {
+        raw.chunks(ROM_BANK_SIZE)
+            .map(|c| {
+                let mut bank = c.to_vec();
+                bank.resize(ROM_BANK_SIZE, 0xff);
+                Self::new(bank)
+            })
+            .collect()
+    }
+
     fn get_index(addr: Addr) -> usize {
         let index = <Addr as Into<u16>>::into(addr) & ((1 << ROM_BANK_SIZE_ORDER) - 1);
         index as usize
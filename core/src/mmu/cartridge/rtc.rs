@@ -0,0 +1,239 @@
+use std::fmt::Debug;
+use std::time::Duration;
+
+use crate::clock::{SystemClock, TimeSource};
+use crate::util::get_nth_bit;
+
+// MBC3 real-time clock
+// https://gbdev.io/pandocs/MBC3.html#clock-counter-registers
+//
+// ============================================================================
+// Register Name    Usage notes
+// ============================================================================
+//  0x08    S       seconds, 0-59
+//  0x09    M       minutes, 0-59
+//  0x0a    H       hours, 0-23
+//  0x0b    DL      lower 8 bits of the day counter
+//  0x0c    DH      bit 0: bit 8 of the day counter
+//                  bit 6: halt, stops the clock
+//                  bit 7: day counter carry, set when the day counter overflows, stays set until cleared
+//
+// The game reads a latched copy of the registers, which is updated by writing 0 and then 1 to 0x6000-0x7fff.
+
+pub(super) const RTC_S: u8 = 0x08;
+pub(super) const RTC_M: u8 = 0x09;
+pub(super) const RTC_H: u8 = 0x0a;
+pub(super) const RTC_DL: u8 = 0x0b;
+pub(super) const RTC_DH: u8 = 0x0c;
+
+const DAYS: u64 = 512;
+
+// The RTC is saved after the RAM in the format used by VBA-M, BGB and most other emulators:
+// the 5 clock registers and the 5 latched registers as little endian u32s, then a unix timestamp
+// as u64 (or u32 in older saves).
+const RTC_SAVE_SIZE: usize = 48;
+const RTC_SAVE_SIZE_32BIT_TIME: usize = 44;
+const RTC_REGISTERS: [u8; 5] = [RTC_S, RTC_M, RTC_H, RTC_DL, RTC_DH];
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(super) struct RtcRegisters {
+    pub(super) seconds: u8,
+    pub(super) minutes: u8,
+    pub(super) hours: u8,
+    pub(super) days: u16, // 9 bit
+    pub(super) halt: bool,
+    pub(super) day_carry: bool,
+}
+
+impl RtcRegisters {
+    pub(super) fn read(&self, reg: u8) -> u8 {
+        match reg {
+            RTC_S  => self.seconds,
+            RTC_M  => self.minutes,
+            RTC_H  => self.hours,
+            RTC_DL => self.days as u8,
+            RTC_DH => (self.day_carry as u8) << 7 | (self.halt as u8) << 6 | (self.days >> 8) as u8 & 1,
+            _      => 0xff,
+        }
+    }
+
+    fn write(&mut self, reg: u8, value: u8) {
+        match reg {
+            RTC_S  => self.seconds = value & 0x3f,
+            RTC_M  => self.minutes = value & 0x3f,
+            RTC_H  => self.hours = value & 0x1f,
+            RTC_DL => self.days = self.days & 0x100 | value as u16,
+            RTC_DH => {
+                self.days = self.days & 0xff | ((value as u16 & 1) << 8);
+                self.halt = get_nth_bit(value, 6);
+                self.day_carry = get_nth_bit(value, 7);
+            }
+            _      => {}
+        }
+    }
+
+    fn advance(&mut self, mut secs: u64) {
+        // whole days first, so a clock that was left alone for years doesn't take years to catch up
+        let days = secs / SECS_PER_DAY;
+        secs %= SECS_PER_DAY;
+        self.add_days(days);
+
+        for _ in 0..secs {
+            self.tick();
+        }
+    }
+
+    // The counters are compared for equality, so a game can write out of range values
+    // (e.g. 63 seconds) and they count up to the register's maximum before wrapping to 0.
+    fn tick(&mut self) {
+        self.seconds = (self.seconds + 1) & 0x3f;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+        self.minutes = (self.minutes + 1) & 0x3f;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+        self.hours = (self.hours + 1) & 0x1f;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+        self.add_days(1);
+    }
+
+    fn add_days(&mut self, days: u64) {
+        let days = self.days as u64 + days;
+        if days >= DAYS {
+            self.day_carry = true;
+        }
+        self.days = (days % DAYS) as u16;
+    }
+}
+
+pub(super) struct Rtc {
+    clock: RtcRegisters,
+    latched: RtcRegisters,
+    // last latch write, latching happens on a 0 followed by a 1
+    latch: u8,
+
+    source: Box<dyn TimeSource>,
+    // time of the last update, the clock is brought up to date before every access
+    synced: Duration,
+}
+
+impl Debug for Rtc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Rtc")
+            .field("clock", &self.clock)
+            .field("latched", &self.latched)
+            .field("synced", &self.synced)
+            .finish()
+    }
+}
+
+impl Rtc {
+    pub(super) fn new() -> Self {
+        let source: Box<dyn TimeSource> = Box::new(SystemClock);
+        Self {
+            clock: Default::default(),
+            latched: Default::default(),
+            latch: 0xff,
+            synced: source.now(),
+            source,
+        }
+    }
+
+    pub(super) fn set_time_source(&mut self, source: Box<dyn TimeSource>) {
+        self.update();
+        self.synced = source.now();
+        self.source = source;
+    }
+
+    pub(super) fn read(&self, reg: u8) -> u8 {
+        self.latched.read(reg)
+    }
+
+    pub(super) fn write(&mut self, reg: u8, value: u8) {
+        self.update();
+        self.clock.write(reg, value);
+        if reg == RTC_S {
+            // writing the seconds also resets the sub-second counter
+            self.synced = self.source.now();
+        }
+    }
+
+    pub(super) fn write_latch(&mut self, value: u8) {
+        if self.latch == 0 && value == 1 {
+            self.update();
+            self.latched = self.clock;
+        }
+        self.latch = value;
+    }
+
+    pub(super) fn save(&self) -> Vec<u8> {
+        // saves from other emulators are stamped with the wall clock,
+        // so bring the clock up to date first, and stamp it with the same time
+        let now = self.source.now();
+        let mut clock = self.clock;
+        if !clock.halt {
+            clock.advance(now.saturating_sub(self.synced).as_secs());
+        }
+
+        let mut data = Vec::with_capacity(RTC_SAVE_SIZE);
+        for regs in [&clock, &self.latched] {
+            for reg in RTC_REGISTERS {
+                data.extend_from_slice(&(regs.read(reg) as u32).to_le_bytes());
+            }
+        }
+        data.extend_from_slice(&now.as_secs().to_le_bytes());
+        data
+    }
+
+    // returns false if the data isn't an RTC save
+    pub(super) fn load(&mut self, data: &[u8]) -> bool {
+        if data.len() != RTC_SAVE_SIZE && data.len() != RTC_SAVE_SIZE_32BIT_TIME {
+            return false;
+        }
+        let words: Vec<u32> = data
+            .chunks_exact(4)
+            .map(|c| u32::from_le_bytes(c.try_into().unwrap()))
+            .collect();
+        for (i, reg) in RTC_REGISTERS.into_iter().enumerate() {
+            self.clock.write(reg, words[i] as u8);
+            self.latched.write(reg, words[i + 5] as u8);
+        }
+        let saved_at = match data.len() {
+            RTC_SAVE_SIZE => u64::from_le_bytes(data[40..48].try_into().unwrap()),
+            _             => words[10] as u64,
+        };
+        // The save is stamped with the time source it was made with, which can have another epoch
+        // than the current one (e.g. a save made with the wall clock, loaded with a ManualClock).
+        // The time that passed is caught up now, and the clock carries on from the current source's
+        // time. A stamp from the source's future counts as no time passed.
+        let now = self.source.now();
+        if !self.clock.halt {
+            self.clock.advance(now.saturating_sub(Duration::from_secs(saved_at)).as_secs());
+        }
+        self.synced = now;
+        true
+    }
+
+    fn update(&mut self) {
+        let now = self.source.now();
+        if self.clock.halt {
+            self.synced = now;
+            return;
+        }
+        // a source going backwards (e.g. the wall clock being changed) just stops the clock for a while
+        let elapsed = now.saturating_sub(self.synced).as_secs();
+        self.clock.advance(elapsed);
+        self.synced += Duration::from_secs(elapsed);
+        if self.synced > now {
+            self.synced = now;
+        }
+    }
+}
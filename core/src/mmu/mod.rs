@@ -1,24 +1,31 @@
 pub(crate) mod busio;
-mod cartridge;
+pub(crate) mod cartridge;
 mod not_usable;
 pub(crate) mod ram;
 mod rom;
 
 use busio::{BusIO, SResult};
-use cartridge::Cartridge;
+use cartridge::{Cartridge, RumbleCallback, ToneCallback};
 use crate::{
+    apu::{APU, AUDIO_START, AUDIO_END, FRAME_SEQUENCER_DIV_BIT},
+    camera::ImageSource,
+    clock::TimeSource,
+    infrared::InfraredDevice,
+    mapper::Mapper,
     cpu::interrupts::{Interrupts, Interrupt},
     ppu::{
-        lcdc::LCDC, palette::{BgWinPalette, ObjPalette}, status::Status, PPU, REG_BG_PALETTE, REG_CURR_SCANLINE, REG_LCDC,
+        palette::{BgWinPalette, ObjPalette}, PPU, REG_BG_PALETTE, REG_CURR_SCANLINE, REG_LCDC, REG_LYC,
         REG_SCROLL_X, REG_SCROLL_Y, REG_STAT, REG_OBJ_PALETTE_0, REG_OBJ_PALETTE_1, REG_WIN_X, REG_WIN_Y,
     },
     util::Addr, 
     timer::{Timer, REG_DIV, REG_TAC, REG_TIMA, REG_TMA}, 
     joypad::{Joypad, REG_JOYPAD},
+    serial::{Serial, REG_SB, REG_SC},
 };
 use not_usable::{NotUsableHigh, NotUsableLow};
 use ram::RAM;
 use rom::ROM;
+use std::error::Error;
 
 const DMA: u16 = 0xff46;
 const BANK_REG: u16 = 0xff50;
@@ -29,6 +36,9 @@ pub struct MMU {
     pub(crate) boot_disabled: bool,
     bootrom: Option<ROM>,
     cartridge: Cartridge,
+    // battery backed cartridge RAM, and whether it changed since it was last saved
+    pub(crate) battery: bool,
+    pub(crate) ram_dirty: bool,
     // external_ram: RAM,
     work_ram: RAM,
     high_ram: RAM,
@@ -38,9 +48,14 @@ pub struct MMU {
 
     ier: Interrupts,
 
-    ppu: PPU,
+    // ticks the CPU's memory accesses already ran the machine for, during the current instruction
+    cpu_cycle_ticks: u64,
+
+    pub(crate) ppu: PPU,
     timer: Timer,
+    pub(crate) serial: Serial,
     pub joypad: Joypad,
+    pub apu: APU,
 }
 
 impl MMU {
@@ -58,8 +73,7 @@ impl MMU {
             
             // 0xff00            => Ok(&self.nuh), // joypad
             
-            0xff01 | 0xff02 => Ok(&self.nuh), // serial transfer
-            0xff10..=0xff3f => Ok(&self.nuh), // audio
+            AUDIO_START..=AUDIO_END => Ok(&self.apu),
             0xff51..=0xff7f => Ok(&self.nuh), // io regs
             0xfea0..0xff00 => Ok(&self.nuh), // not usable
             
@@ -85,8 +99,7 @@ impl MMU {
 
             // 0xff00            => Ok(&mut self.nuh), // joypad
 
-            0xff01 | 0xff02 => Ok(&mut self.nuh), // serial transfer
-            0xff10..=0xff3f => Ok(&mut self.nuh), // audio
+            AUDIO_START..=AUDIO_END => Ok(&mut self.apu),
             0xff51..=0xff7f => Ok(&mut self.nuh), // io regs
             // 0xff06 => Ok(&mut self.nuh), // timer
             0xfea0..0xff00 => Ok(&mut self.nuh), // not usable
@@ -104,11 +117,23 @@ impl MMU {
 
 impl MMU {
     pub fn new(bootrom: Option<Vec<u8>>, cartridge: Vec<u8>) -> Self {
+        let battery = cartridge::has_battery(cartridge::mbc_mode(&cartridge));
+        Self::with_cartridge(bootrom, Cartridge::new(cartridge), battery)
+    }
+
+    // `battery` comes from the header the mapper shows in bank 0
+    pub(crate) fn with_mapper(bootrom: Option<Vec<u8>>, mapper: Box<dyn Mapper>, battery: bool) -> Self {
+        Self::with_cartridge(bootrom, Cartridge::Custom(mapper), battery)
+    }
+
+    fn with_cartridge(bootrom: Option<Vec<u8>>, cartridge: Cartridge, battery: bool) -> Self {
         Self {
             boot_disabled: false, 
 
             bootrom: bootrom.map(ROM::new),
-            cartridge: Cartridge::new(cartridge),
+            battery,
+            ram_dirty: false,
+            cartridge,
             // external_ram: RAM::new(8 * 1024, Box::new(|addr: Addr| addr - 0xa000.into()), 0),
             work_ram: RAM::new(
                 8 * 1024,
@@ -121,25 +146,72 @@ impl MMU {
 
             ier: Default::default(),
 
+            cpu_cycle_ticks: 0,
+
             ppu: PPU::new(),
             timer: Default::default(),
+            serial: Default::default(),
             joypad: Default::default(),
+            apu: APU::new(),
         }
     }
 
-    pub(crate) fn readu16(&self, addr: Addr) -> u16 {
-        let region = self.find_region(addr).unwrap();
-        region.readu16(addr).unwrap()
+    pub(crate) fn save_data(&self) -> Vec<u8> {
+        self.cartridge.save_data()
+    }
+
+    pub(crate) fn load_save_data(&mut self, data: &[u8]) {
+        self.cartridge.load_save_data(data);
+        self.ram_dirty = false;
+    }
+
+    pub(crate) fn mapper_state(&self) -> Option<Vec<u8>> {
+        self.cartridge.save_state()
+    }
+
+    pub(crate) fn load_mapper_state(&mut self, state: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.cartridge.load_state(state)
+    }
+
+    pub(crate) fn set_tilt(&mut self, x: f32, y: f32) {
+        self.cartridge.set_tilt(x, y);
+    }
+
+    pub(crate) fn set_rumble_callback(&mut self, callback: RumbleCallback) {
+        self.cartridge.set_rumble_callback(callback);
+    }
+
+    pub(crate) fn set_time_source(&mut self, source: Box<dyn TimeSource>) {
+        self.cartridge.set_time_source(source);
+    }
+
+    pub(crate) fn set_image_source(&mut self, source: Option<Box<dyn ImageSource>>) {
+        self.cartridge.set_image_source(source);
+    }
+
+    pub(crate) fn set_tone_callback(&mut self, callback: ToneCallback) {
+        self.cartridge.set_tone_callback(callback);
+    }
+
+    pub(crate) fn attach_infrared(&mut self, device: Box<dyn InfraredDevice>) -> Option<Box<dyn InfraredDevice>> {
+        self.cartridge.attach_infrared(device)
     }
+
+    pub(crate) fn detach_infrared(&mut self) -> Option<Box<dyn InfraredDevice>> {
+        self.cartridge.detach_infrared()
+    }
+
     pub(crate) fn readu8(&self, addr: Addr) -> u8 {
         match addr.into() {
             REG_LCDC          => self.ppu.lcdc.into(),
-            REG_STAT          => self.ppu.status.into(),
+            // bit 7 is unused and reads 1
+            REG_STAT          => u8::from(self.ppu.status) | 0x80,
             REG_SCROLL_X      => self.ppu.scx,
             REG_SCROLL_Y      => self.ppu.scy,
             REG_WIN_X         => self.ppu.wx,
             REG_WIN_Y         => self.ppu.wy,
             REG_CURR_SCANLINE => self.ppu.curr_scanline,
+            REG_LYC           => self.ppu.lyc,
 
             REG_OBJ_PALETTE_0 => self.ppu.obp0.into(),
             REG_OBJ_PALETTE_1 => self.ppu.obp1.into(),
@@ -151,6 +223,9 @@ impl MMU {
             
             REG_JOYPAD        => self.joypad.read(),
 
+            REG_SB            => self.serial.read_data(),
+            REG_SC            => self.serial.read_control(),
+
             BANK_REG          => if self.boot_disabled { 1 } else { 0 },
 
             IER               => self.ier.into(),
@@ -164,15 +239,12 @@ impl MMU {
             }
         }
     }
-    pub(crate) fn writeu16(&mut self, addr: Addr, value: u16) {
-        let region = self.find_region_mut(addr).unwrap();
-        region.writeu16(addr, value).unwrap()
-    }
-
+    // byte by byte, so IO registers and battery backed RAM see it as any other write
     pub(crate) fn writeu8(&mut self, addr: Addr, value: u8) {
         match addr.into() {
-            REG_LCDC          => self.ppu.lcdc = LCDC::from(value),
-            REG_STAT          => self.ppu.status = Status::from(value),
+            REG_LCDC          => self.ppu.write_lcdc(value),
+            REG_STAT          => self.ppu.write_stat(value),
+            REG_LYC           => self.ppu.write_lyc(value),
             REG_SCROLL_X      => self.ppu.scx = value,
             REG_SCROLL_Y      => self.ppu.scy = value,
             REG_WIN_X         => self.ppu.wx = value,
@@ -189,6 +261,9 @@ impl MMU {
             // REG_JOYPAD        => {println!("joypad write: {:#b}", value);self.joypad.write_reg(value)},
             REG_JOYPAD        => self.joypad.write_reg(value),
 
+            REG_SB            => self.serial.write_data(value),
+            REG_SC            => self.serial.write_control(value),
+
             IER               => self.ier = value.into(),
             IFR               => self.ifr_set(value),
             // 0x8000..0xa000    => self.ppu.vram.writeu8(addr, value).unwrap(),
@@ -197,12 +272,20 @@ impl MMU {
                 // println!("start dma");
                 let addr = ((value as u16) << 8).into();
                 let mem_reg = self.find_region(addr).unwrap();
-                let slice = mem_reg.as_slice(addr, 0xa0).unwrap().to_owned();
+                // custom mappers have nothing to borrow, those are copied byte by byte
+                let slice = match mem_reg.as_slice(addr, 0xa0) {
+                    Ok(slice) => slice.to_owned(),
+                    Err(_)    => (0..0xa0).map(|i: u16| mem_reg.readu8(addr + i.into()).unwrap()).collect(),
+                };
                 self.ppu.dma(&slice);
                 // println!("end dma");
             }
 
             BANK_REG       => self.boot_disabled = value != 0,
+            0xa000..0xc000    => {
+                self.ram_dirty |= self.battery;
+                self.cartridge.writeu8(addr, value).unwrap()
+            }
             _ => {
                 let region = self.find_region_mut(addr).unwrap();
                 region.writeu8(addr, value).unwrap()
@@ -217,6 +300,23 @@ impl MMU {
             panic!("timer error")
         }
         self.timer.tick(cpu_ticks as u16);
+        self.serial.tick(cpu_ticks as u16);
+        self.apu.tick(cpu_ticks, self.timer.divider_bit(FRAME_SEQUENCER_DIV_BIT));
+        self.cartridge.tick(cpu_ticks);
+    }
+
+    // a CPU memory access, the machine runs for its machine cycle before it happens
+    pub(crate) fn cpu_cycle(&mut self) {
+        self.tick(4);
+        self.cpu_cycle_ticks += 4;
+    }
+
+    // the ticks of an instruction that weren't spent on memory accesses
+    pub(crate) fn finish_instruction(&mut self, cpu_ticks: u64) {
+        debug_assert!(self.cpu_cycle_ticks <= cpu_ticks, "more memory accesses than machine cycles");
+        let ticks = cpu_ticks.saturating_sub(self.cpu_cycle_ticks);
+        self.cpu_cycle_ticks = 0;
+        self.tick(ticks);
     }
 
     fn ifr(&self) -> Interrupts {
@@ -224,9 +324,15 @@ impl MMU {
         if self.ppu.vblank_interrupt {
             ifr.set(Interrupt::VBlank);
         }
+        if self.ppu.stat_interrupt {
+            ifr.set(Interrupt::LCDStat);
+        }
         if self.timer.interrupt {
             ifr.set(Interrupt::Timer);
         }
+        if self.serial.interrupt {
+            ifr.set(Interrupt::Serial);
+        }
         if self.joypad.interrupt {
             ifr.set(Interrupt::Joypad);
         }
@@ -237,7 +343,9 @@ impl MMU {
     fn ifr_set(&mut self, value: u8) {
         let ifr: Interrupts = value.into();
         self.ppu.vblank_interrupt = ifr.vblank;
+        self.ppu.stat_interrupt = ifr.lcd_stat;
         self.timer.interrupt = ifr.timer;
+        self.serial.interrupt = ifr.serial;
         self.joypad.interrupt = ifr.joypad;
     }
 }
@@ -9,7 +9,7 @@
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
-pub(crate) enum Colour {
+pub enum Colour {
     White = 0,
     LightGrey = 1,
     DarkGrey = 2,
@@ -0,0 +1,238 @@
+use std::collections::VecDeque;
+
+use super::{
+    ObjPixel, PPU, SCREEN_WIDTH_PIXELS, TICKS_OAM_SEARCH, TICKS_ONE_LINE, TILE_WIDTH_PIXELS, TILEMAP_WIDTH_TILES,
+    WINDOW_X_OFFSET,
+};
+use crate::util::get_nth_bit;
+
+// https://gbdev.io/pandocs/pixel_fifo.html
+// https://hacktix.github.io/GBEDG/ppu/#the-pixel-fifo
+//
+// Mode 3, one dot at a time. The fetcher reads a row of 8 BG (or window) pixels in 3 steps of
+// 2 dots, and pushes it once the BG FIFO is empty. A pixel is shifted out every dot the FIFO isn't
+// empty, mixed with the OBJ FIFO, and drawn. What makes mode 3 longer than its 172 dots:
+//  - the SCX % 8 pixels shifted out at the start of the line and thrown away
+//  - the window, which empties the FIFO and restarts the fetcher when it's reached
+//  - sprites, which stop the pixels while their row is fetched and mixed into the OBJ FIFO, 6 dots,
+//    and up to 5 more for the BG fetch in progress to finish (once per tile)
+
+// how the PPU draws the picture
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Renderer {
+    // a whole line at the end of mode 3, which always takes 172 dots, fast
+    #[default]
+    Scanline,
+    // dot by dot, with mode 3 as long as on hardware, for mid-line register writes and games that time it
+    Fifo,
+}
+
+// the first fetch of every line is thrown away
+const WARMUP_DOTS: u8 = 6;
+const FETCH_STEP_DOTS: u8 = 2;
+const SPRITE_FETCH_DOTS: u8 = 6;
+const SPRITE_BG_WAIT_DOTS: u8 = 5;
+// mode 3 can't take more than what's left of the line after OAM search, with HBlank at least 8 dots
+const MAX_PIXEL_TRANSFER_DOTS: u64 = TICKS_ONE_LINE - TICKS_OAM_SEARCH - 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum FetchStep {
+    #[default]
+    Tile,
+    DataLow,
+    DataHigh,
+    Push,
+}
+
+#[derive(Debug, Default)]
+pub(super) struct PixelFifo {
+    bg: VecDeque<u8>,
+    obj: VecDeque<Option<ObjPixel>>,
+
+    step: FetchStep,
+    step_dots: u8,
+    // tile column, counted from the start of the line (or of the window)
+    fetch_x: u8,
+    tile_row_addr: u16,
+    low: u8,
+    high: u8,
+
+    // x of the next pixel drawn
+    pub(super) lx: u8,
+    fine_x: u8,
+    // pixels to shift out without drawing them
+    discard: u8,
+    warmup: u8,
+    window: bool,
+
+    // the next of the line's sprites, and the dots left fetching it
+    next_sprite: usize,
+    sprite_fetch: Option<u8>,
+    // the tile the last sprite waited for the BG fetch on
+    waited_tile: Option<u8>,
+
+    // how long mode 3 took so far
+    pub(super) dots: u64,
+}
+
+impl PixelFifo {
+    // at the start of mode 3, the fine scroll is taken from SCX once per line
+    pub(super) fn start_line(&mut self, scx: u8) {
+        *self = Self {
+            discard: scx % TILE_WIDTH_PIXELS,
+            fine_x: scx % TILE_WIDTH_PIXELS,
+            warmup: WARMUP_DOTS,
+            bg: std::mem::take(&mut self.bg),
+            obj: std::mem::take(&mut self.obj),
+            ..Default::default()
+        };
+        self.bg.clear();
+        self.obj.clear();
+    }
+}
+
+impl PPU {
+    // Runs mode 3 for `ticks` dots, or until the line is drawn. Returns the dots that are left over.
+    pub(super) fn fifo_pixel_transfer(&mut self, mut ticks: u64) -> Option<u64> {
+        while ticks > 0 {
+            ticks -= 1;
+            self.fifo.dots += 1;
+            if self.fifo_dot() || self.fifo.dots >= MAX_PIXEL_TRANSFER_DOTS {
+                if self.fifo.window {
+                    self.window_line = self.window_line.wrapping_add(1);
+                }
+                return Some(ticks);
+            }
+        }
+        None
+    }
+
+    // returns true once the last pixel of the line is drawn
+    fn fifo_dot(&mut self) -> bool {
+        if self.fifo.warmup > 0 {
+            self.fifo.warmup -= 1;
+            return false;
+        }
+
+        let lx = self.fifo.lx;
+        if !self.fifo.window && self.fifo.discard == 0 && self.window_visible() && lx as u16 + WINDOW_X_OFFSET as u16 >= self.wx as u16 {
+            self.fifo.window = true;
+            self.fifo.bg.clear();
+            self.fifo.step = FetchStep::Tile;
+            self.fifo.step_dots = 0;
+            self.fifo.fetch_x = 0;
+            // with WX < 7 the window starts off the left edge
+            if lx == 0 {
+                self.fifo.discard = WINDOW_X_OFFSET.saturating_sub(self.wx);
+            }
+        }
+
+        self.fetcher_dot();
+
+        // checked when there is a pixel to shift out at lx
+        if self.fifo.sprite_fetch.is_none() && self.fifo.discard == 0 && !self.fifo.bg.is_empty() {
+            while let Some(s) = self.line_sprites.get(self.fifo.next_sprite) && s.x_pos as i16 - (TILE_WIDTH_PIXELS as i16) <= lx as i16 {
+                if self.lcdc.object_enable {
+                    let x = lx.wrapping_add(self.fifo.fine_x);
+                    let tile = x / TILE_WIDTH_PIXELS;
+                    let wait = if self.fifo.waited_tile == Some(tile) {
+                        0
+                    } else {
+                        SPRITE_BG_WAIT_DOTS - (x % TILE_WIDTH_PIXELS).min(SPRITE_BG_WAIT_DOTS)
+                    };
+                    self.fifo.waited_tile = Some(tile);
+                    self.fifo.sprite_fetch = Some(SPRITE_FETCH_DOTS + wait);
+                    break;
+                }
+                // skipped while sprites are off
+                self.fifo.next_sprite += 1;
+            }
+        }
+
+        // the BG fetch carries on, but no pixels are shifted out
+        if let Some(left) = self.fifo.sprite_fetch {
+            if left > 1 {
+                self.fifo.sprite_fetch = Some(left - 1);
+            } else {
+                self.fetch_sprite();
+                self.fifo.sprite_fetch = None;
+                self.fifo.next_sprite += 1;
+            }
+            return false;
+        }
+
+        let Some(bg) = self.fifo.bg.pop_front() else {
+            return false;
+        };
+        if self.fifo.discard > 0 {
+            self.fifo.discard -= 1;
+            return false;
+        }
+        let obj = self.fifo.obj.pop_front().flatten();
+        let colour = self.mix_pixel(bg, obj);
+        self.screen.set(self.get_curr_scanline(), lx, colour);
+        self.fifo.lx += 1;
+        self.fifo.lx == SCREEN_WIDTH_PIXELS
+    }
+
+    fn fetcher_dot(&mut self) {
+        if self.fifo.step == FetchStep::Push {
+            if self.fifo.bg.is_empty() {
+                let (low, high) = (self.fifo.low, self.fifo.high);
+                self.fifo.bg.extend((0..TILE_WIDTH_PIXELS).rev().map(|n| ((get_nth_bit(high, n) as u8) << 1) | get_nth_bit(low, n) as u8));
+                self.fifo.fetch_x = self.fifo.fetch_x.wrapping_add(1);
+                self.fifo.step = FetchStep::Tile;
+            }
+            return;
+        }
+
+        self.fifo.step_dots += 1;
+        if self.fifo.step_dots < FETCH_STEP_DOTS {
+            return;
+        }
+        self.fifo.step_dots = 0;
+        self.fifo.step = match self.fifo.step {
+            FetchStep::Tile => {
+                // SCX is read again for every tile, only its fine part is kept for the line
+                let (map, x, y) = if self.fifo.window {
+                    (self.get_lcdc().window_tile_map, self.fifo.fetch_x, self.window_line)
+                } else {
+                    let x = (self.get_scroll_x() / TILE_WIDTH_PIXELS).wrapping_add(self.fifo.fetch_x);
+                    (self.get_lcdc().bg_tile_map, x, self.get_curr_scanline().wrapping_add(self.get_scroll_y()))
+                };
+                let x = (x as u16 % TILEMAP_WIDTH_TILES) as u8 * TILE_WIDTH_PIXELS;
+                self.fifo.tile_row_addr = self.get_tile_row_data_addr(map, x, y).into();
+                FetchStep::DataLow
+            }
+            FetchStep::DataLow => {
+                self.fifo.low = self.read_vram(self.fifo.tile_row_addr.into());
+                FetchStep::DataHigh
+            }
+            FetchStep::DataHigh => {
+                self.fifo.high = self.read_vram((self.fifo.tile_row_addr + 1).into());
+                FetchStep::Push
+            }
+            FetchStep::Push => unreachable!(),
+        };
+    }
+
+    // mixes the sprite into the OBJ FIFO, pixels already there from sprites drawn on top are kept
+    fn fetch_sprite(&mut self) {
+        let s = self.line_sprites[self.fifo.next_sprite];
+        let row = self.get_sprite_row(&s, self.get_curr_scanline());
+        for (i, pixel) in row.into_iter().enumerate() {
+            // where the pixel is, from the next one to be drawn
+            let offset = s.x_pos as i16 + i as i16 - TILE_WIDTH_PIXELS as i16 - self.fifo.lx as i16;
+            if offset < 0 {
+                continue;
+            }
+            let offset = offset as usize;
+            if self.fifo.obj.len() <= offset {
+                self.fifo.obj.resize(offset + 1, None);
+            }
+            if self.fifo.obj[offset].is_none() {
+                self.fifo.obj[offset] = pixel;
+            }
+        }
+    }
+}
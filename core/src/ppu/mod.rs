@@ -1,4 +1,5 @@
 mod colour;
+mod fifo;
 pub(crate) mod lcdc;
 mod oam;
 pub(crate) mod palette;
@@ -6,12 +7,13 @@ mod screen;
 pub(crate) mod status;
 
 use crate::{mmu::{ram::RAM, busio::BusIO}, util::{Addr, get_nth_bit}};
-use colour::Colour;
-use lcdc::LCDC;
+pub use colour::Colour;
+use fifo::PixelFifo;
+pub use fifo::Renderer;
+use lcdc::{LCDC, ObjectSize, TileMap};
 use oam::{OAM, Sprite, SpriteAttr, ObjPaletteType};  
 use palette::{BgWinPalette, ObjPalette};
-pub use screen::screen_u32;
-use screen::Screen;
+pub use screen::{screen_u32, Screen, SCREEN_HEIGHT, SCREEN_WIDTH};
 use status::{PpuMode, Status};
 
 use self::lcdc::TileData;
@@ -53,18 +55,42 @@ const TICKS_HBLANK: u64 = 204;
 const TICKS_ONE_LINE: u64 = TICKS_OAM_SEARCH + TICKS_PIXEL_TRANSFER + TICKS_HBLANK;
 const TICKS_VBLANK: u64 = VBLANK_LINES as u64 * TICKS_ONE_LINE;
 const TICKS_ONE_FRAME: u64 = SCREEN_HEIGHT_PIXELS as u64 * TICKS_ONE_LINE + TICKS_VBLANK;
+// the first line after the LCD is turned on skips OAM search, mode 3 starts a bit earlier than usual
+const TICKS_FIRST_LINE_MODE_0: u64 = TICKS_OAM_SEARCH - 4;
+
+// the window is drawn from screen x WX-7, WX above this is off screen
+const WINDOW_X_OFFSET: u8 = 7;
+const WINDOW_X_MAX: u8 = SCREEN_WIDTH_PIXELS + WINDOW_X_OFFSET - 1;
 
 const OAM_SIZE: usize = 0xa0;
 const MAX_SPRITES_PER_ROW: usize = 10;
 
 type ColourScreenRow = [Colour; SCREEN_WIDTH_PIXELS as usize];
-type ColourTileRow = [Colour; TILE_WIDTH_PIXELS as usize];
+// colour numbers (0-3) before they go through a palette
+type ScreenRow = [u8; SCREEN_WIDTH_PIXELS as usize];
+type TileRow = [u8; TILE_WIDTH_PIXELS as usize];
+
+// a sprite's pixel that made it onto the line
+#[derive(Debug, Clone, Copy)]
+struct ObjPixel {
+    colour: u8,
+    palette: ObjPaletteType,
+    behind_bg: bool,
+}
 
 
 #[derive(Debug)]
 pub struct PPU {
     ticks: u64,
+    pub(crate) renderer: Renderer,
+    fifo: PixelFifo,
+    // what's left of the line after mode 3, which the FIFO renderer makes longer or shorter
+    hblank_ticks: u64,
+    // the line after the LCD is turned on, it has no OAM search and shows mode 0 instead
+    first_line: bool,
     pub screen: Screen,
+    // number of frames completed, counted at the start of vblank (every frame's worth of ticks while the LCD is off)
+    pub(crate) frames: u64,
 
     // registers
     pub(crate) lcdc: LCDC,
@@ -73,18 +99,28 @@ pub struct PPU {
     pub(crate) scx: u8,
     pub(crate) scy: u8,
     pub(crate) curr_scanline: u8,
+    pub(crate) lyc: u8,
 
     pub(crate) wy: u8,
     pub(crate) wx: u8,
+    // WY matched LY at the start of a line this frame, the window can show from then on
+    window_y_triggered: bool,
+    // the window's own line counter, it only moves on lines the window was drawn on
+    window_line: u8,
 
     pub(crate) bgp: BgWinPalette,
     pub(crate) obp0: ObjPalette,
     pub(crate) obp1: ObjPalette,
 
     pub(crate) vblank_interrupt: bool,
+    pub(crate) stat_interrupt: bool,
+    // the sources enabled in STAT OR-ed together, the interrupt is requested when it goes high
+    stat_line: bool,
 
     pub(crate) vram: RAM,
     pub(crate) oam: OAM,
+    // the sprites OAM search picked for the current line, in drawing priority
+    line_sprites: Vec<Sprite>,
 
 }
 
@@ -92,26 +128,38 @@ impl PPU {
     pub(crate) fn new() -> Self {
         let ppu = Self {
             ticks: 0,
+            renderer: Renderer::Scanline,
+            fifo: Default::default(),
+            hblank_ticks: TICKS_HBLANK,
+            first_line: false,
             screen: Screen::new(),
+            frames: 0,
 
+            // off until LCDC is written, by the boot ROM or in its place
             lcdc: Default::default(),
-            status: Default::default(),
+            status: Status { mode: PpuMode::HBlank, ..Default::default() },
 
             scx: 0,
             scy: 0,
             curr_scanline: 0,
+            lyc: 0,
             
             wx: 0,
             wy: 0,
+            window_y_triggered: false,
+            window_line: 0,
 
             bgp: Default::default(),
             obp0: Default::default(),
             obp1: Default::default(),
 
             vblank_interrupt: false,
+            stat_interrupt: false,
+            stat_line: false,
 
             vram: RAM::new(8 * 1024, Box::new(|addr: Addr| addr - 0x8000.into()), 0),
             oam: OAM(RAM::new(OAM_SIZE, Box::new(|addr: Addr| addr - 0xfe00.into()), 0)),
+            line_sprites: Vec::with_capacity(MAX_SPRITES_PER_ROW),
         };
         ppu
     }
@@ -119,39 +167,72 @@ impl PPU {
     pub(crate) fn tick(&mut self, cpu_ticks: u64) {
         self.ticks += cpu_ticks;
 
+        // Nothing runs while the LCD is off, LY stays at 0 and no interrupts are requested. Frames
+        // are still counted, for the frontends that pace themselves on them.
+        if !self.lcdc.ppu_enable {
+            if self.ticks >= TICKS_ONE_FRAME {
+                self.ticks -= TICKS_ONE_FRAME;
+                self.frames += 1;
+            }
+            return;
+        }
+
         match self.status.mode {
             PpuMode::OAMSearch => {
                 if self.ticks >= TICKS_OAM_SEARCH {
                     self.ticks -= TICKS_OAM_SEARCH;
                     self.status.mode = PpuMode::PixelTransfer;
+                    self.fifo.start_line(self.get_scroll_x());
                 }
             }
-            PpuMode::PixelTransfer => {
-                if self.ticks >= TICKS_PIXEL_TRANSFER {
-                    self.ticks -= TICKS_PIXEL_TRANSFER;
-                    self.status.mode = PpuMode::HBlank;
+            PpuMode::PixelTransfer => match self.renderer {
+                Renderer::Scanline => {
+                    if self.ticks >= TICKS_PIXEL_TRANSFER {
+                        self.ticks -= TICKS_PIXEL_TRANSFER;
+                        self.status.mode = PpuMode::HBlank;
+                        self.hblank_ticks = TICKS_HBLANK;
 
-                    self.renderscan();
+                        self.renderscan();
+                    }
+                }
+                Renderer::Fifo => {
+                    if let Some(left) = self.fifo_pixel_transfer(self.ticks) {
+                        self.ticks = left;
+                        self.status.mode = PpuMode::HBlank;
+                        self.hblank_ticks = TICKS_ONE_LINE - TICKS_OAM_SEARCH - self.fifo.dots;
+                    } else {
+                        self.ticks = 0;
+                    }
+                }
+            },
+            PpuMode::HBlank if self.first_line => {
+                if self.ticks >= TICKS_FIRST_LINE_MODE_0 {
+                    self.ticks -= TICKS_FIRST_LINE_MODE_0;
+                    self.first_line = false;
+                    self.status.mode = PpuMode::PixelTransfer;
+                    self.start_line();
+                    self.fifo.start_line(self.get_scroll_x());
                 }
             }
             PpuMode::HBlank => {
-                if self.ticks >= TICKS_HBLANK {
-                    self.ticks -= TICKS_HBLANK;
+                if self.ticks >= self.hblank_ticks {
+                    self.ticks -= self.hblank_ticks;
                     self.incr_curr_scanline();
 
                     if self.get_curr_scanline() < SCREEN_HEIGHT_PIXELS {
                         self.status.mode = PpuMode::OAMSearch;
+                        self.start_line();
                     } else {
                         // println!("{}", self.screen);
                         // println!("{:?}", self.oam.into_iter().collect::<Vec<_>>());
                         // println!("{:?}", self.lcdc);
                         self.status.mode = PpuMode::VBlank;
+                        self.vblank_interrupt = true;
+                        self.frames += 1;
                     }
                 }
             }
             PpuMode::VBlank => {
-                self.vblank_interrupt = true;
-
                 if self.ticks >= TICKS_ONE_LINE {
                     self.ticks -= TICKS_ONE_LINE;
                     self.incr_curr_scanline();
@@ -159,10 +240,87 @@ impl PPU {
                     if !(self.get_curr_scanline() < SCREEN_HEIGHT_PIXELS + VBLANK_LINES) {
                         self.status.mode = PpuMode::OAMSearch;
                         self.set_curr_scanline(0);
+                        self.window_y_triggered = false;
+                        self.window_line = 0;
+                        self.start_line();
                     }
                 }
             }
         }
+        self.update_stat();
+    }
+
+    // Turning the LCD off stops the PPU where it is, with LY at 0 and a blank screen. Turning it back
+    // on starts a frame with a shorter first line.
+    pub(crate) fn write_lcdc(&mut self, value: u8) {
+        let lcdc = LCDC::from(value);
+        match (self.lcdc.ppu_enable, lcdc.ppu_enable) {
+            (true, false) => {
+                self.ticks = 0;
+                self.set_curr_scanline(0);
+                self.status.mode = PpuMode::HBlank;
+                self.first_line = false;
+                self.stat_line = false;
+                self.screen.clear();
+            }
+            (false, true) => {
+                self.ticks = 0;
+                self.first_line = true;
+                self.window_y_triggered = false;
+                self.window_line = 0;
+            }
+            _ => {}
+        }
+        self.lcdc = lcdc;
+        self.update_stat();
+    }
+
+    // STAT without the read only bits (mode and LY=LYC)
+    pub(crate) fn write_stat(&mut self, value: u8) {
+        let value = Status::from(value & 0b0111_1000);
+        self.status = Status {
+            lyc_equal: self.status.lyc_equal,
+            mode: self.status.mode,
+            ..value
+        };
+        self.update_stat();
+    }
+
+    pub(crate) fn write_lyc(&mut self, value: u8) {
+        self.lyc = value;
+        self.update_stat();
+    }
+
+    // Compares LY to LYC, and requests the STAT interrupt when any of the enabled sources becomes active
+    // while none was before. While one source is active the others can't request it ("STAT blocking").
+    fn update_stat(&mut self) {
+        if !self.lcdc.ppu_enable {
+            return;
+        }
+        self.status.lyc_equal = self.get_curr_scanline() == self.lyc;
+        let status = self.status;
+        let line = (status.lyc_int && status.lyc_equal)
+            // not on the first line after the LCD is turned on, it isn't a real HBlank
+            || (status.hblank_int && matches!(status.mode, PpuMode::HBlank) && !self.first_line)
+            || (status.vblank_int && matches!(status.mode, PpuMode::VBlank))
+            || (status.oam_int && matches!(status.mode, PpuMode::OAMSearch));
+        if line && !self.stat_line {
+            self.stat_interrupt = true;
+        }
+        self.stat_line = line;
+    }
+
+    // at the start of OAM search
+    fn start_line(&mut self) {
+        if self.get_curr_scanline() == self.wy {
+            self.window_y_triggered = true;
+        }
+
+        // The first 10 sprites in OAM that cover the line, whatever their X. On the DMG the one
+        // with the smaller X is drawn on top, and OAM order breaks ties (the sort is stable).
+        self.line_sprites.clear();
+        self.line_sprites.extend(self.oam.row(self.get_curr_scanline(), self.lcdc.object_size));
+        self.line_sprites.sort_by_key(|s| s.x_pos);
     }
 
     pub(crate) fn dma(&mut self, src: &[u8]) {
@@ -172,80 +330,145 @@ impl PPU {
     fn renderscan(&mut self) {
         let py = self.get_curr_scanline();
 
-        let bg_win_data: ColourScreenRow = if self.lcdc.bg_window_enable {
-            let bg_win_data: [ColourTileRow; SCREEN_WIDTH_TILES as usize] = (0..SCREEN_WIDTH_TILES)
-                .map(|tx| self.get_bg_tile_row(TILE_WIDTH_PIXELS * tx, py))
-                .collect::<Vec<_>>()
-                .try_into()
-                .unwrap();
-            unsafe { std::mem::transmute(bg_win_data) }
-        } else {
-            [Colour::White; SCREEN_WIDTH_PIXELS as usize]
-        };
+        let mut bg_win_data: ScreenRow = [0; SCREEN_WIDTH_PIXELS as usize];
+        if self.lcdc.bg_window_enable {
+            // one tile more than fits on the line, the fine part of SCX shifts them left
+            let mut tiles = [0; (SCREEN_WIDTH_PIXELS + TILE_WIDTH_PIXELS) as usize];
+            for tx in 0..=SCREEN_WIDTH_TILES {
+                let px = (TILE_WIDTH_PIXELS * tx) as usize;
+                tiles[px..][..TILE_WIDTH_PIXELS as usize].copy_from_slice(&self.get_bg_tile_row(TILE_WIDTH_PIXELS * tx, py));
+            }
+            let fine_x = (self.get_scroll_x() % TILE_WIDTH_PIXELS) as usize;
+            bg_win_data.copy_from_slice(&tiles[fine_x..][..SCREEN_WIDTH_PIXELS as usize]);
+        }
+
+        if self.window_visible() {
+            self.draw_window(&mut bg_win_data);
+            self.window_line = self.window_line.wrapping_add(1);
+        }
 
-        let row_data: Box<dyn Iterator<Item = Colour>> = if self.lcdc.object_enable {
-            let sprite_data: ColourScreenRow = self.get_sprite_tile_data(py);
-            Box::from(sprite_data.into_iter().zip(bg_win_data.into_iter()).map(|(s, bw)| s | bw))
+        let obj_data = if self.lcdc.object_enable {
+            self.get_sprite_tile_data(py)
         } else {
-            Box::from(bg_win_data.into_iter())
+            [None; SCREEN_WIDTH_PIXELS as usize]
         };
 
-        // let row_data = Box::from(bg_win_data.into_iter());
+        let mut row: ColourScreenRow = [Colour::White; SCREEN_WIDTH_PIXELS as usize];
+        for (px, (bg, obj)) in bg_win_data.into_iter().zip(obj_data).enumerate() {
+            row[px] = self.mix_pixel(bg, obj);
+        }
 
-        self.update_row(py, row_data);
+        self.update_row(py, Box::from(row.into_iter()));
     }
 
-    fn get_sprite_tile_data(&mut self, py: u8) -> ColourScreenRow {
-        let mut row: ColourScreenRow = [Colour::Transparent; SCREEN_WIDTH_PIXELS as usize];
-        for s in self.oam.row(py, self.lcdc.object_size) {
-            let mut distance_from_top_of_sprite = py - (s.y_pos - 16);
-            if s.attr.y_flip {
-                let sprite_height = self.lcdc.object_size as u8;
-                distance_from_top_of_sprite = sprite_height - distance_from_top_of_sprite;
+    // The pixels of the line's sprites, each taken by the first sprite in priority order that isn't
+    // transparent there, even if that sprite ends up behind the BG.
+    fn get_sprite_tile_data(&self, py: u8) -> [Option<ObjPixel>; SCREEN_WIDTH_PIXELS as usize] {
+        let mut row = [None; SCREEN_WIDTH_PIXELS as usize];
+        for s in self.line_sprites.iter() {
+            for (i, pixel) in self.get_sprite_row(s, py).into_iter().enumerate() {
+                // x_pos is the screen x + 8
+                let px = s.x_pos as i16 + i as i16 - TILE_WIDTH_PIXELS as i16;
+                if (0..SCREEN_WIDTH_PIXELS as i16).contains(&px) && row[px as usize].is_none() {
+                    row[px as usize] = pixel;
+                }
             }
-            
-            let ta = TileData::Low.get_tile_data_addr(s.ti | (distance_from_top_of_sprite > TILE_HEIGHT_PIXELS) as u8);
-            
-            let ro = 2 * (distance_from_top_of_sprite % TILE_HEIGHT_PIXELS) as u16; // 2 bytes per row * (spy % 8)
-            let tra = ta + ro.into();
-
-            let lb = self.read_vram(tra);
-            let hb = self.read_vram(tra + 1.into());
-            (0..TILE_WIDTH_PIXELS)
-                .rev()
-                .map(|n| self.get_obj_palette(&s).map(((get_nth_bit(hb, n) as u8) << 1) | get_nth_bit(lb, n) as u8).unwrap())
-                .enumerate()
-                .for_each(|(i, c)| {
-                    let i = if s.attr.x_flip { TILE_WIDTH_PIXELS as usize - i } else { i };
-                    if (s.x_pos + i as u8) > 8 && (s.x_pos + i as u8) < (SCREEN_WIDTH_PIXELS + 8) { 
-                        row[s.x_pos as usize + i - 8] = c;
-                    }
-                });
+        }
+        row
+    }
+
+    // the sprite's pixels on line `py`, left to right as they're shown, None where it's transparent
+    fn get_sprite_row(&self, s: &Sprite, py: u8) -> [Option<ObjPixel>; TILE_WIDTH_PIXELS as usize] {
+        let sprite_height = self.lcdc.object_size as u8;
+        let mut distance_from_top_of_sprite = py.wrapping_sub(s.y_pos.wrapping_sub(16));
+        if s.attr.y_flip {
+            distance_from_top_of_sprite = sprite_height - 1 - distance_from_top_of_sprite;
+        }
+
+        // 8x16 sprites ignore bit 0 of the tile index, the top half is the even tile
+        let ti = match self.lcdc.object_size {
+            ObjectSize::Short => s.ti,
+            ObjectSize::Long  => (s.ti & 0xfe) | (distance_from_top_of_sprite >= TILE_HEIGHT_PIXELS) as u8,
         };
+        let ta = TileData::Low.get_tile_data_addr(ti);
+
+        let ro = 2 * (distance_from_top_of_sprite % TILE_HEIGHT_PIXELS) as u16; // 2 bytes per row * (spy % 8)
+        let tra = ta + ro.into();
+
+        let lb = self.read_vram(tra);
+        let hb = self.read_vram(tra + 1.into());
+        let mut row = [None; TILE_WIDTH_PIXELS as usize];
+        for (i, pixel) in row.iter_mut().enumerate() {
+            let n = if s.attr.x_flip { i as u8 } else { TILE_WIDTH_PIXELS - 1 - i as u8 };
+            let colour = ((get_nth_bit(hb, n) as u8) << 1) | get_nth_bit(lb, n) as u8;
+            // colour 0 is transparent, whatever the palette
+            if colour != 0 {
+                *pixel = Some(ObjPixel {
+                    colour,
+                    palette: s.attr.palette,
+                    behind_bg: s.attr.bg_win_over_obj,
+                });
+            }
+        }
         row
     }
 
-    fn get_obj_palette(&self, s: &Sprite) -> &ObjPalette {
-        match s.attr.palette {
+    // the colour shown for a BG/window colour number and the sprite pixel on top of it
+    fn mix_pixel(&self, bg: u8, obj: Option<ObjPixel>) -> Colour {
+        match obj {
+            // BG-over-OBJ only lets BG colour 0 through
+            Some(obj) if !(obj.behind_bg && bg != 0) => self.get_obj_palette(obj.palette).map(obj.colour).unwrap(),
+            // with the BG off it's white, whatever BGP says
+            _ if !self.lcdc.bg_window_enable => Colour::White,
+            _ => self.bgp.map(bg).unwrap(),
+        }
+    }
+
+    // whether the window shows on this line, from WX-7 on
+    fn window_visible(&self) -> bool {
+        // on the DMG, clearing the BG enable bit hides the window as well
+        self.lcdc.bg_window_enable && self.lcdc.window_enable && self.window_y_triggered && self.wx <= WINDOW_X_MAX
+    }
+
+    // the window covers the row from WX-7 to the right edge, with WX < 7 its first 7-WX pixels are cut off
+    fn draw_window(&self, row: &mut ScreenRow) {
+        let start = self.wx.saturating_sub(WINDOW_X_OFFSET);
+        let mut tile_row = [0; TILE_WIDTH_PIXELS as usize];
+        for px in start..SCREEN_WIDTH_PIXELS {
+            let wpx = px + WINDOW_X_OFFSET - self.wx;
+            if px == start || wpx % TILE_WIDTH_PIXELS == 0 {
+                tile_row = self.get_win_tile_row(wpx, self.window_line);
+            }
+            row[px as usize] = tile_row[(wpx % TILE_WIDTH_PIXELS) as usize];
+        }
+    }
+
+    fn get_win_tile_row(&self, wpx: u8, wpy: u8) -> TileRow {
+        let tra = self.get_tile_row_data_addr(self.get_lcdc().window_tile_map, wpx, wpy);
+        self.get_tile_row_data(tra)
+    }
+
+    fn get_obj_palette(&self, palette: ObjPaletteType) -> &ObjPalette {
+        match palette {
             ObjPaletteType::OBP0 => &self.obp0,
             ObjPaletteType::OBP1 => &self.obp1,
         }
     }
 
-    fn get_bg_tile_row(&self, px: u8, py: u8) -> ColourTileRow {
+    fn get_bg_tile_row(&self, px: u8, py: u8) -> TileRow {
         let (spx, spy) = self.adjust_viewport_scroll(px, py);
-        let tra = self.get_tile_row_data_addr(spx, spy);
+        let tra = self.get_tile_row_data_addr(self.get_lcdc().bg_tile_map, spx, spy);
         self.get_tile_row_data(tra)
     }
 
     fn adjust_viewport_scroll(&self, px: u8, py: u8) -> (u8, u8) {
         // adjusting for viewport offsets
-        let spx = px + self.get_scroll_x();
-        let spy = py + self.get_scroll_y();
+        let spx = px.wrapping_add(self.get_scroll_x());
+        let spy = py.wrapping_add(self.get_scroll_y());
         (spx, spy)
     }
 
-    fn get_tile_data_addr(&self, spx: u8, spy: u8) -> Addr {
+    fn get_tile_data_addr(&self, map: TileMap, spx: u8, spy: u8) -> Addr {
         // get tile number
         let ty = spy >> 3; // spy / 8
         let tx = spx >> 3; // spx / 8
@@ -253,7 +476,7 @@ impl PPU {
         let to = (ty as u16) * TILEMAP_WIDTH_TILES + tx as u16; // 32 = Number of tiles in a row = 256(number of pixels per row)/8(number of pixels per tile row)
 
         // get tile index from the right tile map
-        let map: Addr = self.get_lcdc().bg_tile_map.into();
+        let map: Addr = map.into();
         let ti = self.read_vram(map + to.into());
 
         // get the address where tile data is stored
@@ -261,8 +484,8 @@ impl PPU {
         ta
     }
 
-    fn get_tile_row_data_addr(&self, spx: u8, spy: u8) -> Addr {
-        let ta = self.get_tile_data_addr(spx, spy);
+    fn get_tile_row_data_addr(&self, map: TileMap, spx: u8, spy: u8) -> Addr {
+        let ta = self.get_tile_data_addr(map, spx, spy);
 
         // get the address where the row data is stored
         let ro = 2 * (spy % TILE_HEIGHT_PIXELS) as u16; // 2 bytes per row * (spy % 8)
@@ -270,19 +493,15 @@ impl PPU {
         tra
     }
 
-    fn get_tile_row_data(&self, tra: Addr) -> ColourTileRow {
-        // read the row colour data
+    // the colour numbers of a tile row, the palette is applied once sprites are mixed in
+    fn get_tile_row_data(&self, tra: Addr) -> TileRow {
         let lb = self.read_vram(tra);
         let hb = self.read_vram(tra + 1.into());
-        (0..TILE_WIDTH_PIXELS)
-            .rev()
-            .map(|n| {
-                self.bgp.map(((get_nth_bit(hb, n) as u8) << 1) | get_nth_bit(lb, n) as u8)
-                    .unwrap()
-            })
-            .collect::<Vec<Colour>>()
-            .try_into()
-            .unwrap()
+        let mut row = [0; TILE_WIDTH_PIXELS as usize];
+        for (i, n) in (0..TILE_WIDTH_PIXELS).rev().enumerate() {
+            row[i] = ((get_nth_bit(hb, n) as u8) << 1) | get_nth_bit(lb, n) as u8;
+        }
+        row
     }
 
     // fn update_row<T: Iterator<Item = Colour>>(&mut self, row: u8, row_data: T) {
@@ -307,6 +526,7 @@ impl PPU {
 // 0xFF42	Scroll-Y	            Read/write
 // 0xFF43	Scroll-X	            Read/write
 // 0xFF44	Current scan line	    Read only
+// 0xFF45	LY compare	            Read/write
 // 0xFF47	Background palette	    Write only
 
 pub(crate) const REG_LCDC: u16 = 0xff40;
@@ -316,6 +536,7 @@ pub(crate) const REG_SCROLL_X: u16 = 0xff43;
 pub(crate) const REG_WIN_Y: u16 = 0xff4a;
 pub(crate) const REG_WIN_X: u16 = 0xff4b;
 pub(crate) const REG_CURR_SCANLINE: u16 = 0xff44;
+pub(crate) const REG_LYC: u16 = 0xff45;
 pub(crate) const REG_BG_PALETTE: u16 = 0xff47;
 pub(crate) const REG_OBJ_PALETTE_0: u16 = 0xff48;
 pub(crate) const REG_OBJ_PALETTE_1: u16 = 0xff49;
@@ -352,3 +573,462 @@ impl PPU {
         self.bgp = palette;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LCDC_ON: u8 = 0x93; // BG and sprites on, tiles at 0x8000
+    const LCDC_ON_TALL: u8 = 0x97; // the same with 8x16 sprites
+
+    // every row of the tile is `row`, as (low, high) bytes
+    fn tile(ppu: &mut PPU, index: u8, rows: [(u8, u8); 8]) {
+        for (i, (low, high)) in rows.into_iter().enumerate() {
+            let addr = 0x8000 + 16 * index as u16 + 2 * i as u16;
+            ppu.vram.writeu8(addr.into(), low).unwrap();
+            ppu.vram.writeu8((addr + 1).into(), high).unwrap();
+        }
+    }
+
+    // (y, x, tile, attributes), in OAM order
+    fn ppu(lcdc: u8, sprites: &[(u8, u8, u8, u8)]) -> PPU {
+        let mut ppu = PPU::new();
+        ppu.bgp = BgWinPalette::from(0xe4);
+        ppu.obp0 = ObjPalette::from(0xe4);
+        ppu.obp1 = ObjPalette::from(0xe4);
+        let mut oam = [0; OAM_SIZE];
+        for (i, s) in sprites.iter().enumerate() {
+            oam[4 * i..][..4].copy_from_slice(&[s.0, s.1, s.2, s.3]);
+        }
+        ppu.dma(&oam);
+        ppu.write_lcdc(lcdc);
+        ppu
+    }
+
+    // draws line `ly` with both renderers, which have to agree
+    fn render(ppu: &mut PPU, ly: u8) -> Vec<Colour> {
+        ppu.curr_scanline = ly;
+        let line = |ppu: &PPU| (0..SCREEN_WIDTH).map(|x| ppu.screen.pixel(x, ly as usize)).collect::<Vec<_>>();
+
+        ppu.start_line();
+        let window_line = ppu.window_line;
+        ppu.renderscan();
+        let scanline = (line(ppu), ppu.window_line);
+
+        ppu.screen.clear();
+        ppu.window_line = window_line;
+        ppu.fifo.start_line(ppu.get_scroll_x());
+        assert!(ppu.fifo_pixel_transfer(TICKS_ONE_LINE).is_some());
+        let fifo = (line(ppu), ppu.window_line);
+
+        assert_eq!(scanline, fifo, "the renderers disagree on line {}", ly);
+        scanline.0
+    }
+
+    #[test]
+    fn x_flip() {
+        use Colour::*;
+        // black on the left, light grey elsewhere
+        let sprites = [(16, 8, 1, 0), (16, 24, 1, 0x20)];
+        let mut ppu = ppu(LCDC_ON, &sprites);
+        tile(&mut ppu, 1, [(0xff, 0x80); 8]);
+        let line = render(&mut ppu, 0);
+        assert_eq!(line[0..8], [Black, LightGrey, LightGrey, LightGrey, LightGrey, LightGrey, LightGrey, LightGrey]);
+        assert_eq!(line[16..24], [LightGrey, LightGrey, LightGrey, LightGrey, LightGrey, LightGrey, LightGrey, Black]);
+    }
+
+    #[test]
+    fn tall_sprites() {
+        // the top tile is light grey, the bottom one dark grey with a black last row
+        let mut bottom = [(0x00, 0xff); 8];
+        bottom[7] = (0xff, 0xff);
+        // odd tile index, y-flipped, and both
+        let sprites = [(16, 8, 3, 0), (16, 16, 2, 0x40), (16, 24, 3, 0x40)];
+        let mut ppu = ppu(LCDC_ON_TALL, &sprites);
+        tile(&mut ppu, 2, [(0xff, 0x00); 8]);
+        tile(&mut ppu, 3, bottom);
+
+        // bit 0 of the index is ignored, the top half is tile 2
+        assert_eq!(render(&mut ppu, 0)[0], Colour::LightGrey);
+        assert_eq!(render(&mut ppu, 8)[0], Colour::DarkGrey);
+        assert_eq!(render(&mut ppu, 15)[0], Colour::Black);
+
+        // flipped as a whole, the last row of tile 3 is on top
+        for x in [8, 16] {
+            assert_eq!(render(&mut ppu, 0)[x], Colour::Black);
+            assert_eq!(render(&mut ppu, 1)[x], Colour::DarkGrey);
+            assert_eq!(render(&mut ppu, 8)[x], Colour::LightGrey);
+            assert_eq!(render(&mut ppu, 15)[x], Colour::LightGrey);
+        }
+    }
+
+    #[test]
+    fn ten_sprites_per_line_in_oam_order() {
+        // 10 sprites from x 14 on, then an 11th one at x 0, which has the smallest X but comes too late in OAM
+        let mut sprites: Vec<_> = (1..=10).map(|i| (16, 8 + 14 * i, 1, 0)).collect();
+        sprites.push((16, 8, 1, 0));
+        let mut ppu = ppu(LCDC_ON, &sprites);
+        tile(&mut ppu, 1, [(0xff, 0xff); 8]);
+
+        let line = render(&mut ppu, 0);
+        assert_eq!(line[0], Colour::White);
+        for i in 1..=10 {
+            assert_eq!(line[14 * i], Colour::Black, "sprite at x {}", 14 * i);
+        }
+
+        // sprites off the line don't count
+        sprites.insert(0, (40, 8, 1, 0));
+        let mut ppu = self::ppu(LCDC_ON, &sprites);
+        tile(&mut ppu, 1, [(0xff, 0xff); 8]);
+        assert_eq!(render(&mut ppu, 0)[0], Colour::White);
+    }
+
+    #[test]
+    fn priority() {
+        // equal X: the first in OAM is on top, light grey over black
+        // overlapping: the smaller X is on top, dark grey over the light grey sprite that comes first in OAM
+        let sprites = [(16, 8, 1, 0), (16, 8, 2, 0), (16, 36, 1, 0), (16, 32, 3, 0)];
+        let mut ppu = ppu(LCDC_ON, &sprites);
+        tile(&mut ppu, 1, [(0xff, 0x00); 8]);
+        tile(&mut ppu, 2, [(0xff, 0xff); 8]);
+        tile(&mut ppu, 3, [(0x00, 0xff); 8]);
+
+        let line = render(&mut ppu, 0);
+        assert_eq!(line[0], Colour::LightGrey);
+        assert_eq!(line[28], Colour::DarkGrey);
+        assert_eq!(line[32], Colour::LightGrey);
+    }
+
+    #[test]
+    fn bg_over_obj() {
+        // the BG is colour 0 except for the tile at x 8-15, which is dark grey
+        let sprites = [(16, 12, 1, 0x80)];
+        let mut ppu = ppu(LCDC_ON, &sprites);
+        tile(&mut ppu, 1, [(0xff, 0xff); 8]);
+        tile(&mut ppu, 2, [(0x00, 0xff); 8]);
+        ppu.vram.writeu8(0x9801.into(), 2).unwrap();
+
+        let line = render(&mut ppu, 0);
+        // shows through BG colour 0 only
+        assert_eq!(line[4..8], [Colour::Black; 4]);
+        assert_eq!(line[8..12], [Colour::DarkGrey; 4]);
+    }
+
+    const LCDC_WINDOW: u8 = 0xf1; // BG and window on, the window's map at 0x9c00, no sprites
+
+    // The window's tile: dark grey then light grey on the first row, black on the second and white
+    // below. The BG is white.
+    fn window_ppu(wx: u8) -> PPU {
+        let mut rows = [(0x00, 0x00); 8];
+        rows[0] = (0x0f, 0xf0);
+        rows[1] = (0xff, 0xff);
+        let mut ppu = ppu(LCDC_WINDOW, &[]);
+        tile(&mut ppu, 1, rows);
+        for i in 0..32 {
+            ppu.vram.writeu8((0x9c00 + i).into(), 1).unwrap();
+        }
+        ppu.wx = wx;
+        ppu
+    }
+
+    #[test]
+    fn window_left_edge() {
+        use Colour::*;
+        let line = render(&mut window_ppu(7), 0);
+        assert_eq!(line[0..8], [DarkGrey, DarkGrey, DarkGrey, DarkGrey, LightGrey, LightGrey, LightGrey, LightGrey]);
+
+        // WX < 7 cuts off the first 7-WX pixels
+        let line = render(&mut window_ppu(3), 0);
+        assert_eq!(line[0..8], [LightGrey, LightGrey, LightGrey, LightGrey, DarkGrey, DarkGrey, DarkGrey, DarkGrey]);
+    }
+
+    #[test]
+    fn window_right_edge() {
+        // only its first pixel is on screen
+        let line = render(&mut window_ppu(166), 0);
+        assert_eq!(line[158], Colour::White);
+        assert_eq!(line[159], Colour::DarkGrey);
+        assert_eq!(render(&mut window_ppu(167), 0)[159], Colour::White);
+    }
+
+    #[test]
+    fn window_line_counter() {
+        let mut ppu = window_ppu(7);
+        ppu.wy = 1;
+        // not before WY
+        assert_eq!(render(&mut ppu, 0)[0], Colour::White);
+        assert_eq!(render(&mut ppu, 1)[0], Colour::DarkGrey);
+        assert_eq!(ppu.window_line, 1);
+
+        // the counter stays where it is while the window is off
+        ppu.write_lcdc(LCDC_WINDOW & !0x20);
+        assert_eq!(render(&mut ppu, 2)[0], Colour::White);
+        assert_eq!(render(&mut ppu, 3)[0], Colour::White);
+        assert_eq!(ppu.window_line, 1);
+
+        // and it picks up with the window's second line
+        ppu.write_lcdc(LCDC_WINDOW);
+        assert_eq!(render(&mut ppu, 4)[0], Colour::Black);
+        assert_eq!(ppu.window_line, 2);
+    }
+
+    #[test]
+    fn scroll_wraps() {
+        let mut ppu = window_ppu(7);
+        ppu.write_lcdc(LCDC_WINDOW & !0x20);
+        ppu.scx = 250;
+        ppu.scy = 250;
+        render(&mut ppu, 143);
+    }
+
+    #[test]
+    fn fine_scroll() {
+        // the first pixel of tiles 0 and 31 of the map's first row is black
+        let mut ppu = ppu(LCDC_ON, &[]);
+        tile(&mut ppu, 1, [(0x80, 0x80); 8]);
+        ppu.vram.writeu8(0x9800.into(), 1).unwrap();
+        ppu.vram.writeu8(0x981f.into(), 1).unwrap();
+
+        // 13 pixels left of the map's origin, the last tile is wrapped around to the start
+        ppu.scx = 0xf3;
+        let line = render(&mut ppu, 0);
+        let black = (0..SCREEN_WIDTH).filter(|&x| line[x] == Colour::Black).collect::<Vec<_>>();
+        assert_eq!(black, [5, 13]);
+    }
+
+    // runs mode 3 of line `ly` with the FIFO, returns how many dots it took
+    fn mode3_dots(ppu: &mut PPU, ly: u8) -> u64 {
+        ppu.curr_scanline = ly;
+        ppu.start_line();
+        ppu.fifo.start_line(ppu.get_scroll_x());
+        assert!(ppu.fifo_pixel_transfer(TICKS_ONE_LINE).is_some());
+        ppu.fifo.dots
+    }
+
+    #[test]
+    fn mode3_fine_scroll() {
+        for scx in 0..=8 {
+            let mut ppu = ppu(LCDC_ON, &[]);
+            ppu.scx = scx;
+            assert_eq!(mode3_dots(&mut ppu, 0), 172 + scx as u64 % 8, "SCX {}", scx);
+        }
+    }
+
+    #[test]
+    fn mode3_window() {
+        // 6 dots to restart the fetcher, unless the window starts the line
+        assert_eq!(mode3_dots(&mut window_ppu(87), 0), 178);
+        assert_eq!(mode3_dots(&mut window_ppu(166), 0), 178);
+        assert_eq!(mode3_dots(&mut window_ppu(7), 0), 172);
+
+        let mut ppu = window_ppu(87);
+        ppu.wy = 1;
+        assert_eq!(mode3_dots(&mut ppu, 0), 172, "not before WY");
+    }
+
+    #[test]
+    fn mode3_sprites() {
+        // 6 dots each, plus up to 5 for the BG fetch to finish, depending on where in the tile it starts
+        assert_eq!(mode3_dots(&mut ppu(LCDC_ON, &[(16, 8, 1, 0)]), 0), 172 + 6 + 5);
+        assert_eq!(mode3_dots(&mut ppu(LCDC_ON, &[(16, 12, 1, 0)]), 0), 172 + 6 + 1);
+        assert_eq!(mode3_dots(&mut ppu(LCDC_ON, &[(16, 13, 1, 0)]), 0), 172 + 6);
+
+        // only the first sprite in a tile waits
+        assert_eq!(mode3_dots(&mut ppu(LCDC_ON, &[(16, 8, 1, 0), (16, 10, 1, 0)]), 0), 172 + 6 + 5 + 6);
+        let sprites = [(16, 8, 1, 0); 10];
+        assert_eq!(mode3_dots(&mut ppu(LCDC_ON, &sprites), 0), 172 + 6 + 5 + 9 * 6);
+
+        // the fine scroll moves the sprite within the BG tile
+        let mut ppu = self::ppu(LCDC_ON, &[(16, 8, 1, 0)]);
+        ppu.scx = 4;
+        assert_eq!(mode3_dots(&mut ppu, 0), 172 + 4 + 6 + 1);
+
+        // off screen, on another line, or with sprites off, no time is taken
+        assert_eq!(mode3_dots(&mut self::ppu(LCDC_ON, &[(16, 168, 1, 0)]), 0), 172);
+        assert_eq!(mode3_dots(&mut self::ppu(LCDC_ON, &[(32, 8, 1, 0)]), 0), 172);
+        assert_eq!(mode3_dots(&mut self::ppu(LCDC_ON & !0x02, &[(16, 8, 1, 0)]), 0), 172);
+    }
+
+    // starts mode 3 of line 0 with the FIFO, and runs it until pixel `x` is next
+    fn fifo_until(ppu: &mut PPU, x: u8) {
+        ppu.curr_scanline = 0;
+        ppu.start_line();
+        ppu.fifo.start_line(ppu.get_scroll_x());
+        while ppu.fifo.lx < x {
+            assert!(ppu.fifo_pixel_transfer(1).is_none());
+        }
+    }
+
+    #[test]
+    fn mid_line_palette_write() {
+        let mut ppu = ppu(LCDC_ON, &[]);
+        fifo_until(&mut ppu, 50);
+        ppu.bgp = BgWinPalette::from(0xff);
+        assert!(ppu.fifo_pixel_transfer(TICKS_ONE_LINE).is_some());
+
+        // from the next pixel drawn on
+        let line = (0..SCREEN_WIDTH).map(|x| ppu.screen.pixel(x, 0)).collect::<Vec<_>>();
+        assert_eq!(line[..50], [Colour::White; 50]);
+        assert_eq!(line[50..], [Colour::Black; 110]);
+    }
+
+    #[test]
+    fn mid_line_scx_write() {
+        // the map's first 20 tiles are white, the rest black
+        let mut ppu = ppu(LCDC_ON, &[]);
+        tile(&mut ppu, 1, [(0xff, 0xff); 8]);
+        for i in 20..32 {
+            ppu.vram.writeu8((0x9800 + i).into(), 1).unwrap();
+        }
+        fifo_until(&mut ppu, 40);
+        ppu.scx = 8 * 8 + 3;
+        assert!(ppu.fifo_pixel_transfer(TICKS_ONE_LINE).is_some());
+
+        // The tiles fetched after it are 8 further on, black from tile 12 of the line instead of 20. The
+        // fine scroll is kept from the start of the line.
+        let line = (0..SCREEN_WIDTH).map(|x| ppu.screen.pixel(x, 0)).collect::<Vec<_>>();
+        assert_eq!(line[..96], [Colour::White; 96]);
+        assert_eq!(line[96..], [Colour::Black; 64]);
+    }
+
+    // ticks one machine cycle at a time until `done`, which has to happen within a frame
+    fn run_until(ppu: &mut PPU, done: impl Fn(&PPU) -> bool) {
+        for _ in 0..TICKS_ONE_FRAME / 4 {
+            if done(ppu) {
+                return;
+            }
+            ppu.tick(4);
+        }
+        panic!("not there after a frame");
+    }
+
+    fn mode(ppu: &PPU) -> u8 {
+        u8::from(ppu.status) & 0b11
+    }
+
+    #[test]
+    fn lyc_interrupt() {
+        let mut ppu = ppu(LCDC_ON, &[]);
+        ppu.write_lyc(5);
+        ppu.write_stat(0x40);
+        run_until(&mut ppu, |p| p.curr_scanline == 4);
+        assert!(!ppu.stat_interrupt);
+
+        run_until(&mut ppu, |p| p.curr_scanline == 5);
+        assert!(ppu.stat_interrupt, "requested as LY becomes LYC");
+        assert_eq!(u8::from(ppu.status) & 0x04, 0x04);
+
+        ppu.stat_interrupt = false;
+        run_until(&mut ppu, |p| p.curr_scanline == 6);
+        assert!(!ppu.stat_interrupt, "once for the line");
+        assert_eq!(u8::from(ppu.status) & 0x04, 0);
+
+        // writing LYC compares right away
+        ppu.write_lyc(6);
+        assert!(ppu.stat_interrupt);
+    }
+
+    #[test]
+    fn stat_blocking() {
+        let mut ppu = ppu(LCDC_ON, &[]);
+        ppu.write_lyc(3);
+        ppu.write_stat(0x48); // LY=LYC and HBlank
+        run_until(&mut ppu, |p| p.curr_scanline == 2 && mode(p) == 0);
+        assert!(ppu.stat_interrupt);
+        ppu.stat_interrupt = false;
+
+        // HBlank is still active as LY becomes LYC, and then LY=LYC is as HBlank starts
+        run_until(&mut ppu, |p| p.curr_scanline == 3);
+        assert!(!ppu.stat_interrupt, "blocked by HBlank");
+        run_until(&mut ppu, |p| p.curr_scanline == 3 && mode(p) == 0);
+        assert!(!ppu.stat_interrupt, "blocked by LY=LYC");
+
+        run_until(&mut ppu, |p| p.curr_scanline == 4 && mode(p) == 0);
+        assert!(ppu.stat_interrupt, "HBlank on its own again");
+    }
+
+    #[test]
+    fn vblank_once() {
+        let mut ppu = ppu(LCDC_ON, &[]);
+        ppu.write_stat(0x10);
+        run_until(&mut ppu, |p| mode(p) == 1);
+        assert!(ppu.vblank_interrupt);
+        assert!(ppu.stat_interrupt);
+        ppu.vblank_interrupt = false;
+        ppu.stat_interrupt = false;
+
+        // not again for the other 9 lines
+        run_until(&mut ppu, |p| p.curr_scanline == 0);
+        assert!(!ppu.vblank_interrupt);
+        assert!(!ppu.stat_interrupt);
+    }
+
+    #[test]
+    fn lcd_off() {
+        // tile 0 is black, so is every line that's been drawn
+        let mut ppu = ppu(LCDC_ON, &[]);
+        tile(&mut ppu, 0, [(0xff, 0xff); 8]);
+        ppu.write_stat(0x78);
+        run_until(&mut ppu, |p| p.curr_scanline == 5 && mode(p) == 3);
+        assert_eq!(ppu.screen.pixel(0, 0), Colour::Black);
+
+        ppu.write_lcdc(LCDC_ON & !0x80);
+        assert_eq!(ppu.curr_scanline, 0);
+        assert_eq!(mode(&ppu), 0);
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                assert_eq!(ppu.screen.pixel(x, y), Colour::White);
+            }
+        }
+
+        // nothing for a couple of frames, but they're still counted
+        ppu.vblank_interrupt = false;
+        ppu.stat_interrupt = false;
+        let frames = ppu.frames;
+        for _ in 0..2 * TICKS_ONE_FRAME / 4 {
+            ppu.tick(4);
+            assert_eq!((ppu.curr_scanline, mode(&ppu)), (0, 0));
+        }
+        assert!(!ppu.vblank_interrupt);
+        assert!(!ppu.stat_interrupt);
+        assert_eq!(ppu.frames, frames + 2);
+    }
+
+    #[test]
+    fn lcd_on_first_line() {
+        let mut ppu = ppu(LCDC_ON & !0x80, &[]);
+        ppu.write_stat(0x28); // OAM search and HBlank
+        ppu.write_lcdc(LCDC_ON);
+
+        // mode 0 without the HBlank interrupt, and straight to mode 3 without an OAM search
+        for _ in 0..TICKS_FIRST_LINE_MODE_0 / 4 {
+            assert_eq!(mode(&ppu), 0);
+            ppu.tick(4);
+        }
+        assert_eq!(mode(&ppu), 3);
+        assert_eq!(ppu.curr_scanline, 0);
+        assert!(!ppu.stat_interrupt);
+
+        // the HBlank after it is a normal one
+        run_until(&mut ppu, |p| mode(p) == 0);
+        assert!(ppu.stat_interrupt);
+        assert_eq!(ppu.curr_scanline, 0);
+        run_until(&mut ppu, |p| p.curr_scanline == 1);
+        assert_eq!(mode(&ppu), 2);
+    }
+
+    #[test]
+    fn stat_write_keeps_mode_and_coincidence() {
+        let mut ppu = ppu(LCDC_ON, &[]);
+        ppu.write_lyc(1);
+        run_until(&mut ppu, |p| p.curr_scanline == 1 && mode(p) == 3);
+        assert_eq!(u8::from(ppu.status), 0b0000_0111);
+
+        // bits 0-2 are read only
+        ppu.write_stat(0xff);
+        assert_eq!(u8::from(ppu.status), 0b0111_1111);
+        ppu.write_stat(0x00);
+        assert_eq!(u8::from(ppu.status), 0b0000_0111);
+        assert_eq!(ppu.curr_scanline, 1);
+    }
+}
@@ -16,9 +16,11 @@ impl OAM {
             ObjectSize::Long  => 16,
             ObjectSize::Short => 8,
         };
+        // y_pos is the screen y + 16
+        let y = y as u16 + 16;
         self
             .into_iter()
-            .filter(move |s| s.y_pos <= y + 16 && y + 16 <= s.y_pos + sprite_size )
+            .filter(move |s| s.y_pos as u16 <= y && y < s.y_pos as u16 + sprite_size)
             .take(MAX_SPRITES_PER_ROW)
     }
 }
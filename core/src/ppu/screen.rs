@@ -2,9 +2,10 @@ use std::fmt::{Debug, Display};
 
 use super::Colour;
 
-const SCREEN_WIDTH: usize = 160;
-const SCREEN_HEIGHT: usize = 144;
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
 
+#[derive(Clone, PartialEq, Eq)]
 pub struct Screen([Colour; SCREEN_WIDTH * SCREEN_HEIGHT]);
 
 pub static mut screen_u32: [u32; SCREEN_WIDTH * SCREEN_HEIGHT] = [0; SCREEN_WIDTH * SCREEN_HEIGHT];
@@ -14,6 +15,23 @@ impl Screen {
         Self([Colour::White; SCREEN_WIDTH * SCREEN_HEIGHT])
     }
 
+    pub fn pixel(&self, x: usize, y: usize) -> Colour {
+        self.0[y * SCREEN_WIDTH + x]
+    }
+
+    // row major, 160 pixels per row
+    pub fn pixels(&self) -> &[Colour] {
+        &self.0
+    }
+
+    // what's shown while the LCD is off
+    pub(super) fn clear(&mut self) {
+        self.0.fill(Colour::White);
+        unsafe {
+            screen_u32.fill(Colour::White.into());
+        }
+    }
+
     pub(super) fn set(&mut self, row: u8, col: u8, colour: Colour) {
         let index = row as usize * SCREEN_WIDTH + col as usize;
         self.0[index] = colour;
@@ -0,0 +1,217 @@
+use crate::util::get_nth_bit;
+
+// https://gbdev.io/pandocs/Serial_Data_Transfer_(Link_Cable).html
+
+pub(crate) const REG_SB: u16 = 0xff01;
+pub(crate) const REG_SC: u16 = 0xff02;
+
+// SC
+// ============================================================================
+// Bit	Name	                        Usage notes
+// ============================================================================
+//  7	Transfer enable	                0=Idle, 1=Transfer requested / in progress
+//  1	Clock speed (CGB only)	        0=Normal, 1=Fast
+//  0	Clock select	                0=External clock, 1=Internal clock
+
+// cpu ticks per bit with the internal clock, 8192 Hz, or 262144 Hz with the CGB's fast clock
+const TICKS_PER_BIT: u16 = 512;
+const TICKS_PER_BIT_FAST: u16 = 16;
+
+// The other end of the link cable.
+pub trait SerialDevice {
+    // This GB drives the clock, and has just shifted out `outgoing`.
+    // Returns the byte the peer shifted back in at the same time.
+    fn transfer(&mut self, outgoing: u8) -> u8;
+
+    // This GB waits for the peer to drive the clock, and is polled every step.
+    // A peer that clocks a transfer returns the byte it sends, and receives the contents of SB.
+    fn external_transfer(&mut self, _outgoing: u8) -> Option<u8> {
+        None
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Serial {
+    data: u8,
+
+    transfer: bool,
+    internal_clock: bool,
+    // SC bit 1 only exists in CGB mode
+    cgb: bool,
+    fast_clock: bool,
+
+    ticks: u16,
+    bits_left: u8,
+
+    device: Option<Box<dyn SerialDevice>>,
+    pub(crate) interrupt: bool,
+}
+
+impl std::fmt::Debug for Serial {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Serial")
+            .field("data", &self.data)
+            .field("transfer", &self.transfer)
+            .field("internal_clock", &self.internal_clock)
+            .field("cgb", &self.cgb)
+            .field("fast_clock", &self.fast_clock)
+            .field("bits_left", &self.bits_left)
+            .field("device", &self.device.is_some())
+            .field("interrupt", &self.interrupt)
+            .finish()
+    }
+}
+
+impl Serial {
+    pub(crate) fn attach(&mut self, device: Box<dyn SerialDevice>) -> Option<Box<dyn SerialDevice>> {
+        self.device.replace(device)
+    }
+
+    pub(crate) fn detach(&mut self) -> Option<Box<dyn SerialDevice>> {
+        self.device.take()
+    }
+
+    pub(crate) fn set_cgb(&mut self, cgb: bool) {
+        self.cgb = cgb;
+        self.fast_clock &= cgb;
+    }
+
+    pub(crate) fn read_data(&self) -> u8 {
+        self.data
+    }
+
+    pub(crate) fn write_data(&mut self, value: u8) {
+        self.data = value;
+    }
+
+    pub(crate) fn read_control(&self) -> u8 {
+        // outside of CGB mode the clock speed bit doesn't exist, and reads as 1 like the other unused bits
+        let fast_clock = if self.cgb { self.fast_clock } else { true };
+        (self.transfer as u8) << 7
+        | 0b0111_1100
+        | (fast_clock as u8) << 1
+        | self.internal_clock as u8
+    }
+
+    pub(crate) fn write_control(&mut self, value: u8) {
+        self.transfer = get_nth_bit(value, 7);
+        self.internal_clock = get_nth_bit(value, 0);
+        self.fast_clock = self.cgb && get_nth_bit(value, 1);
+
+        if self.transfer {
+            self.ticks = 0;
+            self.bits_left = 8;
+        }
+    }
+
+    pub(crate) fn tick(&mut self, cpu_ticks: u16) {
+        if !self.transfer {
+            return;
+        }
+
+        if !self.internal_clock {
+            let incoming = self
+                .device
+                .as_mut()
+                .and_then(|d| d.external_transfer(self.data));
+            if let Some(incoming) = incoming {
+                self.complete(incoming);
+            }
+            return;
+        }
+
+        let ticks_per_bit = if self.fast_clock { TICKS_PER_BIT_FAST } else { TICKS_PER_BIT };
+        self.ticks += cpu_ticks;
+        while self.ticks >= ticks_per_bit && self.bits_left > 0 {
+            self.ticks -= ticks_per_bit;
+            self.bits_left -= 1;
+        }
+
+        if self.bits_left == 0 {
+            // with nothing on the other end of the cable, the line is pulled high
+            let incoming = match self.device.as_mut() {
+                Some(d) => d.transfer(self.data),
+                None => 0xff,
+            };
+            self.complete(incoming);
+        }
+    }
+
+    fn complete(&mut self, incoming: u8) {
+        self.data = incoming;
+        self.transfer = false;
+        self.interrupt = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mmu::MMU;
+
+    const IF_SERIAL: u8 = 1 << 3;
+
+    #[test]
+    fn transfer_takes_4096_ticks() {
+        let mut mmu = MMU::new(None, vec![0; 0x8000]);
+        mmu.writeu8(REG_SB.into(), 0x42);
+        mmu.writeu8(REG_SC.into(), 0x81);
+
+        for _ in 0..4095 {
+            mmu.tick(1);
+        }
+        assert_eq!(mmu.readu8(REG_SC.into()) & 0x80, 0x80, "still transferring");
+        assert_eq!(mmu.readu8(0xff0f.into()) & IF_SERIAL, 0);
+
+        mmu.tick(1);
+        assert_eq!(mmu.readu8(REG_SC.into()) & 0x80, 0, "done after 8 bits at 8192 Hz");
+        assert_eq!(mmu.readu8(0xff0f.into()) & IF_SERIAL, IF_SERIAL);
+        // nothing on the other end
+        assert_eq!(mmu.readu8(REG_SB.into()), 0xff);
+    }
+
+    #[test]
+    fn device_gets_the_byte() {
+        struct Echo;
+        impl SerialDevice for Echo {
+            fn transfer(&mut self, outgoing: u8) -> u8 {
+                !outgoing
+            }
+        }
+
+        let mut serial = Serial::default();
+        serial.attach(Box::new(Echo));
+        serial.write_data(0x0f);
+        serial.write_control(0x81);
+        serial.tick(4096);
+        assert!(serial.interrupt);
+        assert_eq!(serial.read_data(), 0xf0);
+    }
+
+    #[test]
+    fn no_fast_clock() {
+        let mut serial = Serial::default();
+        // bit 1 reads 1 whatever is written, and doesn't speed the transfer up
+        serial.write_control(0x83);
+        assert_eq!(serial.read_control(), 0xff);
+        serial.tick(4095);
+        assert!(!serial.interrupt);
+    }
+
+    #[test]
+    fn fast_clock() {
+        let mut serial = Serial::default();
+        serial.set_cgb(true);
+        serial.write_control(0x81);
+        assert_eq!(serial.read_control(), 0xfd);
+
+        // 8 bits at 262144 Hz
+        serial.write_control(0x83);
+        assert_eq!(serial.read_control(), 0xff);
+        serial.tick(127);
+        assert!(!serial.interrupt);
+        serial.tick(1);
+        assert!(serial.interrupt);
+        assert_eq!(serial.read_control(), 0x7f);
+    }
+}
@@ -24,6 +24,10 @@ impl Timer {
         self.divider = 0;
     }
 
+    pub(crate) fn divider_bit(&self, n: u16) -> bool {
+        self.divider & (1 << n) != 0
+    }
+
     pub(crate) fn read_counter(&self) -> u8 {
         self.counter
     }
@@ -49,8 +53,16 @@ impl Timer {
     }
 
     pub(crate) fn tick(&mut self, cpu_ticks: u16) {
+        // one machine cycle at a time, an instruction can take longer than the period of the
+        // selected bit and every falling edge has to be seen
+        for _ in 0..cpu_ticks / 4 {
+            self.step();
+        }
+    }
+
+    fn step(&mut self) {
         // increment divider
-        self.divider += cpu_ticks;
+        self.divider = self.divider.wrapping_add(4);
 
         // A bit position of the 16-bit counter is determined based on the lower 2 bits of the TAC register, as seen here:
         // 0b00: Bit 9
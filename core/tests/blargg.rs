@@ -0,0 +1,32 @@
+// Blargg's cpu_instrs, instr_timing and mem_timing, the ROMs are in roms/blargg.
+// https://github.com/retrio/gb-test-roms
+
+mod common;
+
+use machine::harness::blargg;
+
+// cpu_instrs is the slowest of them, at about 60 seconds of Game Boy time
+const MAX_CYCLES: u64 = 120 * 4_194_304;
+
+fn run(name: &str) {
+    let Some(path) = common::test_rom(&format!("blargg/{}", name)) else {
+        return;
+    };
+    let res = blargg::run(&path, MAX_CYCLES).unwrap();
+    assert!(res.passed(), "{}: {:?} after {} cycles\n{}", name, res.status, res.cycles, res.output);
+}
+
+#[test]
+fn cpu_instrs() {
+    run("cpu_instrs.gb");
+}
+
+#[test]
+fn instr_timing() {
+    run("instr_timing.gb");
+}
+
+#[test]
+fn mem_timing() {
+    run("mem_timing.gb");
+}
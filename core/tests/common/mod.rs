@@ -0,0 +1,16 @@
+use std::{env, path::PathBuf};
+
+// Test ROMs are looked up in roms/ (or the directory in GB_TEST_ROMS).
+// None when the ROM isn't there, and the test is skipped.
+pub fn test_rom(name: &str) -> Option<PathBuf> {
+    let dir = env::var_os("GB_TEST_ROMS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../roms"));
+    let path = dir.join(name);
+    if path.exists() {
+        Some(path)
+    } else {
+        eprintln!("skipped, {} not found", path.display());
+        None
+    }
+}
@@ -0,0 +1,39 @@
+// dmg-acid2 draws a face that only comes out right when the PPU gets the details right: sprite
+// priority and flipping, 8x16 sprites, the window line counter, BG-over-OBJ, ... It's done when
+// it executes LD B,B, and the frame is compared with the reference image checked in next to this
+// test, with both renderers. The ROM is in roms/.
+// https://github.com/mattcurrie/dmg-acid2
+
+mod common;
+
+use std::path::{Path, PathBuf};
+
+use machine::{harness::golden, ppu::Renderer, Machine};
+
+const MAX_CYCLES: u64 = 60 * 70_224;
+
+fn run(renderer: Renderer) {
+    let Some(rom) = common::test_rom("dmg-acid2.gb") else {
+        return;
+    };
+    let reference = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/reference/dmg-acid2.png");
+
+    let mut m = Machine::new(rom, None::<&Path>).unwrap();
+    m.set_save_path(None);
+    m.set_renderer(renderer);
+    let screen = m.run_until_breakpoint(MAX_CYCLES).expect("dmg-acid2 never hit its breakpoint");
+
+    let diff_path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("dmg-acid2-{:?}-diff.png", renderer));
+    let diff = golden::check(screen, &reference, &diff_path).expect("couldn't load the reference image");
+    assert!(diff.matches(), "{}diff image written to {}", diff.report(), diff_path.display());
+}
+
+#[test]
+fn dmg_acid2() {
+    run(Renderer::Scanline);
+}
+
+#[test]
+fn dmg_acid2_fifo() {
+    run(Renderer::Fifo);
+}
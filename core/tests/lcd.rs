@@ -0,0 +1,43 @@
+// The machine keeps running, and counting frames, while the game has the LCD off.
+
+use machine::{
+    mapper::Mapper,
+    ppu::{Colour, SCREEN_HEIGHT, SCREEN_WIDTH},
+    Machine,
+};
+
+// 32 KiB of ROM without RAM, with `program` at the entry point
+struct RomOnly(Vec<u8>);
+
+impl RomOnly {
+    fn new(program: &[u8]) -> Self {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xc3, 0x50, 0x01]);
+        rom[0x150..0x150 + program.len()].copy_from_slice(program);
+        Self(rom)
+    }
+}
+
+impl Mapper for RomOnly {
+    fn read_rom(&self, addr: u16) -> u8 {
+        self.0[addr as usize]
+    }
+
+    fn write_rom(&mut self, _addr: u16, _value: u8) {}
+}
+
+#[test]
+fn run_frames_with_the_lcd_off() {
+    // LD A,0x11; LDH (0x40),A; JR -2
+    let cart = RomOnly::new(&[0x3e, 0x11, 0xe0, 0x40, 0x18, 0xfe]);
+    let mut m = Machine::with_mapper(Box::new(cart), None::<&str>).unwrap();
+
+    let frames = m.frames();
+    let screen = m.run_frames(3);
+    for y in 0..SCREEN_HEIGHT {
+        for x in 0..SCREEN_WIDTH {
+            assert_eq!(screen.pixel(x, y), Colour::White);
+        }
+    }
+    assert_eq!(m.frames(), frames + 3);
+}
@@ -0,0 +1,100 @@
+// A mapper's save state goes through the machine.
+
+use std::error::Error;
+
+use machine::{mapper::Mapper, Machine};
+
+// 64 KiB of ROM in 4 banks, with the bank at 0x4000-0x7fff picked by writes to 0x2000-0x3fff, and
+// `program` at the entry point
+struct Banked {
+    rom: Vec<u8>,
+    bank: u8,
+}
+
+impl Banked {
+    fn new(program: &[u8]) -> Self {
+        let mut rom = vec![0; 0x10000];
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xc3, 0x50, 0x01]);
+        rom[0x150..0x150 + program.len()].copy_from_slice(program);
+        Self { rom, bank: 1 }
+    }
+}
+
+impl Mapper for Banked {
+    fn read_rom(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3fff => self.rom[addr as usize],
+            _ => self.rom[self.bank as usize * 0x4000 + addr as usize - 0x4000],
+        }
+    }
+
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        if let 0x2000..=0x3fff = addr {
+            self.bank = value & 0x03;
+        }
+    }
+
+    fn save_state(&self) -> Option<Vec<u8>> {
+        Some(vec![self.bank])
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), Box<dyn Error>> {
+        match state {
+            &[bank] => self.bank = bank & 0x03,
+            _ => return Err("wrong state size".into()),
+        }
+        Ok(())
+    }
+}
+
+// the same without save states
+struct NoState;
+
+impl Mapper for NoState {
+    fn read_rom(&self, addr: u16) -> u8 {
+        match addr {
+            0x0100..=0x0103 => [0x00, 0xc3, 0x50, 0x01][addr as usize - 0x100],
+            // JR -2
+            0x0150 => 0x18,
+            0x0151 => 0xfe,
+            _ => 0,
+        }
+    }
+
+    fn write_rom(&mut self, _addr: u16, _value: u8) {}
+}
+
+fn run_for(m: &mut Machine, ticks: u64) {
+    let mut t = 0;
+    while t < ticks {
+        t += m.step();
+    }
+}
+
+#[test]
+fn mapper_state_round_trip() {
+    // LD A,3; LD (0x2000),A; JR -2
+    let cart = Banked::new(&[0x3e, 0x03, 0xea, 0x00, 0x20, 0x18, 0xfe]);
+    let mut m = Machine::with_mapper(Box::new(cart), None::<&str>).unwrap();
+    assert_eq!(m.mapper_state(), Some(vec![1]));
+
+    run_for(&mut m, 1000);
+    let state = m.mapper_state().unwrap();
+    assert_eq!(state, [3]);
+
+    m.load_mapper_state(&[2]).unwrap();
+    assert_eq!(m.mapper_state(), Some(vec![2]));
+    m.load_mapper_state(&state).unwrap();
+    assert_eq!(m.mapper_state(), Some(vec![3]));
+
+    // the mapper's errors are passed on, and it's left as it was
+    assert!(m.load_mapper_state(&[]).is_err());
+    assert_eq!(m.mapper_state(), Some(vec![3]));
+}
+
+#[test]
+fn mapper_without_state() {
+    let mut m = Machine::with_mapper(Box::new(NoState), None::<&str>).unwrap();
+    assert_eq!(m.mapper_state(), None);
+    assert!(m.load_mapper_state(&[0]).is_err());
+}
@@ -0,0 +1,88 @@
+// Battery backed RAM is written to the save path about a second after the game changes it.
+
+use std::{env, fs, path::PathBuf};
+
+use machine::{mapper::Mapper, Machine};
+
+const SECOND: u64 = 4 * 1024 * 1024;
+
+// 32 KiB of ROM and 8 KiB of RAM that's always enabled, with `program` at the entry point
+struct BatteryCart {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+}
+
+impl BatteryCart {
+    fn new(program: &[u8]) -> Self {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xc3, 0x50, 0x01]);
+        // MBC1+RAM+BATTERY, 32 KiB ROM, 8 KiB RAM
+        rom[0x147..0x14a].copy_from_slice(&[0x03, 0x00, 0x02]);
+        rom[0x150..0x150 + program.len()].copy_from_slice(program);
+        Self { rom, ram: vec![0; 0x2000] }
+    }
+}
+
+impl Mapper for BatteryCart {
+    fn read_rom(&self, addr: u16) -> u8 {
+        self.rom[addr as usize]
+    }
+
+    fn write_rom(&mut self, _addr: u16, _value: u8) {}
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        self.ram[addr as usize - 0xa000]
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        self.ram[addr as usize - 0xa000] = value;
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+}
+
+fn run_for(m: &mut Machine, ticks: u64) {
+    let mut t = 0;
+    while t < ticks {
+        t += m.step();
+    }
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("gb-save-test-{}-{}", std::process::id(), name))
+}
+
+#[test]
+fn failed_save_is_reported_and_retried() {
+    // LD A,0x42; LD (0xa000),A; JR -2
+    let cart = BatteryCart::new(&[0x3e, 0x42, 0xea, 0x00, 0xa0, 0x18, 0xfe]);
+    let mut m = Machine::with_mapper(Box::new(cart), None::<&str>).unwrap();
+
+    m.set_save_path(Some(temp_path("missing-dir").join("game.sav")));
+    run_for(&mut m, 2 * SECOND);
+    assert!(m.last_save_error().is_some());
+
+    let path = temp_path("game.sav");
+    m.set_save_path(Some(path.clone()));
+    run_for(&mut m, 2 * SECOND);
+    assert!(m.last_save_error().is_none());
+    let data = fs::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(data[0], 0x42);
+}
+
+#[test]
+fn sixteen_bit_writes_are_saved() {
+    // LD SP,0xbeef; LD (0xa000),SP; JR -2
+    let cart = BatteryCart::new(&[0x31, 0xef, 0xbe, 0x08, 0x00, 0xa0, 0x18, 0xfe]);
+    let mut m = Machine::with_mapper(Box::new(cart), None::<&str>).unwrap();
+
+    let path = temp_path("sp.sav");
+    m.set_save_path(Some(path.clone()));
+    run_for(&mut m, 2 * SECOND);
+    let data = fs::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(data[..2], [0xef, 0xbe]);
+}